@@ -1,89 +1,290 @@
 use super::{
-    symbol_table::{SymbolEntry, SymbolTable, VarKind, VarType},
+    program::Program,
+    symbol_table::{
+        ClassSymbols, SubroutineSymbols, Symbol, SymbolEntry, SymbolTable, VarKind, VarType,
+    },
     vm_writer::{ArithmeticCommand, Segment, VmWriter},
 };
+use crate::diagnostics::{closest_match, Diagnostic, Severity, Span};
 use crate::parser::*;
+use crate::tokenizer::{char_code, CHARACTER_SET_NOTE};
+use crate::{Extension, Extensions, Lint, LintLevel};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::iter;
 
-pub fn compile_class(class: Class) -> impl Iterator<Item = String> {
-    let mut code_generator = CodeGenerator::new();
-    code_generator.compile_class(class);
-    code_generator.vm_writer.into_iter()
+/// Everything generated for a class
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledClass {
+    pub vm: Vec<String>,
+    /// The span of the statement or declaration each VM command was generated from
+    pub source_map: Vec<Option<Span>>,
+    pub symbols: ClassSymbols,
+    /// What the lints that are set to `warn` found
+    pub warnings: Vec<Diagnostic>,
 }
 
-struct CodeGenerator {
+pub fn compile(class: &Class) -> Result<CompiledClass, Diagnostic> {
+    compile_in_program(
+        class,
+        &Program::new(std::iter::once(class), &Extensions::new()),
+    )
+}
+
+/// Like `compile`, but the class can refer to the declarations of the other classes in
+/// `program`, as far as its extensions allow
+pub fn compile_in_program(class: &Class, program: &Program) -> Result<CompiledClass, Diagnostic> {
+    let mut code_generator = CodeGenerator::new(program);
+    let class_name = class.class_name.to_string();
+    code_generator.compile_class(class)?;
+    let (vm, source_map) = code_generator.vm_writer.finish();
+    Ok(CompiledClass {
+        vm,
+        source_map,
+        symbols: ClassSymbols {
+            class_name,
+            symbols: code_generator.class_symbols,
+            subroutines: code_generator.subroutine_symbols,
+        },
+        warnings: code_generator.warnings,
+    })
+}
+
+pub fn compile_class(class: Class) -> Result<impl Iterator<Item = String>, Diagnostic> {
+    Ok(compile(&class)?.vm.into_iter())
+}
+
+struct CodeGenerator<'a> {
+    program: &'a Program,
     symbol_table: SymbolTable,
     vm_writer: VmWriter,
     label_count: usize,
-    class_name: Option<String>,
+    class_name: Option<Identifier>,
+    subroutine_types: HashMap<String, (SubroutineType, Span)>,
+    subroutine_name: Option<String>,
+    subroutine_type: Option<SubroutineType>,
+    class_symbols: Vec<Symbol>,
+    subroutine_symbols: Vec<SubroutineSymbols>,
+    /// The labels that `continue` and `break` jump to in each loop around the
+    /// current statement, innermost last
+    loop_labels: Vec<(String, String)>,
+    warnings: Vec<Diagnostic>,
 }
 
-impl CodeGenerator {
-    pub fn new() -> CodeGenerator {
+impl<'a> CodeGenerator<'a> {
+    pub fn new(program: &'a Program) -> CodeGenerator<'a> {
         CodeGenerator {
+            program,
             symbol_table: SymbolTable::new(),
             vm_writer: VmWriter::new(),
             label_count: 0,
             class_name: None,
+            subroutine_types: HashMap::new(),
+            subroutine_name: None,
+            subroutine_type: None,
+            class_symbols: Vec::new(),
+            subroutine_symbols: Vec::new(),
+            loop_labels: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
-    fn compile_class(&mut self, class: Class) {
+    fn compile_class(&mut self, class: &Class) -> Result<(), Diagnostic> {
         self.symbol_table = SymbolTable::new();
-        self.class_name = Some(class.class_name.to_string());
+        self.class_name = Some(class.class_name.clone());
+
+        // Record the kind of every subroutine up front so that calls without
+        // a class or var name can be resolved regardless of declaration order
+        self.subroutine_types = class
+            .subroutine_declarations
+            .iter()
+            .map(|subroutine| {
+                (
+                    subroutine.name.to_string(),
+                    (subroutine.subroutine_type, subroutine.name.span),
+                )
+            })
+            .collect();
+
+        if let Some(parent) = &class.superclass {
+            if self.program.fields(&parent.name).is_none() {
+                return Err(Diagnostic::error(
+                    "E0216",
+                    format!("cannot find class `{}` to extend", parent),
+                )
+                .with_span(parent.span)
+                .with_label("not found in this program")
+                .with_note("only classes that are compiled together can be extended"));
+            }
+            if self.program.inherits_from_itself(&class.class_name.name) {
+                return Err(Diagnostic::error(
+                    "E0216",
+                    format!("class `{}` inherits from itself", class.class_name),
+                )
+                .with_span(parent.span)
+                .with_label(format!("`{}` inherits from `{}`", parent, class.class_name))
+                .with_help("remove `extends` from one of the classes in the cycle"));
+            }
+            // Inherited fields come first, so the parent's methods find them at the same indices
+            for (name, var_type) in self.program.fields(&parent.name).unwrap_or_default() {
+                self.symbol_table
+                    .define(name.to_string(), var_type.clone(), VarKind::Field, None);
+            }
+        }
 
-        for var_dec in class.class_var_declarations {
-            for name in var_dec.var_names {
+        for var_dec in class.class_var_declarations.iter() {
+            for name in var_dec.var_names.iter() {
+                // The class's own variable would hide the inherited field from its methods, but
+                // not from the parent's
+                if let Some(parent) = &class.superclass {
+                    if self.program.field(&parent.name, &name.name).is_some() {
+                        return Err(Diagnostic::error(
+                            "E0216",
+                            format!("field `{}` is already inherited from `{}`", name, parent),
+                        )
+                        .with_span(name.span)
+                        .with_label("declared again here")
+                        .with_help("rename it, or use the inherited field"));
+                    }
+                }
                 self.symbol_table.define(
-                    name,
+                    name.to_string(),
                     var_dec.var_type.clone(),
                     if var_dec.static_or_field == StaticOrField::Static {
                         VarKind::Static
                     } else {
                         VarKind::Field
                     },
+                    Some(name.span),
                 );
             }
         }
 
-        for subroutine in class.subroutine_declarations {
-            self.compile_subroutine(subroutine);
+        self.class_symbols = self.symbol_table.class_symbols();
+
+        // Constants take up no space, but their errors are reported with the class that declares them
+        for declaration in class.enum_declarations.iter() {
+            match self
+                .program
+                .constants
+                .declaring_class(&declaration.name.name)
+            {
+                Some(other) if other != class.class_name.name => {
+                    return Err(Diagnostic::error(
+                        "E0212",
+                        format!(
+                            "enum `{}` is already declared in class `{}`",
+                            declaration.name, other
+                        ),
+                    )
+                    .with_span(declaration.name.span)
+                    .with_label("declared again here")
+                    .with_help(
+                        "enums are referred to by name from every class, so rename one of them",
+                    ));
+                }
+                _ => {}
+            }
         }
+        for declaration in class.constant_declarations.iter() {
+            if let VarType::ClassName(type_name) = &declaration.var_type {
+                return Err(Diagnostic::error(
+                    "E0217",
+                    format!(
+                        "constant `{}` cannot have type `{}`",
+                        declaration.name, type_name
+                    ),
+                )
+                .with_span(type_name.span)
+                .with_label("not a primitive type")
+                .with_note("constants can only be `int`, `char` or `boolean`"));
+            }
+            self.program
+                .constants
+                .evaluate(&class.class_name.name, &declaration.expression)?;
+        }
+
+        for subroutine in class.subroutine_declarations.iter() {
+            self.compile_subroutine(subroutine)?;
+        }
+        Ok(())
     }
 
-    fn compile_subroutine(&mut self, subroutine: SubroutineDeclaration) {
+    fn compile_subroutine(&mut self, subroutine: &SubroutineDeclaration) -> Result<(), Diagnostic> {
         self.symbol_table.start_subroutine();
         let class_name = self.class_name.to_owned().unwrap();
+        self.subroutine_name = Some(format!("{}.{}", class_name, subroutine.name));
+        self.subroutine_type = Some(subroutine.subroutine_type);
+
+        // Constructors must return an instance of their own class
+        if subroutine.subroutine_type == SubroutineType::Constructor
+            && subroutine.return_type != Some(VarType::ClassName(class_name.clone()))
+        {
+            let diagnostic = Diagnostic::error(
+                "E0205",
+                format!(
+                    "constructor `{}.{}` must return `{}`",
+                    class_name, subroutine.name, class_name
+                ),
+            )
+            .with_secondary(class_name.span, "class declared here");
+            return Err(match &subroutine.return_type {
+                Some(VarType::ClassName(return_type)) => diagnostic
+                    .with_span(return_type.span)
+                    .with_label(format!("expected `{}`", class_name))
+                    .with_suggestion(
+                        "change the return type",
+                        return_type.span,
+                        class_name.to_string(),
+                    ),
+                _ => diagnostic
+                    .with_span(subroutine.name.span)
+                    .with_label(format!("must return `{}`", class_name))
+                    .with_help(format!("change the return type to `{}`", class_name)),
+            });
+        }
 
         // Methods are called with `this` as the first argument
         if subroutine.subroutine_type == SubroutineType::Method {
             self.symbol_table.define(
                 "this".to_string(),
-                VarType::ClassName(class_name.to_string()),
+                VarType::ClassName(class_name.clone()),
                 VarKind::Arg,
+                None,
             );
         }
 
         // Add arguments to symbol table
-        for (arg_type, arg_name) in subroutine.parameter_list {
-            self.symbol_table
-                .define(arg_name.to_string(), arg_type.clone(), VarKind::Arg);
+        for (arg_type, arg_name) in subroutine.parameter_list.iter() {
+            self.symbol_table.define(
+                arg_name.to_string(),
+                arg_type.clone(),
+                VarKind::Arg,
+                Some(arg_name.span),
+            );
         }
 
         // Add local variables to symbol table
         for var_dec in subroutine.body.var_declarations.iter() {
             for name in var_dec.var_names.iter() {
-                self.symbol_table
-                    .define(name.to_string(), var_dec.var_type.clone(), VarKind::Var);
+                self.symbol_table.define(
+                    name.to_string(),
+                    var_dec.var_type.clone(),
+                    VarKind::Var,
+                    Some(name.span),
+                );
             }
         }
 
+        self.subroutine_symbols.push(SubroutineSymbols {
+            name: subroutine.name.to_string(),
+            symbols: self.symbol_table.subroutine_symbols(),
+        });
+
+        self.vm_writer.set_span(Some(subroutine.name.span));
         self.vm_writer.write_function(
             &format!("{}.{}", class_name, subroutine.name),
-            self.symbol_table
-                .var_count(VarKind::Var)
-                .try_into()
-                .unwrap(),
+            self.symbol_table.var_count(VarKind::Var),
         );
 
         // Constructor
@@ -100,79 +301,349 @@ impl CodeGenerator {
             self.vm_writer.write_pop(Segment::Pointer, 0);
         }
 
-        for statement in subroutine.body.statements {
-            self.compile_statement(statement);
+        for statement in subroutine.body.statements.iter() {
+            self.compile_statement(statement)?;
+        }
+
+        // Any identifier with the name of a variable counts as a use, so the lint never
+        // reports a variable that is used
+        let mut names = Names::default();
+        for statement in subroutine.body.statements.iter() {
+            names.visit_statement(statement);
+        }
+        for var_dec in subroutine.body.var_declarations.iter() {
+            for name in var_dec.var_names.iter() {
+                if names.0.contains(name.name.as_str()) || name.name.starts_with('_') {
+                    continue;
+                }
+                self.lint(
+                    Lint::UnusedVariable,
+                    Diagnostic::warning(
+                        Lint::UnusedVariable.code(),
+                        format!("unused variable `{}`", name),
+                    )
+                    .with_span(name.span)
+                    .with_label("declared here but never used")
+                    .with_suggestion(
+                        "if this is intentional, prefix it with an underscore",
+                        name.span,
+                        format!("_{}", name),
+                    ),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports what a lint found at the level the lint is set to
+    fn lint(&mut self, lint: Lint, diagnostic: Diagnostic) -> Result<(), Diagnostic> {
+        let level = self.program.lints.level(lint);
+        let note = if self.program.lints.is_set(lint) {
+            format!("`{}` is set to `{}`", lint.as_ref(), level.as_ref())
+        } else {
+            format!(
+                "`{}` is set to `{}` by default",
+                lint.as_ref(),
+                level.as_ref()
+            )
+        };
+        let diagnostic = diagnostic.with_note(note);
+        match level {
+            LintLevel::Allow => Ok(()),
+            LintLevel::Warn => {
+                self.warnings.push(diagnostic);
+                Ok(())
+            }
+            LintLevel::Deny => Err(Diagnostic {
+                severity: Severity::Error,
+                ..diagnostic
+            }),
+        }
+    }
+
+    fn is_function(&self) -> bool {
+        self.subroutine_type == Some(SubroutineType::Function)
+    }
+
+    fn lookup_var(&self, var_name: &Identifier) -> Result<&SymbolEntry, Diagnostic> {
+        let entry = self.symbol_table.get(&var_name.name).ok_or_else(|| {
+            let diagnostic = Diagnostic::error(
+                "E0200",
+                format!("cannot find variable `{}` in this scope", var_name),
+            )
+            .with_span(var_name.span)
+            .with_label("not found in this scope");
+            match closest_match(&var_name.name, self.symbol_table.names()) {
+                Some(similar) => diagnostic.with_suggestion(
+                    "a variable with a similar name exists",
+                    var_name.span,
+                    similar,
+                ),
+                None => diagnostic,
+            }
+        })?;
+
+        // Functions have no `this`, so fields cannot be accessed from them
+        if entry.kind == VarKind::Field && self.is_function() {
+            let mut diagnostic = Diagnostic::error(
+                "E0202",
+                format!(
+                    "cannot access field `{}` inside function `{}`",
+                    var_name,
+                    self.subroutine_name.as_ref().unwrap()
+                ),
+            )
+            .with_span(var_name.span)
+            .with_label("field accessed here");
+            if let Some(span) = entry.span {
+                diagnostic = diagnostic.with_secondary(span, "field declared here");
+            }
+            return Err(diagnostic
+                .with_note("functions are not called on an object, so they have no fields")
+                .with_help("declare the subroutine as a method to access fields"));
+        }
+        Ok(entry)
+    }
+
+    /// Whether a name without a class refers to a constant of this class rather than a variable
+    fn is_constant(&self, name: &Identifier) -> bool {
+        self.symbol_table.get(&name.name).is_none()
+            && self
+                .program
+                .constants
+                .contains(&self.class_name.as_ref().unwrap().name, &name.name)
+    }
+
+    /// The index of `field` in the object the variable `var_name` refers to
+    fn lookup_field(&self, var_name: &Identifier, field: &Identifier) -> Result<u16, Diagnostic> {
+        let entry = self.lookup_var(var_name)?;
+        let span = var_name.span.to(field.span);
+        let class_name = match &entry.symbol_type {
+            VarType::ClassName(class_name) => class_name,
+            var_type => {
+                return Err(Diagnostic::error(
+                    "E0214",
+                    format!("no field `{}` on type `{}`", field, var_type),
+                )
+                .with_span(span)
+                .with_label(format!("`{}` has type `{}`", var_name, var_type))
+                .with_note("only objects have fields"));
+            }
+        };
+        let fields = self.program.fields(&class_name.name).ok_or_else(|| {
+            Diagnostic::error(
+                "E0214",
+                format!("cannot find the fields of class `{}`", class_name),
+            )
+            .with_span(span)
+            .with_label(format!("`{}` has type `{}`", var_name, class_name))
+            .with_note("only the fields of classes that are compiled together can be accessed")
+        })?;
+        match self.program.field(&class_name.name, &field.name) {
+            Some((index, _, _)) => Ok(index),
+            None => {
+                let diagnostic = Diagnostic::error(
+                    "E0214",
+                    format!("no field `{}` on type `{}`", field, class_name),
+                )
+                .with_span(field.span)
+                .with_label("unknown field");
+                let names = fields.iter().map(|(name, _)| name.name.as_str());
+                Err(match closest_match(&field.name, names) {
+                    Some(similar) => diagnostic.with_suggestion(
+                        "a field with a similar name exists",
+                        field.span,
+                        similar,
+                    ),
+                    None => diagnostic,
+                })
+            }
+        }
+    }
+
+    /// Points `that` to the object in a variable, so its fields are `that 0`, `that 1`, ...
+    fn write_field_pointer(&mut self, kind: VarKind, index: u16) {
+        self.vm_writer.write_push(Segment::from(kind), index);
+        self.vm_writer.write_pop(Segment::Pointer, 1);
+    }
+
+    /// Constants are pushed with their value. The VM can only push non-negative constants.
+    fn write_constant(&mut self, value: u16) {
+        if value <= 0x7FFF {
+            self.vm_writer.write_push(Segment::Const, value);
+        } else if value == 0x8000 {
+            self.vm_writer.write_push(Segment::Const, 0x7FFF);
+            self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+        } else {
+            self.vm_writer
+                .write_push(Segment::Const, value.wrapping_neg());
+            self.vm_writer.write_arithmetic(ArithmeticCommand::Neg);
         }
     }
 
-    fn compile_statement(&mut self, statement: Statement) {
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        // Nested statements set their own span, so it is set again after them
+        let span = statement_span(statement);
+        self.vm_writer.set_span(span);
         match statement {
             Statement::Do(statement) => {
-                self.compile_subroutine_call(statement.0);
+                self.compile_subroutine_call(&statement.subroutine_call)?;
                 // Discard return value
                 self.vm_writer.write_pop(Segment::Temp, 0);
             }
             Statement::If(statement) => {
                 self.label_count += 1;
-                let label1 = format!("IF_{}_FALSE", self.label_count);
-                let label2 = format!("IF_{}_END", self.label_count);
-                self.compile_expression(statement.expression);
-                self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
-                self.vm_writer.write_if(&label1);
-                for statement in statement.if_statements {
-                    self.compile_statement(statement);
+                // Every branch of an `else if` chain jumps to the same end label
+                let end_label = format!("IF_{}_END", self.label_count);
+                let mut branch = statement;
+                loop {
+                    let false_label = format!("IF_{}_FALSE", self.label_count);
+                    self.compile_expression(&branch.expression)?;
+                    self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+                    self.vm_writer.write_if(&false_label);
+                    for statement in branch.if_statements.iter() {
+                        self.compile_statement(statement)?;
+                    }
+                    self.vm_writer.set_span(Some(branch.expression.span));
+                    self.vm_writer.write_goto(&end_label);
+                    self.vm_writer.write_label(&false_label);
+                    match branch.next_else_if() {
+                        Some(next) => {
+                            self.label_count += 1;
+                            self.vm_writer.set_span(Some(next.expression.span));
+                            branch = next;
+                        }
+                        None => break,
+                    }
                 }
-                self.vm_writer.write_goto(&label2);
-                self.vm_writer.write_label(&label1);
-                if let Some(else_statements) = statement.else_statements {
-                    for statement in else_statements {
-                        self.compile_statement(statement);
+                if let Some(else_statements) = &branch.else_statements {
+                    for statement in else_statements.iter() {
+                        self.compile_statement(statement)?;
                     }
                 }
-                self.vm_writer.write_label(&label2);
+                self.vm_writer.set_span(span);
+                self.vm_writer.write_label(&end_label);
             }
             Statement::While(statement) => {
                 self.label_count += 1;
                 let label1 = format!("WHILE_{}_CONDITION", self.label_count);
                 let label2 = format!("WHILE_{}_END", self.label_count);
                 self.vm_writer.write_label(&label1);
-                self.compile_expression(statement.expression);
+                self.compile_expression(&statement.expression)?;
                 self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
                 self.vm_writer.write_if(&label2);
-                for statement in statement.statements {
-                    self.compile_statement(statement);
+                self.loop_labels.push((label1.clone(), label2.clone()));
+                for statement in statement.statements.iter() {
+                    self.compile_statement(statement)?;
                 }
+                self.loop_labels.pop();
+                self.vm_writer.set_span(span);
                 self.vm_writer.write_goto(&label1);
                 self.vm_writer.write_label(&label2);
             }
             Statement::Let(statement) => {
-                let entry = self
-                    .symbol_table
-                    .get(&statement.var_name)
-                    .expect("Variables must be declared before they are assigned");
+                if self.is_constant(&statement.var_name) {
+                    return Err(Diagnostic::error(
+                        "E0213",
+                        format!("cannot assign to constant `{}`", statement.var_name),
+                    )
+                    .with_span(statement.var_name.span)
+                    .with_label("cannot assign twice to a constant")
+                    .with_note("constants are replaced by their value when they are compiled"));
+                }
+                let entry = self.lookup_var(&statement.var_name)?;
                 let entry_kind = entry.kind;
                 let entry_index = entry.index;
 
+                if let Some(field) = &statement.field {
+                    let field_index = self.lookup_field(&statement.var_name, field)?;
+                    if let Some(op) = &statement.op {
+                        self.write_field_pointer(entry_kind, entry_index);
+                        self.vm_writer.write_push(Segment::That, field_index);
+                        self.compile_expression(&statement.right_side_expression)?;
+                        self.write_op(op);
+                    } else {
+                        self.compile_expression(&statement.right_side_expression)?;
+                    }
+                    // The right side can access other objects, so `that` is only set afterwards
+                    self.write_field_pointer(entry_kind, entry_index);
+                    self.vm_writer.write_pop(Segment::That, field_index);
                 // If the statement has an array access expression on the left side
-                if let Some(expression) = statement.left_side_expression {
+                } else if let Some(expression) = &statement.left_side_expression {
                     self.vm_writer
                         .write_push(Segment::from(entry_kind), entry_index);
-                    self.compile_expression(expression);
+                    self.compile_expression(expression)?;
                     self.vm_writer.write_arithmetic(ArithmeticCommand::Add);
-                    self.compile_expression(statement.right_side_expression);
+                    if let Some(op) = &statement.op {
+                        // Read the old value while keeping a copy of the address, so that
+                        // the index is only evaluated once
+                        self.vm_writer.write_pop(Segment::Pointer, 1);
+                        self.vm_writer.write_push(Segment::Pointer, 1);
+                        self.vm_writer.write_push(Segment::That, 0);
+                        self.compile_expression(&statement.right_side_expression)?;
+                        self.write_op(op);
+                    } else {
+                        self.compile_expression(&statement.right_side_expression)?;
+                    }
                     self.vm_writer.write_pop(Segment::Temp, 0);
                     self.vm_writer.write_pop(Segment::Pointer, 1);
                     self.vm_writer.write_push(Segment::Temp, 0);
                     self.vm_writer.write_pop(Segment::That, 0);
                 } else {
-                    self.compile_expression(statement.right_side_expression);
+                    if let Some(op) = &statement.op {
+                        self.vm_writer
+                            .write_push(Segment::from(entry_kind), entry_index);
+                        self.compile_expression(&statement.right_side_expression)?;
+                        self.write_op(op);
+                    } else {
+                        self.compile_expression(&statement.right_side_expression)?;
+                    }
                     self.vm_writer
                         .write_pop(Segment::from(entry_kind), entry_index);
                 }
             }
+            Statement::For(statement) => {
+                self.label_count += 1;
+                let condition_label = format!("FOR_{}_CONDITION", self.label_count);
+                let update_label = format!("FOR_{}_UPDATE", self.label_count);
+                let end_label = format!("FOR_{}_END", self.label_count);
+                if let Some(initializer) = &statement.initializer {
+                    self.compile_statement(initializer)?;
+                    self.vm_writer.set_span(span);
+                }
+                self.vm_writer.write_label(&condition_label);
+                // Without a condition, the loop only ends with `break` or `return`
+                if let Some(condition) = &statement.condition {
+                    self.compile_expression(condition)?;
+                    self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+                    self.vm_writer.write_if(&end_label);
+                }
+                self.loop_labels
+                    .push((update_label.clone(), end_label.clone()));
+                for statement in statement.statements.iter() {
+                    self.compile_statement(statement)?;
+                }
+                self.loop_labels.pop();
+                self.vm_writer.set_span(span);
+                self.vm_writer.write_label(&update_label);
+                if let Some(update) = &statement.update {
+                    self.compile_statement(update)?;
+                    self.vm_writer.set_span(span);
+                }
+                self.vm_writer.write_goto(&condition_label);
+                self.vm_writer.write_label(&end_label);
+            }
+            Statement::Break(statement) => {
+                let break_label = self.innermost_loop("break", statement.span)?.1.clone();
+                self.vm_writer.write_goto(&break_label);
+            }
+            Statement::Continue(statement) => {
+                let continue_label = self.innermost_loop("continue", statement.span)?.0.clone();
+                self.vm_writer.write_goto(&continue_label);
+            }
             Statement::Return(statement) => {
-                if let Some(expression) = statement.0 {
-                    self.compile_expression(expression);
+                if let Some(expression) = &statement.expression {
+                    self.compile_expression(expression)?;
                 } else {
                     // void functions push a 0 onto the stack before returning
                     self.vm_writer.write_push(Segment::Const, 0);
@@ -180,138 +651,1227 @@ impl CodeGenerator {
                 self.vm_writer.write_return();
             }
         }
+        Ok(())
     }
 
-    fn compile_subroutine_call(&mut self, subroutine_call: SubroutineCall) {
+    /// The labels of the loop that a `break` or `continue` statement at `span` jumps out of
+    fn innermost_loop(&self, keyword: &str, span: Span) -> Result<&(String, String), Diagnostic> {
+        self.loop_labels.last().ok_or_else(|| {
+            Diagnostic::error("E0208", format!("`{}` outside of a loop", keyword))
+                .with_span(span)
+                .with_label(format!("cannot `{}` outside of a loop", keyword))
+                .with_note("`break` and `continue` can only be used inside `while` and `for` loops")
+        })
+    }
+
+    fn compile_subroutine_call(
+        &mut self,
+        subroutine_call: &SubroutineCall,
+    ) -> Result<(), Diagnostic> {
         let mut num_args = subroutine_call.expression_list.len();
 
-        // Method call
+        if let Some(receiver) = &subroutine_call.receiver {
+            let subroutine_name = &subroutine_call.subroutine_name;
+            let class_name =
+                if let TermKind::KeywordConstant(KeywordConstant::Super) = receiver.kind {
+                    // The object is `this`, but the subroutine is the parent's
+                    let class_name = self.super_class(subroutine_name)?;
+                    self.vm_writer.write_push(Segment::Pointer, 0);
+                    class_name
+                } else {
+                    // Like a method called on a variable, the object is added as arg 0
+                    self.compile_term(receiver, subroutine_name.span)?;
+                    let class_name = self.receiver_class(receiver, subroutine_name)?;
+                    self.inherited_from(class_name, &subroutine_name.name)
+                };
+            self.check_method(&class_name, subroutine_name)?;
+            for expression in subroutine_call.expression_list.iter() {
+                self.compile_expression(expression)?;
+            }
+            self.vm_writer.write_call(
+                &format!("{}.{}", class_name, subroutine_name),
+                (num_args + 1).try_into().unwrap(),
+            );
+            return Ok(());
+        }
+
         if let Some(class_or_var) = &subroutine_call.class_or_var_name {
-            if let Some(entry) = self.symbol_table.get(&class_or_var) {
+            // Method call
+            if self.symbol_table.get(&class_or_var.name).is_some() {
+                let entry = self.lookup_var(class_or_var)?;
+                let (entry_kind, entry_index) = (entry.kind, entry.index);
                 // The base address of the object is added as arg 0
                 num_args += 1;
                 self.vm_writer
-                    .write_push(Segment::from(entry.kind), entry.index);
+                    .write_push(Segment::from(entry_kind), entry_index);
             }
         } else {
-            // Method in the same class
-            num_args += 1;
-            self.vm_writer.write_push(Segment::Pointer, 0);
+            let subroutine_name = &subroutine_call.subroutine_name;
+            let class_name = &self.class_name.as_ref().unwrap().name;
+            let subroutine_type = match self.subroutine_types.get(&subroutine_name.name) {
+                Some((subroutine_type, span)) => Some((*subroutine_type, Some(*span))),
+                // Inherited subroutines are declared in another file
+                None => self
+                    .program
+                    .subroutine(class_name, &subroutine_name.name)
+                    .map(|(subroutine_type, _)| (subroutine_type, None)),
+            };
+            match subroutine_type {
+                // Method in the same class, or inherited from a parent class
+                Some((SubroutineType::Method, declaration_span)) => {
+                    if self.is_function() {
+                        let diagnostic = Diagnostic::error(
+                            "E0204",
+                            format!(
+                                "cannot call method `{}` without an object inside function `{}`",
+                                subroutine_name,
+                                self.subroutine_name.as_ref().unwrap()
+                            ),
+                        )
+                        .with_span(subroutine_name.span)
+                        .with_label("called without an object");
+                        let diagnostic = match declaration_span {
+                            Some(span) => diagnostic.with_secondary(span, "method declared here"),
+                            None => diagnostic,
+                        };
+                        return Err(diagnostic.with_help(format!(
+                            "call the method on an object, or declare `{}` as a function",
+                            subroutine_name
+                        )));
+                    }
+                    num_args += 1;
+                    self.vm_writer.write_push(Segment::Pointer, 0);
+                }
+                // Function or constructor in the same class, or inherited function
+                Some(_) => {}
+                None => {
+                    let diagnostic = Diagnostic::error(
+                        "E0201",
+                        format!(
+                            "cannot find subroutine `{}` in class `{}`",
+                            subroutine_name,
+                            self.class_name.as_ref().unwrap()
+                        ),
+                    )
+                    .with_span(subroutine_name.span)
+                    .with_label("not found in this class");
+                    let names = self.subroutine_types.keys().map(|name| name.as_str());
+                    return Err(match closest_match(&subroutine_name.name, names) {
+                        Some(similar) => diagnostic.with_suggestion(
+                            "a subroutine with a similar name exists",
+                            subroutine_name.span,
+                            similar,
+                        ),
+                        None => diagnostic,
+                    });
+                }
+            }
         }
 
-        for expression in subroutine_call.expression_list {
-            self.compile_expression(expression);
+        for expression in subroutine_call.expression_list.iter() {
+            self.compile_expression(expression)?;
         }
 
-        let class_name: String = if let Some(class_or_var_name) = subroutine_call.class_or_var_name
+        let class_name: String = if let Some(class_or_var_name) = &subroutine_call.class_or_var_name
         {
             // If we're calling a method on a var, the function we actually need to call
             // is the {class name}.method
             if let Some(SymbolEntry {
-                kind: _,
                 symbol_type: VarType::ClassName(class_name),
-                index: _,
-            }) = self.symbol_table.get(&class_or_var_name)
+                ..
+            }) = self.symbol_table.get(&class_or_var_name.name)
             {
                 class_name.to_string()
             } else {
-                class_or_var_name
+                class_or_var_name.to_string()
             }
         } else {
-            self.class_name.to_owned().unwrap()
+            self.class_name.as_ref().unwrap().to_string()
         };
+        let class_name = self.inherited_from(class_name, &subroutine_call.subroutine_name.name);
         let subroutine_name = format!("{}.{}", class_name, subroutine_call.subroutine_name);
         self.vm_writer
             .write_call(&subroutine_name, num_args.try_into().unwrap());
+        Ok(())
     }
 
-    fn compile_expression(&mut self, expression: Expression) {
-        self.compile_term(expression.term);
+    /// The class that declares the subroutine `class_name.subroutine_name` calls, which is an
+    /// ancestor of the class if the subroutine is inherited
+    fn inherited_from(&self, class_name: String, subroutine_name: &str) -> String {
+        let declaring_class = self
+            .program
+            .subroutine_class(&class_name, subroutine_name)
+            .map(String::from);
+        declaring_class.unwrap_or(class_name)
+    }
+
+    /// The class that declares the subroutine a `super.name()` call goes to
+    fn super_class(&self, subroutine_name: &Identifier) -> Result<String, Diagnostic> {
+        let class_name = self.class_name.as_ref().unwrap();
+        if self.is_function() {
+            return Err(Diagnostic::error(
+                "E0203",
+                format!(
+                    "cannot use `super` inside function `{}`",
+                    self.subroutine_name.as_ref().unwrap()
+                ),
+            )
+            .with_span(subroutine_name.span)
+            .with_note("functions are not called on an object, so they have no `super`"));
+        }
+        let parent = self.program.parent(&class_name.name).ok_or_else(|| {
+            Diagnostic::error(
+                "E0216",
+                format!("cannot use `super` in class `{}`", class_name),
+            )
+            .with_span(subroutine_name.span)
+            .with_label(format!("`{}` doesn't extend a class", class_name))
+            .with_secondary(class_name.span, "class declared here")
+        })?;
+        self.program
+            .subroutine_class(&parent.name, &subroutine_name.name)
+            .map(String::from)
+            .ok_or_else(|| {
+                Diagnostic::error(
+                    "E0201",
+                    format!(
+                        "cannot find subroutine `{}` in class `{}`",
+                        subroutine_name, parent
+                    ),
+                )
+                .with_span(subroutine_name.span)
+                .with_label(format!(
+                    "not found in `{}` or the classes it extends",
+                    parent
+                ))
+            })
+    }
+
+    /// Checks that the subroutine `class_name.subroutine_name` is a method, since the object it
+    /// is called on is passed to it as arg 0. Classes that aren't compiled together, like the
+    /// OS classes, can't be checked.
+    fn check_method(
+        &self,
+        class_name: &str,
+        subroutine_name: &Identifier,
+    ) -> Result<(), Diagnostic> {
+        match self.program.subroutine(class_name, &subroutine_name.name) {
+            Some((SubroutineType::Method, _)) => Ok(()),
+            Some((subroutine_type, _)) => {
+                let kind = match subroutine_type {
+                    SubroutineType::Constructor => "constructor",
+                    _ => "function",
+                };
+                Err(Diagnostic::error(
+                    "E0215",
+                    format!(
+                        "cannot call {} `{}.{}` on an object",
+                        kind, class_name, subroutine_name
+                    ),
+                )
+                .with_span(subroutine_name.span)
+                .with_label(format!("`{}` is not a method", subroutine_name))
+                .with_help(format!(
+                    "call it on its class instead, like `{}.{}()`",
+                    class_name, subroutine_name
+                )))
+            }
+            None if self.program.has_class(class_name) => {
+                let diagnostic = Diagnostic::error(
+                    "E0201",
+                    format!(
+                        "cannot find subroutine `{}` in class `{}`",
+                        subroutine_name, class_name
+                    ),
+                )
+                .with_span(subroutine_name.span)
+                .with_label(format!(
+                    "not found in `{}` or the classes it extends",
+                    class_name
+                ));
+                let names = self
+                    .program
+                    .subroutines(class_name)
+                    .map(|(name, _, _)| name);
+                Err(match closest_match(&subroutine_name.name, names) {
+                    Some(similar) => diagnostic.with_suggestion(
+                        "a subroutine with a similar name exists",
+                        subroutine_name.span,
+                        similar,
+                    ),
+                    None => diagnostic,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The class of the object a method is called on in a chain, which has to be known at
+    /// compile time
+    fn receiver_class(
+        &self,
+        receiver: &Term,
+        subroutine_name: &Identifier,
+    ) -> Result<String, Diagnostic> {
+        match self.term_type(receiver) {
+            Some(VarType::ClassName(class_name)) => Ok(class_name.name),
+            // Arrays hold values of any class, so nothing says which class an element is
+            None if matches!(receiver.kind, TermKind::VarNameExpression(_)) => {
+                Err(Diagnostic::error(
+                    "E0215",
+                    format!(
+                        "cannot call method `{}` on an array element",
+                        subroutine_name
+                    ),
+                )
+                .with_span(subroutine_name.span)
+                .with_label("called on an array element")
+                .with_note(
+                    "calls on array elements are not supported, \
+                     because arrays don't record the class of their elements",
+                )
+                .with_help(
+                    "assign the element to a variable of its class, and call the method on that",
+                ))
+            }
+            Some(var_type) => Err(Diagnostic::error(
+                "E0215",
+                format!(
+                    "cannot call method `{}` on a value of type `{}`",
+                    subroutine_name, var_type
+                ),
+            )
+            .with_span(subroutine_name.span)
+            .with_label(format!("`{}` values have no methods", var_type))),
+            None => Err(Diagnostic::error(
+                "E0215",
+                format!(
+                    "cannot find the class of the object `{}` is called on",
+                    subroutine_name
+                ),
+            )
+            .with_span(subroutine_name.span)
+            .with_label("called on a value of unknown class")
+            .with_note(
+                "the class is only known for variables, fields, `this`, strings \
+                 and what subroutines of the classes compiled together return",
+            )
+            .with_help("assign the value to a variable of its class, and call the method on that")),
+        }
+    }
+
+    /// The type of the value of a term, if it is known at compile time
+    fn term_type(&self, term: &Term) -> Option<VarType> {
+        match &term.kind {
+            TermKind::IntegerConstant(_) => Some(VarType::Int),
+            TermKind::StringConstant(_) => Some(VarType::ClassName(Identifier::new(
+                "String",
+                Span::default(),
+            ))),
+            TermKind::KeywordConstant(KeywordConstant::True)
+            | TermKind::KeywordConstant(KeywordConstant::False) => Some(VarType::Boolean),
+            TermKind::KeywordConstant(KeywordConstant::This) => {
+                Some(VarType::ClassName(self.class_name.clone()?))
+            }
+            TermKind::KeywordConstant(KeywordConstant::Super) => {
+                let parent = self.program.parent(&self.class_name.as_ref()?.name)?;
+                Some(VarType::ClassName(parent.clone()))
+            }
+            TermKind::VarName(name) if self.is_constant(name) => Some(VarType::Int),
+            TermKind::VarName(name) => Some(self.symbol_table.get(&name.name)?.symbol_type.clone()),
+            TermKind::MemberAccess((name, member)) => match self.symbol_table.get(&name.name) {
+                Some(SymbolEntry {
+                    symbol_type: VarType::ClassName(class_name),
+                    ..
+                }) => {
+                    let (_, _, var_type) = self.program.field(&class_name.name, &member.name)?;
+                    Some(var_type.clone())
+                }
+                Some(_) => None,
+                None => Some(VarType::Int),
+            },
+            TermKind::SubroutineCall(call) => {
+                let class_name = match (&call.receiver, &call.class_or_var_name) {
+                    (Some(receiver), _) => match self.term_type(receiver)? {
+                        VarType::ClassName(class_name) => class_name.name,
+                        _ => return None,
+                    },
+                    (None, Some(name)) => match self.symbol_table.get(&name.name) {
+                        Some(SymbolEntry {
+                            symbol_type: VarType::ClassName(class_name),
+                            ..
+                        }) => class_name.name.clone(),
+                        Some(_) => return None,
+                        None => name.name.clone(),
+                    },
+                    (None, None) => self.class_name.as_ref()?.name.clone(),
+                };
+                let (_, return_type) = self
+                    .program
+                    .subroutine(&class_name, &call.subroutine_name.name)?;
+                return_type.cloned()
+            }
+            TermKind::Expression(expression) if expression.ops.is_empty() => {
+                self.term_type(&expression.term)
+            }
+            TermKind::KeywordConstant(KeywordConstant::Null)
+            | TermKind::VarNameExpression(_)
+            | TermKind::Expression(_)
+            | TermKind::UnaryOpTerm(_) => None,
+        }
+    }
 
-        for (op, term) in expression.ops {
-            self.compile_term(term);
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), Diagnostic> {
+        if self.program.opt_level >= 1 && self.is_constant_expression(expression) {
+            let class_name = &self.class_name.as_ref().unwrap().name;
+            // Division by zero is left to fail at runtime, like it does without optimizations
+            if let Ok(value) = self.program.constants.evaluate(class_name, expression) {
+                self.write_constant(value);
+                return Ok(());
+            }
+        }
+        self.compile_term(&expression.term, expression.span)?;
+
+        for (op, term) in expression.ops.iter() {
             match op {
-                Op::Plus => self.vm_writer.write_arithmetic(ArithmeticCommand::Add),
-                Op::Minus => self.vm_writer.write_arithmetic(ArithmeticCommand::Sub),
-                Op::Asterix => self.vm_writer.write_call("Math.multiply", 2),
-                Op::GreaterThan => self.vm_writer.write_arithmetic(ArithmeticCommand::Gt),
-                Op::LessThan => self.vm_writer.write_arithmetic(ArithmeticCommand::Lt),
-                Op::Ampersand => self.vm_writer.write_arithmetic(ArithmeticCommand::And),
-                Op::Equals => self.vm_writer.write_arithmetic(ArithmeticCommand::Eq),
-                Op::Slash => self.vm_writer.write_call("Math.divide", 2),
-                Op::VerticalBar => self.vm_writer.write_arithmetic(ArithmeticCommand::Or),
-            };
+                Op::DoubleAmpersand => {
+                    // false && anything is false, without evaluating the right side.
+                    // Otherwise the result is whether the right side is true, as -1 or 0
+                    self.label_count += 1;
+                    let right_label = format!("AND_{}_RIGHT", self.label_count);
+                    let end_label = format!("AND_{}_END", self.label_count);
+                    self.vm_writer.write_if(&right_label);
+                    self.vm_writer.write_push(Segment::Const, 0);
+                    self.vm_writer.write_goto(&end_label);
+                    self.vm_writer.write_label(&right_label);
+                    self.compile_term(term, expression.span)?;
+                    self.write_is_true();
+                    self.vm_writer.write_label(&end_label);
+                }
+                Op::DoubleVerticalBar => {
+                    // true || anything is true, without evaluating the right side.
+                    // Otherwise the result is whether the right side is true, as -1 or 0
+                    self.label_count += 1;
+                    let true_label = format!("OR_{}_TRUE", self.label_count);
+                    let end_label = format!("OR_{}_END", self.label_count);
+                    self.vm_writer.write_if(&true_label);
+                    self.compile_term(term, expression.span)?;
+                    self.write_is_true();
+                    self.vm_writer.write_goto(&end_label);
+                    self.vm_writer.write_label(&true_label);
+                    self.vm_writer.write_push(Segment::Const, 1);
+                    self.vm_writer.write_arithmetic(ArithmeticCommand::Neg);
+                    self.vm_writer.write_label(&end_label);
+                }
+                Op::DoubleLessThan | Op::DoubleGreaterThan => match self.constant_term(term) {
+                    Some(amount) => self.write_constant_shift(op, amount),
+                    None => {
+                        self.compile_term(term, expression.span)?;
+                        self.write_op(op);
+                    }
+                },
+                _ => {
+                    self.compile_term(term, expression.span)?;
+                    self.write_op(op);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an expression is made only of constants, so its value is known at compile time
+    fn is_constant_expression(&self, expression: &Expression) -> bool {
+        iter::once(&expression.term)
+            .chain(expression.ops.iter().map(|(_, term)| term))
+            .all(|term| self.is_constant_term(term))
+    }
+
+    fn is_constant_term(&self, term: &Term) -> bool {
+        match &term.kind {
+            TermKind::IntegerConstant(_)
+            | TermKind::KeywordConstant(KeywordConstant::True)
+            | TermKind::KeywordConstant(KeywordConstant::False)
+            | TermKind::KeywordConstant(KeywordConstant::Null) => true,
+            TermKind::Expression(expression) => self.is_constant_expression(expression),
+            TermKind::UnaryOpTerm((_, term)) => self.is_constant_term(term),
+            TermKind::VarName(_) | TermKind::MemberAccess(_) => self.constant_term(term).is_some(),
+            _ => false,
+        }
+    }
+
+    /// The value of a term that is known without generating any code for it
+    fn constant_term(&self, term: &Term) -> Option<u16> {
+        match &term.kind {
+            TermKind::IntegerConstant(int) => Some(*int),
+            TermKind::VarName(name) if self.is_constant(name) => {
+                let class_name = &self.class_name.as_ref().unwrap().name;
+                self.program.constants.get(class_name, &name.name)
+            }
+            TermKind::MemberAccess((name, member))
+                if self.symbol_table.get(&name.name).is_none() =>
+            {
+                self.program.constants.get(&name.name, &member.name)
+            }
+            _ => None,
+        }
+    }
+
+    /// Shifts the value on the stack by a constant amount, without any loops.
+    /// Like shifts by a variable amount, a negative amount does nothing.
+    fn write_constant_shift(&mut self, op: &Op, amount: u16) {
+        let amount = amount as i16;
+        if amount <= 0 {
+            return;
+        }
+        if amount >= 16 {
+            self.vm_writer.write_pop(Segment::Temp, 0);
+            self.vm_writer.write_push(Segment::Const, 0);
+        } else if *op == Op::DoubleLessThan {
+            // Each doubling shifts left by one
+            for _ in 0..amount {
+                self.vm_writer.write_pop(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Temp, 0);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Add);
+            }
+        } else {
+            // Copy each bit that is kept down to its new position
+            self.vm_writer.write_pop(Segment::Temp, 0);
+            self.vm_writer.write_push(Segment::Const, 0);
+            for bit in 0..16 - amount {
+                self.vm_writer.write_push(Segment::Temp, 0);
+                self.write_constant(1 << (bit + amount));
+                self.vm_writer.write_arithmetic(ArithmeticCommand::And);
+                self.vm_writer.write_push(Segment::Const, 0);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Eq);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+                self.write_constant(1 << bit);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::And);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Or);
+            }
+        }
+    }
+
+    /// Writes an operator that takes both of its operands from the stack
+    fn write_op(&mut self, op: &Op) {
+        match op {
+            Op::Plus => self.vm_writer.write_arithmetic(ArithmeticCommand::Add),
+            Op::Minus => self.vm_writer.write_arithmetic(ArithmeticCommand::Sub),
+            Op::Asterix => self.vm_writer.write_call("Math.multiply", 2),
+            Op::GreaterThan => self.vm_writer.write_arithmetic(ArithmeticCommand::Gt),
+            Op::LessThan => self.vm_writer.write_arithmetic(ArithmeticCommand::Lt),
+            Op::Ampersand => self.vm_writer.write_arithmetic(ArithmeticCommand::And),
+            Op::Equals => self.vm_writer.write_arithmetic(ArithmeticCommand::Eq),
+            Op::Slash => self.vm_writer.write_call("Math.divide", 2),
+            Op::VerticalBar => self.vm_writer.write_arithmetic(ArithmeticCommand::Or),
+            // Each is the opposite of a comparison the VM has
+            Op::LessThanEquals => {
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Gt);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+            }
+            Op::GreaterThanEquals => {
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Lt);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+            }
+            Op::NotEquals => {
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Eq);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+            }
+            Op::Percent => {
+                // a % b is a - b * (a / b), so both operands are needed twice
+                self.vm_writer.write_pop(Segment::Temp, 1);
+                self.vm_writer.write_pop(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Temp, 1);
+                self.vm_writer.write_push(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Temp, 1);
+                self.vm_writer.write_call("Math.divide", 2);
+                self.vm_writer.write_call("Math.multiply", 2);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Sub);
+            }
+            Op::DoubleLessThan => {
+                // Double the value in temp 0 as many times as the amount in temp 1 counts down
+                self.label_count += 1;
+                let loop_label = format!("SHIFT_LEFT_{}_LOOP", self.label_count);
+                let end_label = format!("SHIFT_LEFT_{}_END", self.label_count);
+                self.vm_writer.write_pop(Segment::Temp, 1);
+                self.vm_writer.write_pop(Segment::Temp, 0);
+                self.vm_writer.write_label(&loop_label);
+                self.write_count_down(1, &end_label);
+                self.write_double(0);
+                self.vm_writer.write_goto(&loop_label);
+                self.vm_writer.write_label(&end_label);
+                self.vm_writer.write_push(Segment::Temp, 0);
+            }
+            Op::DoubleGreaterThan => {
+                // Temp 2 starts as the lowest bit that is kept, found by doubling 1 by the
+                // amount. Then each bit from there up is copied into the result in temp 3 at
+                // the bit in temp 1, until temp 2 overflows to 0.
+                self.label_count += 1;
+                let start_label = format!("SHIFT_RIGHT_{}_START", self.label_count);
+                let bit_label = format!("SHIFT_RIGHT_{}_BIT", self.label_count);
+                let copy_label = format!("SHIFT_RIGHT_{}_COPY", self.label_count);
+                let end_label = format!("SHIFT_RIGHT_{}_END", self.label_count);
+                self.vm_writer.write_pop(Segment::Temp, 1);
+                self.vm_writer.write_pop(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Const, 1);
+                self.vm_writer.write_pop(Segment::Temp, 2);
+                self.vm_writer.write_label(&start_label);
+                self.write_count_down(1, &bit_label);
+                self.write_double(2);
+                self.vm_writer.write_goto(&start_label);
+                self.vm_writer.write_label(&bit_label);
+                self.vm_writer.write_push(Segment::Const, 1);
+                self.vm_writer.write_pop(Segment::Temp, 1);
+                self.vm_writer.write_push(Segment::Const, 0);
+                self.vm_writer.write_pop(Segment::Temp, 3);
+                self.vm_writer.write_label(&copy_label);
+                self.vm_writer.write_push(Segment::Temp, 2);
+                self.vm_writer.write_push(Segment::Const, 0);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Eq);
+                self.vm_writer.write_if(&end_label);
+                self.vm_writer.write_push(Segment::Temp, 0);
+                self.vm_writer.write_push(Segment::Temp, 2);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::And);
+                self.vm_writer.write_push(Segment::Const, 0);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Eq);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+                self.vm_writer.write_push(Segment::Temp, 1);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::And);
+                self.vm_writer.write_push(Segment::Temp, 3);
+                self.vm_writer.write_arithmetic(ArithmeticCommand::Or);
+                self.vm_writer.write_pop(Segment::Temp, 3);
+                self.write_double(2);
+                self.write_double(1);
+                self.vm_writer.write_goto(&copy_label);
+                self.vm_writer.write_label(&end_label);
+                self.vm_writer.write_push(Segment::Temp, 3);
+            }
+            Op::DoubleAmpersand | Op::DoubleVerticalBar => {
+                unreachable!("short-circuit operators are compiled with their right side")
+            }
         }
     }
 
-    fn compile_term(&mut self, term: Term) {
-        match term {
-            Term::Expression(expression) => self.compile_expression(*expression),
-            Term::IntegerConstant(int) => self.vm_writer.write_push(Segment::Const, int),
-            Term::KeywordConstant(keyword) => match keyword {
+    /// Replaces the value on the stack with `true` (-1) if it isn't 0, or `false` (0) if it is
+    fn write_is_true(&mut self) {
+        self.vm_writer.write_push(Segment::Const, 0);
+        self.vm_writer.write_arithmetic(ArithmeticCommand::Eq);
+        self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+    }
+
+    /// Jumps to `end_label` once the counter in the temp segment is no longer positive,
+    /// otherwise decrements it
+    fn write_count_down(&mut self, temp: u16, end_label: &str) {
+        self.vm_writer.write_push(Segment::Temp, temp);
+        self.vm_writer.write_push(Segment::Const, 0);
+        self.vm_writer.write_arithmetic(ArithmeticCommand::Gt);
+        self.vm_writer.write_arithmetic(ArithmeticCommand::Not);
+        self.vm_writer.write_if(end_label);
+        self.vm_writer.write_push(Segment::Temp, temp);
+        self.vm_writer.write_push(Segment::Const, 1);
+        self.vm_writer.write_arithmetic(ArithmeticCommand::Sub);
+        self.vm_writer.write_pop(Segment::Temp, temp);
+    }
+
+    /// Doubles the value in the temp segment, which shifts it left by one
+    fn write_double(&mut self, temp: u16) {
+        self.vm_writer.write_push(Segment::Temp, temp);
+        self.vm_writer.write_push(Segment::Temp, temp);
+        self.vm_writer.write_arithmetic(ArithmeticCommand::Add);
+        self.vm_writer.write_pop(Segment::Temp, temp);
+    }
+
+    /// `span` is the span of the enclosing expression, used for error reporting
+    fn compile_term(&mut self, term: &Term, span: Span) -> Result<(), Diagnostic> {
+        match &term.kind {
+            TermKind::Expression(expression) => self.compile_expression(expression)?,
+            TermKind::IntegerConstant(int) => self.vm_writer.write_push(Segment::Const, *int),
+            TermKind::KeywordConstant(keyword) => match keyword {
                 KeywordConstant::True => {
                     self.vm_writer.write_push(Segment::Const, 1);
                     self.vm_writer.write_arithmetic(ArithmeticCommand::Neg);
                 }
                 KeywordConstant::False => self.vm_writer.write_push(Segment::Const, 0),
                 KeywordConstant::This => {
+                    if self.is_function() {
+                        return Err(Diagnostic::error(
+                            "E0203",
+                            format!(
+                                "cannot use `this` inside function `{}`",
+                                self.subroutine_name.as_ref().unwrap()
+                            ),
+                        )
+                        .with_span(span)
+                        .with_note(
+                            "functions are not called on an object, so they have no `this`",
+                        ));
+                    }
                     self.vm_writer.write_push(Segment::Pointer, 0);
                 }
                 KeywordConstant::Null => self.vm_writer.write_push(Segment::Const, 0),
+                KeywordConstant::Super => {
+                    return Err(Diagnostic::error(
+                        "E0216",
+                        "`super` can only be used to call a subroutine of the parent class",
+                    )
+                    .with_span(span)
+                    .with_help("call a method on it, like `super.draw()`, or use `this`"));
+                }
             },
-            Term::UnaryOpTerm((op, term)) => {
-                self.compile_term(*term);
+            TermKind::UnaryOpTerm((op, term)) => {
+                self.compile_term(term, span)?;
                 match op {
                     UnaryOp::Minus => self.vm_writer.write_arithmetic(ArithmeticCommand::Neg),
                     UnaryOp::Tilde => self.vm_writer.write_arithmetic(ArithmeticCommand::Not),
                 };
             }
-            Term::VarName(var_name) => {
-                let entry = self
-                    .symbol_table
-                    .get(&&var_name)
-                    .expect(&format!("Unknown variable: {}", var_name));
+            TermKind::VarName(var_name) if self.is_constant(var_name) => {
+                let class_name = &self.class_name.as_ref().unwrap().name;
+                let value = self.program.constants.resolve(class_name, None, var_name)?;
+                self.write_constant(value);
+            }
+            TermKind::MemberAccess((name, member))
+                if self.symbol_table.get(&name.name).is_some()
+                    && self.program.is_enabled(Extension::FieldAccess) =>
+            {
+                let field_index = self.lookup_field(name, member)?;
+                let entry = self.lookup_var(name)?;
+                let (entry_kind, entry_index) = (entry.kind, entry.index);
+                self.write_field_pointer(entry_kind, entry_index);
+                self.vm_writer.write_push(Segment::That, field_index);
+            }
+            TermKind::MemberAccess((name, member)) => {
+                if let Some(entry) = self.symbol_table.get(&name.name) {
+                    let mut diagnostic = Diagnostic::error(
+                        "E0209",
+                        format!("cannot find constant `{}` in `{}`", member, name),
+                    )
+                    .with_span(name.span.to(member.span))
+                    .with_label(format!("`{}` is a variable", name));
+                    if let Some(span) = entry.span {
+                        diagnostic = diagnostic.with_secondary(span, "variable declared here");
+                    }
+                    return Err(diagnostic
+                        .with_note("only classes and enums have constants")
+                        .with_help("fields of other objects need the `field-access` extension"));
+                }
+                let class_name = &self.class_name.as_ref().unwrap().name;
+                let value = self
+                    .program
+                    .constants
+                    .resolve(class_name, Some(name), member)?;
+                self.write_constant(value);
+            }
+            TermKind::VarName(var_name) => {
+                let entry = self.lookup_var(var_name)?;
+                let (entry_kind, entry_index) = (entry.kind, entry.index);
                 self.vm_writer
-                    .write_push(Segment::from(entry.kind), entry.index);
+                    .write_push(Segment::from(entry_kind), entry_index);
             }
-            Term::SubroutineCall(subroutine_call) => self.compile_subroutine_call(subroutine_call),
-            Term::StringConstant(string) => {
+            TermKind::SubroutineCall(subroutine_call) => {
+                self.compile_subroutine_call(subroutine_call)?
+            }
+            TermKind::StringConstant(string) => {
                 // Create the string
                 self.vm_writer.write_push(
                     Segment::Const,
-                    string
-                        .len()
-                        .try_into()
-                        .expect("String constant length exceeds u16 size"),
+                    string.chars().count().try_into().map_err(|_| {
+                        Diagnostic::error("E0206", "string constant is too long").with_span(span)
+                    })?,
                 );
                 self.vm_writer.write_call("String.new", 1);
-                self.vm_writer.write_pop(Segment::Temp, 0);
 
-                // Append each character
+                // Append each character. appendChar returns the string, which
+                // leaves it on the stack for the next call.
+                // Strings are only limited to the Jack character set with the literals extension,
+                // so programs that compiled before it keep compiling
+                let is_checked = self.program.is_enabled(Extension::Literals);
                 for c in string.chars() {
-                    self.vm_writer.write_push(Segment::Temp, 0);
+                    let code = if is_checked {
+                        char_code(c)
+                    } else {
+                        u32::from(c).try_into().ok()
+                    };
                     self.vm_writer.write_push(
                         Segment::Const,
-                        u32::from(c)
-                            .try_into()
-                            .expect(&format!("Character {} is outside the range of u16", c)),
+                        code.ok_or_else(|| {
+                            let diagnostic = Diagnostic::error(
+                                "E0207",
+                                format!(
+                                    "character `{}` is not in the Jack character set",
+                                    c.escape_debug()
+                                ),
+                            )
+                            .with_span(span);
+                            if is_checked {
+                                diagnostic.with_note(CHARACTER_SET_NOTE)
+                            } else {
+                                diagnostic.with_note("its code does not fit in 16 bits")
+                            }
+                        })?,
                     );
-                    // TODO is this the write function signature?
                     self.vm_writer.write_call("String.appendChar", 2);
                 }
             }
-            Term::VarNameExpression((var_name, expression)) => {
-                let entry = self
-                    .symbol_table
-                    .get(&var_name)
-                    .expect(&format!("Unknown variable: {}", var_name));
+            TermKind::VarNameExpression((var_name, expression)) => {
+                let entry = self.lookup_var(var_name)?;
+                let (entry_kind, entry_index) = (entry.kind, entry.index);
                 self.vm_writer
-                    .write_push(Segment::from(entry.kind), entry.index);
-                self.compile_expression(*expression);
+                    .write_push(Segment::from(entry_kind), entry_index);
+                self.compile_expression(expression)?;
                 self.vm_writer.write_arithmetic(ArithmeticCommand::Add);
                 self.vm_writer.write_pop(Segment::Pointer, 1);
                 self.vm_writer.write_push(Segment::That, 0);
             }
         }
+        Ok(())
+    }
+}
+
+/// The span used for the commands generated by a statement in the source map.
+/// Statements only record the spans of their parts, so this covers as much of them as possible.
+fn statement_span(statement: &Statement) -> Option<Span> {
+    match statement {
+        Statement::Let(statement) => Some(
+            statement
+                .var_name
+                .span
+                .to(statement.right_side_expression.span),
+        ),
+        Statement::If(statement) => Some(statement.expression.span),
+        Statement::While(statement) => Some(statement.expression.span),
+        Statement::Do(statement) => {
+            let call = &statement.subroutine_call;
+            let start = call
+                .class_or_var_name
+                .as_ref()
+                .map_or(call.subroutine_name.span, |name| name.span);
+            Some(match call.expression_list.last() {
+                Some(expression) => start.to(expression.span),
+                None => start.to(call.subroutine_name.span),
+            })
+        }
+        Statement::Return(statement) => statement
+            .expression
+            .as_ref()
+            .map(|expression| expression.span),
+        Statement::For(statement) => statement.condition.as_ref().map(|condition| condition.span),
+        Statement::Break(statement) => Some(statement.span),
+        Statement::Continue(statement) => Some(statement.span),
+    }
+}
+
+/// The names of the identifiers in some statements, which include every variable they use
+#[derive(Default)]
+struct Names<'ast>(HashSet<&'ast str>);
+
+impl<'ast> Visitor<'ast> for Names<'ast> {
+    fn visit_identifier(&mut self, identifier: &'ast Identifier) {
+        self.0.insert(&identifier.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(classes: &[Class]) -> Program {
+        let extensions = Extension::NAMES
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+        Program::new(classes.iter(), &extensions)
+    }
+
+    /// Compiles the first of `sources` with every extension, in a program with the others
+    fn compile(sources: &[&str]) -> Result<CompiledClass, Diagnostic> {
+        let classes: Vec<Class> = sources.iter().map(|source| parse_source(source)).collect();
+        compile_in_program(&classes[0], &program(&classes))
+    }
+
+    /// Compiles a class with every extension and returns its VM code
+    fn vm(source: &str) -> Vec<String> {
+        compile(&[source]).unwrap().vm
+    }
+
+    /// Compiles a class with every extension and returns its error
+    fn error(source: &str) -> Option<Diagnostic> {
+        compile(&[source]).err()
+    }
+
+    fn error_code(source: &str) -> Option<&'static str> {
+        error(source).map(|diagnostic| diagnostic.code)
+    }
+
+    fn call_in_method(call: &str) -> String {
+        format!(
+            "class A {{
+                function int make() {{ return 1; }}
+                method int get() {{ return 1; }}
+                method A me() {{ return this; }}
+                method void run(Array items) {{ var int v; let v = {}; return; }}
+            }}",
+            call
+        )
+    }
+
+    #[test]
+    fn chained_calls_must_call_methods() {
+        assert_eq!(error_code(&call_in_method("me().get()")), None);
+        assert_eq!(error_code(&call_in_method("me().make()")), Some("E0215"));
+        assert_eq!(error_code(&call_in_method("me().gett()")), Some("E0201"));
+        // The OS classes aren't compiled with the program, so their calls can't be checked
+        assert_eq!(error_code(&call_in_method("\"ab\".length()")), None);
+    }
+
+    const SHAPE: &str = "class Shape {
+        field int x;
+        method int getX() { return x; }
+        method void draw() { return; }
+    }";
+
+    #[test]
+    fn subclasses_lay_out_inherited_fields_first() {
+        let source = "class Square extends Shape {
+            field int size;
+            constructor Square new() { let x = 1; let size = 2; return this; }
+        }";
+        assert_eq!(
+            compile(&[source, SHAPE]).unwrap().vm,
+            [
+                "function Square.new 0",
+                "push constant 2",
+                "call Memory.alloc 1",
+                "pop pointer 0",
+                "push constant 1",
+                "pop this 0",
+                "push constant 2",
+                "pop this 1",
+                "push pointer 0",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_go_to_the_closest_class_that_declares_the_method() {
+        let source = "class Square extends Shape {
+            method void draw() { do super.draw(); return; }
+            function int use(Square s) { do s.draw(); return s.getX(); }
+        }";
+        assert_eq!(
+            compile(&[source, SHAPE]).unwrap().vm,
+            [
+                "function Square.draw 0",
+                "push argument 0",
+                "pop pointer 0",
+                // `super` is `this`, with the method looked up from the parent
+                "push pointer 0",
+                "call Shape.draw 1",
+                "pop temp 0",
+                "push constant 0",
+                "return",
+                "function Square.use 0",
+                "push argument 0",
+                "call Square.draw 1",
+                "pop temp 0",
+                "push argument 0",
+                "call Shape.getX 1",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn inherited_fields_cannot_be_declared_again() {
+        let source = "class Square extends Shape { field int size, x; }";
+        let diagnostic = compile(&[source, SHAPE]).unwrap_err();
+        assert_eq!(diagnostic.code, "E0216");
+        assert_eq!(
+            diagnostic.message,
+            "field `x` is already inherited from `Shape`"
+        );
+        // Fields of every ancestor are inherited
+        let cube = "class Cube extends Square { static int x; }";
+        let source = "class Square extends Shape { field int size; }";
+        assert_eq!(compile(&[cube, source, SHAPE]).unwrap_err().code, "E0216");
+    }
+
+    #[test]
+    fn classes_cannot_inherit_from_themselves() {
+        let a = "class A extends B { }";
+        let b = "class B extends A { }";
+        let diagnostic = compile(&[a, b]).unwrap_err();
+        assert_eq!(diagnostic.code, "E0216");
+        assert_eq!(diagnostic.message, "class `A` inherits from itself");
+    }
+
+    /// `arr[i].draw()` was one of the examples the call-chains extension was asked for, but
+    /// nothing records which class an array's elements are
+    #[test]
+    fn calls_on_array_elements_are_not_supported() {
+        let diagnostic = error(&call_in_method("items[0].get()")).unwrap();
+        assert_eq!(diagnostic.code, "E0215");
+        assert_eq!(
+            diagnostic.message,
+            "cannot call method `get` on an array element"
+        );
+        assert_eq!(
+            diagnostic.help[0].message,
+            "assign the element to a variable of its class, and call the method on that"
+        );
+        // Which is what the help says to do
+        let source = "class A {
+            method int get() { return 1; }
+            method void run(Array items) { var A a; var int v; let a = items[0]; let v = a.get(); return; }
+        }";
+        assert_eq!(error_code(source), None);
+    }
+
+    #[test]
+    fn unused_variables_are_allowed_by_default() {
+        let classes = [parse_source(
+            "class A { function void f() { var int unused; return; } }",
+        )];
+        let mut program = program(&classes);
+        let compiled = compile_in_program(&classes[0], &program).unwrap();
+        assert!(compiled.warnings.is_empty());
+
+        program.lints.set(Lint::UnusedVariable, LintLevel::Warn);
+        let compiled = compile_in_program(&classes[0], &program).unwrap();
+        assert_eq!(compiled.warnings[0].code, "W0001");
+        assert_eq!(
+            compiled.warnings[0].notes,
+            vec!["`unused-variable` is set to `warn`"]
+        );
+    }
+
+    #[test]
+    fn else_if_chains_share_one_end_label() {
+        let vm = vm("class A {
+            function int f(int x) {
+                if (x = 1) { return 1; } else if (x = 2) { return 2; } else { return 3; }
+            }
+        }");
+        let labels: Vec<&str> = vm
+            .iter()
+            .filter(|line| line.starts_with("label"))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            labels,
+            ["label IF_1_FALSE", "label IF_2_FALSE", "label IF_1_END"]
+        );
+        let gotos = vm.iter().filter(|line| *line == "goto IF_1_END").count();
+        assert_eq!(gotos, 2);
+    }
+
+    #[test]
+    fn for_loops_continue_at_the_update() {
+        let vm = vm("class A {
+            function void f() {
+                var int i;
+                for (let i = 0; i < 3; let i = i + 1) {
+                    if (i = 1) { continue; }
+                    break;
+                }
+                return;
+            }
+        }");
+        assert_eq!(
+            vm,
+            [
+                "function A.f 1",
+                "push constant 0",
+                "pop local 0",
+                "label FOR_1_CONDITION",
+                "push local 0",
+                "push constant 3",
+                "lt",
+                "not",
+                "if-goto FOR_1_END",
+                "push local 0",
+                "push constant 1",
+                "eq",
+                "not",
+                "if-goto IF_2_FALSE",
+                "goto FOR_1_UPDATE",
+                "goto IF_2_END",
+                "label IF_2_FALSE",
+                "label IF_2_END",
+                "goto FOR_1_END",
+                "label FOR_1_UPDATE",
+                "push local 0",
+                "push constant 1",
+                "add",
+                "pop local 0",
+                "goto FOR_1_CONDITION",
+                "label FOR_1_END",
+                "push constant 0",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn break_and_continue_go_to_the_innermost_loop() {
+        let vm = vm("class A {
+            function void f() {
+                var int i;
+                while (true) {
+                    for (;;) { continue; }
+                    for (;;) { break; }
+                    continue;
+                }
+                return;
+            }
+        }");
+        let jumps: Vec<&str> = vm
+            .iter()
+            .filter(|line| line.starts_with("goto") || line.starts_with("label"))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            jumps,
+            [
+                "label WHILE_1_CONDITION",
+                "label FOR_2_CONDITION",
+                "goto FOR_2_UPDATE",
+                "label FOR_2_UPDATE",
+                "goto FOR_2_CONDITION",
+                "label FOR_2_END",
+                "label FOR_3_CONDITION",
+                "goto FOR_3_END",
+                "label FOR_3_UPDATE",
+                "goto FOR_3_CONDITION",
+                "label FOR_3_END",
+                "goto WHILE_1_CONDITION",
+                "goto WHILE_1_CONDITION",
+                "label WHILE_1_END",
+            ]
+        );
+    }
+
+    #[test]
+    fn break_and_continue_must_be_in_a_loop() {
+        let diagnostic = error("class A { function void f() { break; } }").unwrap();
+        assert_eq!(diagnostic.code, "E0208");
+        assert_eq!(diagnostic.message, "`break` outside of a loop");
+        let source = "class A { function void f() { while (true) { } continue; } }";
+        assert_eq!(error_code(source), Some("E0208"));
+        let source = "class A { function void f() { for (;;) { if (true) { break; } } return; } }";
+        assert_eq!(error_code(source), None);
+    }
+
+    #[test]
+    fn compound_assignment_to_an_element_evaluates_the_index_once() {
+        let vm = vm("class A {
+            function void f(Array a) {
+                let a[A.i()] += 3;
+                return;
+            }
+            function int i() { return 1; }
+        }");
+        assert_eq!(
+            vm[..14],
+            [
+                "function A.f 0",
+                "push argument 0",
+                "call A.i 0",
+                "add",
+                // The address is kept on the stack for the assignment
+                "pop pointer 1",
+                "push pointer 1",
+                "push that 0",
+                "push constant 3",
+                "add",
+                "pop temp 0",
+                "pop pointer 1",
+                "push temp 0",
+                "pop that 0",
+                "push constant 0",
+            ]
+        );
+        assert_eq!(vm.iter().filter(|line| *line == "call A.i 0").count(), 1);
+    }
+
+    #[test]
+    fn only_the_literals_extension_limits_strings_to_the_character_set() {
+        let source =
+            "class A { function void f() { do Output.printString(\"caf\u{e9}\"); return; } }";
+        let class = parse_source(source);
+        let program = Program::new(std::iter::once(&class), &Extensions::new());
+        let compiled = compile_in_program(&class, &program).unwrap();
+        assert!(compiled.vm.contains(&"push constant 233".to_string()));
+
+        let diagnostic = error(source).unwrap();
+        assert_eq!(diagnostic.code, "E0207");
+        assert_eq!(
+            diagnostic.message,
+            "character `\u{e9}` is not in the Jack character set"
+        );
+    }
+
+    const POINT: &str = "class Point {
+        field int x, y;
+        function int getY(Point p) { return p.y; }
+        function void setY(Point p) { let p.y = 5; return; }
+    }";
+
+    #[test]
+    fn fields_of_other_objects_are_read_and_written_through_that() {
+        assert_eq!(
+            vm(POINT),
+            [
+                "function Point.getY 0",
+                "push argument 0",
+                "pop pointer 1",
+                "push that 1",
+                "return",
+                "function Point.setY 0",
+                // The value is computed before `that` points at the object
+                "push constant 5",
+                "push argument 0",
+                "pop pointer 1",
+                "pop that 1",
+                "push constant 0",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn inherited_fields_come_before_the_classes_own_fields() {
+        let source = "class Point3 extends Point {
+            field int z;
+            function int sum(Point3 p) { return p.y + p.z; }
+            method void setY() { let y = 1; return; }
+        }";
+        let vm = compile(&[source, POINT]).unwrap().vm;
+        assert_eq!(
+            vm[..9],
+            [
+                "function Point3.sum 0",
+                "push argument 0",
+                "pop pointer 1",
+                "push that 1",
+                "push argument 0",
+                "pop pointer 1",
+                "push that 2",
+                "add",
+                "return",
+            ]
+        );
+        assert!(vm.contains(&"pop this 1".to_string()));
+    }
+
+    #[test]
+    fn unknown_fields_are_reported() {
+        let source = "class Point3 extends Point {
+            function int f(Point3 p) { return p.w; }
+        }";
+        let diagnostic = compile(&[source, POINT]).unwrap_err();
+        assert_eq!(diagnostic.code, "E0214");
     }
 }
@@ -0,0 +1,299 @@
+use crate::diagnostics::{closest_match, Diagnostic, Span};
+use crate::parser::*;
+use std::collections::HashMap;
+
+/// The values of the constants and enum variants of a program.
+///
+/// Constants are referred to through the class that declares them, and enum variants through
+/// their enum, so both are grouped by that name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Constants {
+    scopes: HashMap<String, Scope>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Scope {
+    /// The class that declares the constants or the enum
+    class_name: String,
+    /// `None` for a constant whose value can't be computed, because its type or expression
+    /// has an error or it depends on itself
+    values: HashMap<String, Option<u16>>,
+}
+
+impl Constants {
+    /// Computes the value of every constant declared in the classes. Errors are left for
+    /// `evaluate` to report when the class that declares the constant is compiled.
+    pub fn new<'ast>(classes: impl IntoIterator<Item = &'ast Class>) -> Constants {
+        let mut constants = Constants::default();
+        let mut pending = HashMap::new();
+        for class in classes {
+            let class_name = &class.class_name.name;
+            for declaration in class.enum_declarations.iter() {
+                let scope = constants.scope(&declaration.name.name, class_name);
+                // An enum that is declared again by another class is reported when it is compiled
+                if let Some(scope) = scope {
+                    for (index, variant) in declaration.variants.iter().enumerate() {
+                        scope
+                            .values
+                            .insert(variant.name.clone(), Some(index as u16));
+                    }
+                }
+            }
+            if let Some(scope) = constants.scope(class_name, class_name) {
+                for declaration in class.constant_declarations.iter() {
+                    scope.values.insert(declaration.name.name.clone(), None);
+                    // Only `int`, `char` and `boolean` constants have values
+                    if let VarType::ClassName(_) = declaration.var_type {
+                        continue;
+                    }
+                    pending.insert(
+                        (class_name.clone(), declaration.name.name.clone()),
+                        &declaration.expression,
+                    );
+                }
+            }
+        }
+        let names: Vec<(String, String)> = pending.keys().cloned().collect();
+        for (class_name, name) in names {
+            constants.compute(&mut pending, &class_name, &name);
+        }
+        constants
+    }
+
+    /// The scope for `name`, unless another class already declared it
+    fn scope(&mut self, name: &str, class_name: &str) -> Option<&mut Scope> {
+        let scope = self
+            .scopes
+            .entry(name.to_string())
+            .or_insert_with(|| Scope {
+                class_name: class_name.to_string(),
+                values: HashMap::new(),
+            });
+        if scope.class_name == class_name {
+            Some(scope)
+        } else {
+            None
+        }
+    }
+
+    /// Computes a constant declared in `class_name`, first computing the ones it refers to.
+    /// A constant is taken out of `pending` while it is computed, so one that depends on
+    /// itself finds no value.
+    fn compute(
+        &mut self,
+        pending: &mut HashMap<(String, String), &Expression>,
+        class_name: &str,
+        name: &str,
+    ) -> Option<u16> {
+        let expression = match pending.remove(&(class_name.to_string(), name.to_string())) {
+            Some(expression) => expression,
+            None => return self.get(class_name, name),
+        };
+        let value = evaluate(expression, &mut |scope, member| {
+            let scope = scope.map_or(class_name, |scope| &scope.name);
+            // Only whether there is a value matters here, `resolve` reports the details
+            self.compute(pending, scope, &member.name).ok_or_else(|| {
+                Diagnostic::error(
+                    "E0211",
+                    format!(
+                        "cannot compute the value of constant `{}.{}`",
+                        scope, member
+                    ),
+                )
+            })
+        })
+        .ok();
+        if let Some(scope) = self.scopes.get_mut(class_name) {
+            scope.values.insert(name.to_string(), value);
+        }
+        value
+    }
+
+    /// The value of `scope.name`, if it is a constant or enum variant with a value
+    pub fn get(&self, scope: &str, name: &str) -> Option<u16> {
+        *self.scopes.get(scope)?.values.get(name)?
+    }
+
+    /// Whether `scope.name` is declared, even if it has no value
+    pub fn contains(&self, scope: &str, name: &str) -> bool {
+        self.scopes
+            .get(scope)
+            .is_some_and(|scope| scope.values.contains_key(name))
+    }
+
+    /// The class that declares the constants of a class, or an enum
+    pub fn declaring_class(&self, scope: &str) -> Option<&str> {
+        self.scopes
+            .get(scope)
+            .map(|scope| scope.class_name.as_str())
+    }
+
+    /// The value of a constant expression written in `class_name`
+    pub fn evaluate(&self, class_name: &str, expression: &Expression) -> Result<u16, Diagnostic> {
+        evaluate(expression, &mut |scope, name| {
+            self.resolve(class_name, scope, name)
+        })
+    }
+
+    /// The value of `scope.name`, or of `name` in `class_name` when there is no scope
+    pub fn resolve(
+        &self,
+        class_name: &str,
+        scope: Option<&Identifier>,
+        name: &Identifier,
+    ) -> Result<u16, Diagnostic> {
+        let scope_name = scope.map_or(class_name, |scope| &scope.name);
+        let qualified_name = format!("{}.{}", scope_name, name);
+        let span = scope.map_or(name.span, |scope| scope.span.to(name.span));
+        let values = match self.scopes.get(scope_name) {
+            Some(scope) => &scope.values,
+            None => {
+                return Err(Diagnostic::error(
+                    "E0209",
+                    format!("cannot find constant `{}`", qualified_name),
+                )
+                .with_span(span)
+                .with_label(format!(
+                    "no class or enum named `{}` declares constants",
+                    scope_name
+                )));
+            }
+        };
+        match values.get(&name.name) {
+            Some(Some(value)) => Ok(*value),
+            Some(None) => Err(Diagnostic::error(
+                "E0211",
+                format!("cannot compute the value of constant `{}`", qualified_name),
+            )
+            .with_span(span)
+            .with_note("its value depends on itself, or its declaration has an error")),
+            None => {
+                let diagnostic = Diagnostic::error(
+                    "E0209",
+                    format!("cannot find constant `{}` in `{}`", name, scope_name),
+                )
+                .with_span(name.span)
+                .with_label(format!("not found in `{}`", scope_name));
+                let names = values.keys().map(|name| name.as_str());
+                Err(match closest_match(&name.name, names) {
+                    Some(similar) => diagnostic.with_suggestion(
+                        "a constant with a similar name exists",
+                        name.span,
+                        similar,
+                    ),
+                    None => diagnostic,
+                })
+            }
+        }
+    }
+}
+
+/// Finds the value of the constant `name`, which is in `scope` or the current class
+type Lookup<'a> = dyn FnMut(Option<&Identifier>, &Identifier) -> Result<u16, Diagnostic> + 'a;
+
+/// Computes a constant expression the way the VM would, using `lookup` for the constants it
+/// refers to. Like everywhere else in Jack, the operators are applied from left to right.
+fn evaluate(expression: &Expression, lookup: &mut Lookup) -> Result<u16, Diagnostic> {
+    let mut value = evaluate_term(&expression.term, expression.span, lookup)?;
+    for (op, term) in expression.ops.iter() {
+        let right = evaluate_term(term, expression.span, lookup)?;
+        let (left_signed, right_signed) = (value as i16, right as i16);
+        value = match op {
+            Op::Plus => value.wrapping_add(right),
+            Op::Minus => value.wrapping_sub(right),
+            Op::Asterix => left_signed.wrapping_mul(right_signed) as u16,
+            Op::Slash | Op::Percent if right == 0 => {
+                return Err(
+                    Diagnostic::error("E0210", "division by zero in constant expression")
+                        .with_span(expression.span),
+                );
+            }
+            Op::Slash => left_signed.wrapping_div(right_signed) as u16,
+            Op::Percent => left_signed.wrapping_rem(right_signed) as u16,
+            // Shifting by a negative amount does nothing, and by 16 or more leaves only zeros
+            Op::DoubleLessThan => match right_signed {
+                i16::MIN..=0 => value,
+                1..=15 => value << right,
+                _ => 0,
+            },
+            Op::DoubleGreaterThan => match right_signed {
+                i16::MIN..=0 => value,
+                1..=15 => value >> right,
+                _ => 0,
+            },
+            Op::Ampersand => value & right,
+            Op::VerticalBar => value | right,
+            Op::LessThan => boolean(left_signed < right_signed),
+            Op::GreaterThan => boolean(left_signed > right_signed),
+            Op::Equals => boolean(value == right),
+            Op::LessThanEquals => boolean(left_signed <= right_signed),
+            Op::GreaterThanEquals => boolean(left_signed >= right_signed),
+            Op::NotEquals => boolean(value != right),
+            // Like the code generator, these give `true` or `false` rather than either side
+            Op::DoubleAmpersand => boolean(value != 0 && right != 0),
+            Op::DoubleVerticalBar => boolean(value != 0 || right != 0),
+        };
+    }
+    Ok(value)
+}
+
+/// `span` is the span of the enclosing expression, used for error reporting
+fn evaluate_term(term: &Term, span: Span, lookup: &mut Lookup) -> Result<u16, Diagnostic> {
+    match &term.kind {
+        TermKind::IntegerConstant(int) => Ok(*int),
+        TermKind::KeywordConstant(KeywordConstant::True) => Ok(boolean(true)),
+        TermKind::KeywordConstant(KeywordConstant::False)
+        | TermKind::KeywordConstant(KeywordConstant::Null) => Ok(0),
+        TermKind::Expression(expression) => evaluate(expression, lookup),
+        TermKind::UnaryOpTerm((op, term)) => {
+            let value = evaluate_term(term, span, lookup)?;
+            Ok(match op {
+                UnaryOp::Minus => value.wrapping_neg(),
+                UnaryOp::Tilde => !value,
+            })
+        }
+        TermKind::VarName(name) => lookup(None, name),
+        TermKind::MemberAccess((scope, name)) => lookup(Some(scope), name),
+        TermKind::KeywordConstant(KeywordConstant::This)
+        | TermKind::KeywordConstant(KeywordConstant::Super)
+        | TermKind::StringConstant(_)
+        | TermKind::VarNameExpression(_)
+        | TermKind::SubroutineCall(_) => Err(Diagnostic::error(
+            "E0210",
+            "constant expression cannot be computed at compile time",
+        )
+        .with_span(span)
+        .with_note(
+            "constant expressions can only contain integer, character and keyword constants, \
+             other constants and operators",
+        )),
+    }
+}
+
+fn boolean(value: bool) -> u16 {
+    if value {
+        0xFFFF
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_primitive_constants_have_values() {
+        let class = parse_source(
+            "class A { const int X = 1; const char C = 'a'; const boolean B = true; \
+             const Array P = 1; const int Q = P + 1; }",
+        );
+        let constants = Constants::new(std::iter::once(&class));
+        assert_eq!(constants.get("A", "X"), Some(1));
+        assert_eq!(constants.get("A", "C"), Some(97));
+        assert_eq!(constants.get("A", "B"), Some(0xFFFF));
+        assert!(constants.contains("A", "P"));
+        assert_eq!(constants.get("A", "P"), None);
+        assert_eq!(constants.get("A", "Q"), None);
+    }
+}
@@ -1,5 +1,10 @@
 mod code_generator;
+mod constants;
+mod program;
 mod symbol_table;
 mod vm_writer;
 
-pub use code_generator::compile_class;
+pub use code_generator::{compile, compile_class, compile_in_program, CompiledClass};
+pub use constants::Constants;
+pub use program::Program;
+pub use symbol_table::{ClassSymbols, SubroutineSymbols, Symbol, VarKind};
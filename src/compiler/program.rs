@@ -0,0 +1,216 @@
+use super::constants::Constants;
+use crate::parser::*;
+use crate::{Extension, Extensions, Lints};
+use std::collections::{BTreeMap, HashMap};
+
+/// What the classes compiled together declare, for the extensions that let a class use
+/// more of another class than calling its subroutines, and the settings they are compiled with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    pub extensions: Extensions,
+    /// 0 compiles every expression as it is written. 1 computes the value of expressions made
+    /// only of constants at compile time.
+    pub opt_level: u8,
+    pub lints: Lints,
+    pub constants: Constants,
+    /// The class each class extends, if it extends one
+    parents: HashMap<String, Identifier>,
+    /// The fields of each class in the order of their indices, inherited fields first
+    fields: HashMap<String, Vec<(Identifier, VarType)>>,
+    /// The subroutines each class declares, with their return types (`None` for `void`)
+    subroutines: HashMap<String, BTreeMap<String, (SubroutineType, Option<VarType>)>>,
+}
+
+impl Program {
+    pub fn new<'ast>(
+        classes: impl IntoIterator<Item = &'ast Class>,
+        extensions: &Extensions,
+    ) -> Program {
+        let classes: Vec<&Class> = classes.into_iter().collect();
+        let parents = classes
+            .iter()
+            .filter_map(|class| {
+                let parent = class.superclass.clone()?;
+                Some((class.class_name.name.clone(), parent))
+            })
+            .collect();
+        let fields = classes
+            .iter()
+            .map(|class| {
+                let fields = class
+                    .class_var_declarations
+                    .iter()
+                    .filter(|declaration| declaration.static_or_field == StaticOrField::Field)
+                    .flat_map(|declaration| {
+                        declaration
+                            .var_names
+                            .iter()
+                            .map(move |name| (name.clone(), declaration.var_type.clone()))
+                    })
+                    .collect();
+                (class.class_name.name.clone(), fields)
+            })
+            .collect();
+        let subroutines = classes
+            .iter()
+            .map(|class| {
+                let subroutines = class
+                    .subroutine_declarations
+                    .iter()
+                    .map(|subroutine| {
+                        (
+                            subroutine.name.name.clone(),
+                            (subroutine.subroutine_type, subroutine.return_type.clone()),
+                        )
+                    })
+                    .collect();
+                (class.class_name.name.clone(), subroutines)
+            })
+            .collect();
+        let mut program = Program {
+            extensions: extensions.clone(),
+            opt_level: 0,
+            lints: Lints::new(),
+            constants: Constants::new(classes),
+            parents,
+            fields,
+            subroutines,
+        };
+        // Each class only knows its own fields so far
+        program.fields = program
+            .fields
+            .keys()
+            .map(|class_name| {
+                let mut layout = program.ancestors(class_name);
+                layout.reverse();
+                layout.push(class_name);
+                let fields = layout
+                    .into_iter()
+                    .flat_map(|name| program.fields[name].iter().cloned())
+                    .collect();
+                (class_name.clone(), fields)
+            })
+            .collect();
+        program
+    }
+
+    pub fn is_enabled(&self, extension: Extension) -> bool {
+        self.extensions.is_enabled(extension)
+    }
+
+    /// The class that a class extends
+    pub fn parent(&self, class_name: &str) -> Option<&Identifier> {
+        self.parents.get(class_name)
+    }
+
+    /// The classes in the program that a class inherits from, its parent first. When the
+    /// classes inherit from each other in a cycle, this stops before a class comes up again.
+    pub fn ancestors<'a>(&'a self, class_name: &'a str) -> Vec<&'a str> {
+        let mut ancestors = Vec::new();
+        let mut current = class_name;
+        while let Some(parent) = self.parents.get(current) {
+            let parent = parent.name.as_str();
+            if parent == class_name
+                || ancestors.contains(&parent)
+                || !self.subroutines.contains_key(parent)
+            {
+                break;
+            }
+            ancestors.push(parent);
+            current = parent;
+        }
+        ancestors
+    }
+
+    /// Whether a class is one of its own ancestors
+    pub fn inherits_from_itself(&self, class_name: &str) -> bool {
+        let last = self
+            .ancestors(class_name)
+            .last()
+            .copied()
+            .unwrap_or(class_name);
+        self.parents
+            .get(last)
+            .is_some_and(|parent| parent.name == class_name)
+    }
+
+    /// Whether a class is one of the classes compiled together
+    pub fn has_class(&self, class_name: &str) -> bool {
+        self.subroutines.contains_key(class_name)
+    }
+
+    /// The fields of a class in the program, in the order of their indices
+    pub fn fields(&self, class_name: &str) -> Option<&[(Identifier, VarType)]> {
+        self.fields.get(class_name).map(Vec::as_slice)
+    }
+
+    /// The index, declaration and type of a field of a class in the program
+    pub fn field(
+        &self,
+        class_name: &str,
+        field_name: &str,
+    ) -> Option<(u16, &Identifier, &VarType)> {
+        self.fields(class_name)?
+            .iter()
+            .enumerate()
+            .find(|(_, (name, _))| name.name == field_name)
+            .map(|(index, (name, var_type))| (index as u16, name, var_type))
+    }
+
+    /// The subroutines a class in the program declares itself, by name
+    pub fn subroutines(
+        &self,
+        class_name: &str,
+    ) -> impl Iterator<Item = (&str, SubroutineType, Option<&VarType>)> {
+        self.subroutines.get(class_name).into_iter().flatten().map(
+            |(name, (subroutine_type, return_type))| {
+                (name.as_str(), *subroutine_type, return_type.as_ref())
+            },
+        )
+    }
+
+    /// The class that declares the subroutine `class_name.subroutine_name` calls, which is the
+    /// class itself or the closest ancestor that declares it. Constructors aren't inherited.
+    pub fn subroutine_class<'a>(
+        &'a self,
+        class_name: &'a str,
+        subroutine_name: &str,
+    ) -> Option<&'a str> {
+        if self
+            .subroutines
+            .get(class_name)?
+            .contains_key(subroutine_name)
+        {
+            return Some(class_name);
+        }
+        self.ancestors(class_name).into_iter().find(|ancestor| {
+            matches!(
+                self.subroutines[*ancestor].get(subroutine_name),
+                Some((subroutine_type, _)) if *subroutine_type != SubroutineType::Constructor
+            )
+        })
+    }
+
+    /// The type and return type (`None` for `void`) of the subroutine that
+    /// `class_name.subroutine_name` calls, if it is declared in the program
+    pub fn subroutine(
+        &self,
+        class_name: &str,
+        subroutine_name: &str,
+    ) -> Option<(SubroutineType, Option<&VarType>)> {
+        let class_name = self.subroutine_class(class_name, subroutine_name)?;
+        let (subroutine_type, return_type) = &self.subroutines[class_name][subroutine_name];
+        Some((*subroutine_type, return_type.as_ref()))
+    }
+
+    /// The classes in the program that declare a subroutine named `subroutine_name`
+    pub fn classes_declaring<'a>(
+        &'a self,
+        subroutine_name: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.subroutines
+            .iter()
+            .filter(move |(_, subroutines)| subroutines.contains_key(subroutine_name))
+            .map(|(class_name, _)| class_name.as_str())
+    }
+}
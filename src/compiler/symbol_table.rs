@@ -1,7 +1,10 @@
+use crate::diagnostics::Span;
 pub use crate::parser::VarType;
+use serde::Serialize;
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum VarKind {
     Static,
     Field,
@@ -14,6 +17,32 @@ pub struct SymbolEntry {
     pub symbol_type: VarType,
     pub kind: VarKind,
     pub index: u16,
+    /// Where the symbol was declared, if it was declared explicitly
+    pub span: Option<Span>,
+}
+
+/// A symbol as it is written to the `symbols` output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub symbol_type: String,
+    pub kind: VarKind,
+    pub index: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubroutineSymbols {
+    pub name: String,
+    pub symbols: Vec<Symbol>,
+}
+
+/// Every symbol defined in a class, in the order they are numbered
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClassSymbols {
+    pub class_name: String,
+    pub symbols: Vec<Symbol>,
+    pub subroutines: Vec<SubroutineSymbols>,
 }
 
 #[derive(Debug)]
@@ -44,7 +73,13 @@ impl SymbolTable {
         self.num_vars = 0;
     }
 
-    pub fn define(&mut self, name: String, symbol_type: VarType, kind: VarKind) {
+    pub fn define(
+        &mut self,
+        name: String,
+        symbol_type: VarType,
+        kind: VarKind,
+        span: Option<Span>,
+    ) {
         match kind {
             VarKind::Static => {
                 self.class_symbols.insert(
@@ -53,6 +88,7 @@ impl SymbolTable {
                         symbol_type,
                         kind,
                         index: self.num_statics,
+                        span,
                     },
                 );
                 self.num_statics += 1;
@@ -64,6 +100,7 @@ impl SymbolTable {
                         symbol_type,
                         kind,
                         index: self.num_fields,
+                        span,
                     },
                 );
                 self.num_fields += 1;
@@ -75,6 +112,7 @@ impl SymbolTable {
                         symbol_type,
                         kind,
                         index: self.num_args,
+                        span,
                     },
                 );
                 self.num_args += 1;
@@ -86,6 +124,7 @@ impl SymbolTable {
                         symbol_type,
                         kind,
                         index: self.num_vars,
+                        span,
                     },
                 );
                 self.num_vars += 1;
@@ -102,9 +141,41 @@ impl SymbolTable {
         }
     }
 
+    /// Names of all the symbols currently in scope
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.subroutine_symbols
+            .keys()
+            .chain(self.class_symbols.keys())
+            .map(|name| name.as_str())
+    }
+
     pub fn get(&self, name: &str) -> Option<&SymbolEntry> {
         self.subroutine_symbols
             .get(name)
             .or_else(|| self.class_symbols.get(name))
     }
+
+    /// The statics and fields of the class
+    pub fn class_symbols(&self) -> Vec<Symbol> {
+        symbols(&self.class_symbols)
+    }
+
+    /// The arguments and local variables of the current subroutine
+    pub fn subroutine_symbols(&self) -> Vec<Symbol> {
+        symbols(&self.subroutine_symbols)
+    }
+}
+
+fn symbols(entries: &HashMap<String, SymbolEntry>) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = entries
+        .iter()
+        .map(|(name, entry)| Symbol {
+            name: name.clone(),
+            symbol_type: entry.symbol_type.to_string(),
+            kind: entry.kind,
+            index: entry.index,
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| (symbol.kind, symbol.index));
+    symbols
 }
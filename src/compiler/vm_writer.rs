@@ -1,6 +1,5 @@
 use super::symbol_table::VarKind;
-use std::iter::IntoIterator;
-use std::vec::IntoIter;
+use crate::diagnostics::Span;
 
 pub enum Segment {
     Const,
@@ -66,56 +65,70 @@ impl AsRef<str> for ArithmeticCommand {
         }
     }
 }
-pub struct VmWriter(Vec<String>);
+/// Collects VM commands along with the source span each one was generated from
+pub struct VmWriter {
+    commands: Vec<String>,
+    spans: Vec<Option<Span>>,
+    span: Option<Span>,
+}
 
 impl VmWriter {
     pub fn new() -> VmWriter {
-        VmWriter(Vec::new())
+        VmWriter {
+            commands: Vec::new(),
+            spans: Vec::new(),
+            span: None,
+        }
+    }
+
+    /// Sets the span recorded for the commands written after this
+    pub fn set_span(&mut self, span: Option<Span>) {
+        self.span = span;
+    }
+
+    /// Returns the commands and the span of each one
+    pub fn finish(self) -> (Vec<String>, Vec<Option<Span>>) {
+        (self.commands, self.spans)
+    }
+
+    fn write(&mut self, command: String) {
+        self.commands.push(command);
+        self.spans.push(self.span);
     }
 
     pub fn write_push(&mut self, segment: Segment, index: u16) {
-        self.0.push(format!("push {} {}", segment.as_ref(), index));
+        self.write(format!("push {} {}", segment.as_ref(), index));
     }
 
     pub fn write_pop(&mut self, segment: Segment, index: u16) {
-        self.0.push(format!("pop {} {}", segment.as_ref(), index));
+        self.write(format!("pop {} {}", segment.as_ref(), index));
     }
 
     pub fn write_arithmetic(&mut self, command: ArithmeticCommand) {
-        self.0.push(command.as_ref().to_string());
+        self.write(command.as_ref().to_string());
     }
 
     pub fn write_label(&mut self, label: &str) {
-        self.0.push(format!("label {}", label));
+        self.write(format!("label {}", label));
     }
 
     pub fn write_goto(&mut self, label: &str) {
-        self.0.push(format!("goto {}", label));
+        self.write(format!("goto {}", label));
     }
 
     pub fn write_if(&mut self, label: &str) {
-        self.0.push(format!("if-goto {}", label));
+        self.write(format!("if-goto {}", label));
     }
 
     pub fn write_call(&mut self, function_name: &str, num_args: u16) {
-        self.0.push(format!("call {} {}", function_name, num_args));
+        self.write(format!("call {} {}", function_name, num_args));
     }
 
     pub fn write_function(&mut self, function_name: &str, num_locals: u16) {
-        self.0
-            .push(format!("function {} {}", function_name, num_locals));
+        self.write(format!("function {} {}", function_name, num_locals));
     }
 
     pub fn write_return(&mut self) {
-        self.0.push("return".to_string())
-    }
-}
-
-impl IntoIterator for VmWriter {
-    type Item = String;
-    type IntoIter = IntoIter<String>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.write("return".to_string())
     }
 }
@@ -0,0 +1,104 @@
+use super::diagnostic::Diagnostic;
+use super::renderer::{ColorChoice, Renderer};
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    /// Source snippets for people to read
+    Human,
+    /// One JSON object per line, for tools to consume
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ErrorFormat, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("Invalid error format: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    file: &'a str,
+    #[serde(flatten)]
+    diagnostic: &'a Diagnostic,
+}
+
+/// Formats diagnostics in the format selected on the command line
+pub struct Emitter {
+    format: ErrorFormat,
+    renderer: Renderer,
+}
+
+impl Emitter {
+    pub fn new(format: ErrorFormat, color: ColorChoice) -> Emitter {
+        Emitter {
+            format,
+            renderer: Renderer::new(color),
+        }
+    }
+
+    /// The formatted diagnostic always ends with a newline
+    pub fn emit(&self, diagnostic: &Diagnostic, path: &str, source: &str) -> String {
+        match self.format {
+            ErrorFormat::Human => self.renderer.render(diagnostic, path, source),
+            ErrorFormat::Json => {
+                let json = serde_json::to_string(&JsonDiagnostic {
+                    file: path,
+                    diagnostic,
+                })
+                .expect("Diagnostics can always be serialized");
+                format!("{}\n", json)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::tests::multi_label;
+    use serde_json::json;
+
+    #[test]
+    fn emits_json() {
+        let (diagnostic, source) = multi_label();
+        let json = Emitter::new(ErrorFormat::Json, ColorChoice::Never).emit(
+            &diagnostic,
+            "Main.jack",
+            source,
+        );
+        // One line per diagnostic
+        assert!(json.ends_with('\n'));
+        assert_eq!(json.lines().count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let span = |line, start, end| json!({"start": {"line": line, "column": start}, "end": {"line": line, "column": end}});
+        assert_eq!(
+            value,
+            json!({
+                "file": "Main.jack",
+                "severity": "error",
+                "code": "E0201",
+                "message": "cannot find subroutine `mian` in class `Main`",
+                "span": span(3, 17, 21),
+                "label": "not found in this class",
+                "related": [{
+                    "span": span(2, 19, 23),
+                    "message": "a subroutine with a similar name is declared here",
+                }],
+                "notes": ["subroutines are looked up in the class they are called on"],
+                "suggestions": [{
+                    "message": "a subroutine with a similar name exists",
+                    "span": span(3, 17, 21),
+                    "replacement": "main",
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,35 @@
+mod diagnostic;
+mod emitter;
+mod renderer;
+
+pub use diagnostic::*;
+pub use emitter::{Emitter, ErrorFormat};
+pub use renderer::{ColorChoice, Renderer};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A diagnostic with a primary and a secondary label on different lines, a note and a
+    /// suggestion, and the source it is about
+    pub fn multi_label() -> (Diagnostic, &'static str) {
+        let span =
+            |line, start, end| Span::new(Position::new(line, start), Position::new(line, end));
+        let source = "class Main {\n    function void main() {\n        do Main.mian();\n        return;\n    }\n}\n";
+        let diagnostic =
+            Diagnostic::error("E0201", "cannot find subroutine `mian` in class `Main`")
+                .with_span(span(3, 17, 21))
+                .with_label("not found in this class")
+                .with_secondary(
+                    span(2, 19, 23),
+                    "a subroutine with a similar name is declared here",
+                )
+                .with_note("subroutines are looked up in the class they are called on")
+                .with_suggestion(
+                    "a subroutine with a similar name exists",
+                    span(3, 17, 21),
+                    "main",
+                );
+        (diagnostic, source)
+    }
+}
@@ -0,0 +1,229 @@
+use super::diagnostic::{Diagnostic, Severity, Span};
+use std::cmp;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+const TAB_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn use_color(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // Diagnostics are written to stderr, so only color them if that is a terminal
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none()
+                    && env::var("TERM").map(|term| term != "dumb").unwrap_or(true)
+                    && io::stderr().is_terminal()
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorChoice, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Invalid color choice: {}", s)),
+        }
+    }
+}
+
+/// Formats diagnostics for humans, in the style of rustc
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: ColorChoice) -> Renderer {
+        Renderer {
+            color: color.use_color(),
+        }
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic, path: &str, source: &str) -> String {
+        let severity_style = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut output = format!(
+            "{}{}\n",
+            self.paint(
+                severity_style,
+                &format!("{}[{}]", diagnostic.severity.as_ref(), diagnostic.code)
+            ),
+            self.paint(BOLD, &format!(": {}", diagnostic.message))
+        );
+
+        // (span, label, is primary)
+        let mut annotations: Vec<(Span, Option<&str>, bool)> = Vec::new();
+        if let Some(span) = diagnostic.span {
+            annotations.push((span, diagnostic.label.as_deref(), true));
+        }
+        for label in diagnostic.labels.iter() {
+            annotations.push((label.span, Some(&label.message), false));
+        }
+        // Sorting is stable, so the primary span comes first on its line
+        annotations.sort_by_key(|(span, _, _)| span.start.line);
+
+        let max_line = annotations
+            .iter()
+            .map(|(span, _, _)| span.start.line)
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(cmp::max(max_line.to_string().len(), 1));
+        let bar = self.paint(BLUE, "|");
+
+        let location = match diagnostic.span {
+            Some(span) => format!("{}:{}:{}", path, span.start.line, span.start.column),
+            None => path.to_string(),
+        };
+        output.push_str(&format!(
+            "{}{} {}\n",
+            gutter,
+            self.paint(BLUE, "-->"),
+            location
+        ));
+
+        if !annotations.is_empty() {
+            let lines: Vec<&str> = source.lines().collect();
+            output.push_str(&format!("{} {}\n", gutter, bar));
+
+            let mut previous_line = None;
+            for (span, label, is_primary) in annotations {
+                let line_number = span.start.line;
+                let line = lines.get(line_number.wrapping_sub(1)).unwrap_or(&"");
+
+                if previous_line != Some(line_number) {
+                    if let Some(previous_line) = previous_line {
+                        if line_number > previous_line + 1 {
+                            output.push_str(&format!("{}\n", self.paint(BLUE, "...")));
+                        }
+                    }
+                    output.push_str(&format!(
+                        "{}{} {}\n",
+                        self.paint(BLUE, &format!("{:>1$} ", line_number, gutter.len())),
+                        bar,
+                        expand_tabs(line)
+                    ));
+                    previous_line = Some(line_number);
+                }
+
+                // Spans that continue onto later lines are underlined to the end of the first line
+                let start = display_column(line, span.start.column);
+                let end = if span.end.line == span.start.line {
+                    display_column(line, span.end.column)
+                } else {
+                    display_column(line, line.chars().count() + 1)
+                };
+                let marker = if is_primary { "^" } else { "-" };
+                let mut underline = marker.repeat(cmp::max(end.saturating_sub(start), 1));
+                if let Some(label) = label {
+                    underline.push(' ');
+                    underline.push_str(label);
+                }
+                output.push_str(&format!(
+                    "{} {} {}{}\n",
+                    gutter,
+                    bar,
+                    " ".repeat(start.saturating_sub(1)),
+                    self.paint(if is_primary { severity_style } else { BLUE }, &underline)
+                ));
+            }
+        }
+
+        if !diagnostic.notes.is_empty() || !diagnostic.help.is_empty() {
+            output.push_str(&format!("{} {}\n", gutter, bar));
+        }
+        for note in diagnostic.notes.iter() {
+            output.push_str(&format!(
+                "{} {} {}: {}\n",
+                gutter,
+                self.paint(BLUE, "="),
+                self.paint(BOLD, "note"),
+                note
+            ));
+        }
+        for help in diagnostic.help.iter() {
+            let message = match &help.replacement {
+                Some(replacement) => format!("{}: `{}`", help.message, replacement),
+                None => help.message.to_string(),
+            };
+            output.push_str(&format!(
+                "{} {} {}: {}\n",
+                gutter,
+                self.paint(BLUE, "="),
+                self.paint(BOLD, "help"),
+                message
+            ));
+        }
+
+        output
+    }
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+/// Converts a 1-based character column into the 1-based column it is displayed at
+fn display_column(line: &str, column: usize) -> usize {
+    1 + line
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::tests::multi_label;
+
+    #[test]
+    fn renders_multiple_labels() {
+        let (diagnostic, source) = multi_label();
+        let rendered = Renderer::new(ColorChoice::Never).render(&diagnostic, "Main.jack", source);
+        assert_eq!(
+            rendered,
+            "\
+error[E0201]: cannot find subroutine `mian` in class `Main`
+ --> Main.jack:3:17
+  |
+2 |     function void main() {
+  |                   ---- a subroutine with a similar name is declared here
+3 |         do Main.mian();
+  |                 ^^^^ not found in this class
+  |
+  = note: subroutines are looked up in the class they are called on
+  = help: a subroutine with a similar name exists: `main`
+"
+        );
+    }
+}
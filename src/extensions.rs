@@ -0,0 +1,153 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+/// Syntax beyond the Jack language spec, which is only accepted when it is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Extension {
+    /// `else if (...) { }` chains
+    ElseIf,
+    /// `for (init; condition; update) { }` loops, and `break` and `continue` in any loop
+    ForLoops,
+    /// `<=`, `>=` and `!=`, and `&&` and `||`, which only evaluate their right side when needed
+    Operators,
+    /// `let x += e;` and the same for `-=`, `*=`, `/=`, `&=` and `|=`
+    CompoundAssignment,
+    /// `'a'` character constants, `0x7FFF` and `0b1010` integer constants,
+    /// and `\n`, `\\`, `\"` and `\'` in string constants
+    Literals,
+    /// `const int NAME = 1;` and `enum Dir { Up, Down }` declarations, whose values are
+    /// known at compile time
+    Constants,
+    /// `%`, and `<<` and `>>`, which shift in zeros
+    ArithmeticOperators,
+    /// `obj.field` and `let obj.field = e;` for the fields of other objects
+    FieldAccess,
+    /// Calls on the value of any term whose class is known, like `a.b().c()` or `this.run()`.
+    /// Array elements have no class, so `a[i].run()` is not supported.
+    CallChains,
+    /// `class B extends A`, where `B` gets the fields and subroutines of `A`,
+    /// and `super.m()` calls the `m` of `A`
+    Inheritance,
+}
+
+impl Extension {
+    pub const NAMES: &'static [&'static str] = &[
+        "else-if",
+        "for-loops",
+        "operators",
+        "compound-assignment",
+        "literals",
+        "constants",
+        "arithmetic-operators",
+        "field-access",
+        "call-chains",
+        "inheritance",
+    ];
+
+    /// Whether compiling a class with this extension needs the declarations of the other
+    /// classes in the program
+    pub fn needs_program(self) -> bool {
+        matches!(
+            self,
+            Extension::Constants
+                | Extension::FieldAccess
+                | Extension::CallChains
+                | Extension::Inheritance
+        )
+    }
+}
+
+impl AsRef<str> for Extension {
+    fn as_ref(&self) -> &str {
+        match self {
+            Extension::ElseIf => "else-if",
+            Extension::ForLoops => "for-loops",
+            Extension::Operators => "operators",
+            Extension::CompoundAssignment => "compound-assignment",
+            Extension::Literals => "literals",
+            Extension::Constants => "constants",
+            Extension::ArithmeticOperators => "arithmetic-operators",
+            Extension::FieldAccess => "field-access",
+            Extension::CallChains => "call-chains",
+            Extension::Inheritance => "inheritance",
+        }
+    }
+}
+
+impl FromStr for Extension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Extension, Self::Err> {
+        match s {
+            "else-if" => Ok(Extension::ElseIf),
+            "for-loops" => Ok(Extension::ForLoops),
+            "operators" => Ok(Extension::Operators),
+            "compound-assignment" => Ok(Extension::CompoundAssignment),
+            "literals" => Ok(Extension::Literals),
+            "constants" => Ok(Extension::Constants),
+            "arithmetic-operators" => Ok(Extension::ArithmeticOperators),
+            "field-access" => Ok(Extension::FieldAccess),
+            "call-chains" => Ok(Extension::CallChains),
+            "inheritance" => Ok(Extension::Inheritance),
+            _ => Err(format!("Invalid extension: {}", s)),
+        }
+    }
+}
+
+/// The extensions enabled for a compilation. None are enabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Extensions(BTreeSet<Extension>);
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    pub fn enable(&mut self, extension: Extension) {
+        self.0.insert(extension);
+    }
+
+    pub fn is_enabled(&self, extension: Extension) -> bool {
+        self.0.contains(&extension)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<Extension> for Extensions {
+    fn from_iter<I: IntoIterator<Item = Extension>>(extensions: I) -> Extensions {
+        Extensions(extensions.into_iter().collect())
+    }
+}
+
+impl Extend<Extension> for Extensions {
+    fn extend<I: IntoIterator<Item = Extension>>(&mut self, extensions: I) {
+        self.0.extend(extensions)
+    }
+}
+
+impl<'a> IntoIterator for &'a Extensions {
+    type Item = Extension;
+    type IntoIter = std::iter::Copied<std::collections::btree_set::Iter<'a, Extension>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter().copied()
+    }
+}
+
+/// Comma-separated names, like they are written for `--extensions`
+impl fmt::Display for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, extension) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(extension.as_ref())?;
+        }
+        Ok(())
+    }
+}
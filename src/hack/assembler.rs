@@ -0,0 +1,174 @@
+use crate::diagnostics::{Diagnostic, Position, Span};
+use std::collections::HashMap;
+
+/// Variables are allocated starting after the registers
+const FIRST_VARIABLE: u16 = 16;
+const MAX_ADDRESS: u16 = 32767;
+
+const PREDEFINED_SYMBOLS: &[(&str, u16)] = &[
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+/// Translates Hack assembly into Hack machine code, one 16-character binary string per instruction
+pub fn assemble(source: &str) -> Result<Vec<String>, Diagnostic> {
+    // (line number, span, instruction) with comments and whitespace removed
+    let mut instructions = Vec::new();
+    let mut symbols: HashMap<String, u16> = PREDEFINED_SYMBOLS
+        .iter()
+        .map(|(name, address)| (name.to_string(), *address))
+        .chain((0..16).map(|register| (format!("R{}", register), register)))
+        .collect();
+
+    // The first pass finds the address of each label
+    for (line_index, line) in source.lines().enumerate() {
+        let instruction: String = line
+            .split("//")
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        if instruction.is_empty() {
+            continue;
+        }
+        let start = line.chars().take_while(|c| c.is_whitespace()).count();
+        let span = Span::new(
+            Position::new(line_index + 1, start + 1),
+            Position::new(line_index + 1, line.trim_end().chars().count() + 1),
+        );
+        if instruction.starts_with('(') {
+            let label = instruction
+                .strip_prefix('(')
+                .and_then(|label| label.strip_suffix(')'))
+                .filter(|label| is_symbol(label))
+                .ok_or_else(|| error("invalid label", span))?;
+            let address = instructions.len() as u16;
+            if symbols.insert(label.to_string(), address).is_some() {
+                return Err(error(
+                    &format!("label `{}` is defined more than once", label),
+                    span,
+                ));
+            }
+        } else {
+            instructions.push((span, instruction));
+        }
+    }
+    if instructions.len() > usize::from(MAX_ADDRESS) + 1 {
+        return Err(Diagnostic::error(
+            "E0510",
+            format!(
+                "the program has {} instructions, but the ROM only has room for {}",
+                instructions.len(),
+                usize::from(MAX_ADDRESS) + 1
+            ),
+        ));
+    }
+
+    // The second pass translates the instructions and allocates variables
+    let mut next_variable = FIRST_VARIABLE;
+    instructions
+        .into_iter()
+        .map(|(span, instruction)| match instruction.strip_prefix('@') {
+            Some(value) => {
+                let address = if value.starts_with(|c: char| c.is_ascii_digit()) {
+                    value
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|address| *address <= MAX_ADDRESS)
+                        .ok_or_else(|| error(&format!("invalid address `{}`", value), span))?
+                } else if is_symbol(value) {
+                    *symbols.entry(value.to_string()).or_insert_with(|| {
+                        next_variable += 1;
+                        next_variable - 1
+                    })
+                } else {
+                    return Err(error(&format!("invalid symbol `{}`", value), span));
+                };
+                Ok(format!("{:016b}", address))
+            }
+            None => c_instruction(&instruction)
+                .ok_or_else(|| error(&format!("invalid instruction `{}`", instruction), span)),
+        })
+        .collect()
+}
+
+fn error(message: &str, span: Span) -> Diagnostic {
+    Diagnostic::error("E0510", message).with_span(span)
+}
+
+fn is_symbol(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && !symbol.starts_with(|c: char| c.is_ascii_digit())
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+/// Translates an instruction of the form `dest=comp;jump`
+fn c_instruction(instruction: &str) -> Option<String> {
+    let (dest, rest) = match instruction.find('=') {
+        Some(index) => (&instruction[..index], &instruction[index + 1..]),
+        None => ("", instruction),
+    };
+    let (comp, jump) = match rest.find(';') {
+        Some(index) => (&rest[..index], &rest[index + 1..]),
+        None => (rest, ""),
+    };
+
+    let comp = match comp {
+        "0" => "0101010",
+        "1" => "0111111",
+        "-1" => "0111010",
+        "D" => "0001100",
+        "A" => "0110000",
+        "M" => "1110000",
+        "!D" => "0001101",
+        "!A" => "0110001",
+        "!M" => "1110001",
+        "-D" => "0001111",
+        "-A" => "0110011",
+        "-M" => "1110011",
+        "D+1" => "0011111",
+        "A+1" => "0110111",
+        "M+1" => "1110111",
+        "D-1" => "0001110",
+        "A-1" => "0110010",
+        "M-1" => "1110010",
+        "D+A" | "A+D" => "0000010",
+        "D+M" | "M+D" => "1000010",
+        "D-A" => "0010011",
+        "D-M" => "1010011",
+        "A-D" => "0000111",
+        "M-D" => "1000111",
+        "D&A" | "A&D" => "0000000",
+        "D&M" | "M&D" => "1000000",
+        "D|A" | "A|D" => "0010101",
+        "D|M" | "M|D" => "1010101",
+        _ => return None,
+    };
+    if !dest.chars().all(|c| "AMD".contains(c)) {
+        return None;
+    }
+    let dest = ['A', 'D', 'M']
+        .iter()
+        .map(|register| if dest.contains(*register) { '1' } else { '0' })
+        .collect::<String>();
+    let jump = match jump {
+        "" => "000",
+        "JGT" => "001",
+        "JEQ" => "010",
+        "JGE" => "011",
+        "JLT" => "100",
+        "JNE" => "101",
+        "JLE" => "110",
+        "JMP" => "111",
+        _ => return None,
+    };
+    Some(format!("111{}{}{}", comp, dest, jump))
+}
@@ -0,0 +1,5 @@
+mod assembler;
+mod translator;
+
+pub use assembler::assemble;
+pub use translator::Translator;
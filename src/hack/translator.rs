@@ -0,0 +1,290 @@
+use crate::diagnostics::{Diagnostic, Position, Span};
+use std::collections::HashSet;
+
+/// Where the stack starts in RAM
+const STACK_BASE: u16 = 256;
+const TEMP_BASE: u16 = 5;
+
+/// Translates VM code into Hack assembly.
+///
+/// A program is translated by calling `translate_file` for each of its `.vm` files and then
+/// `finish`, which adds the bootstrap code that sets up the stack and calls `Sys.init`.
+#[derive(Debug, Default)]
+pub struct Translator {
+    output: Vec<String>,
+    label_count: usize,
+    file_name: String,
+    function_name: String,
+    functions: HashSet<String>,
+}
+
+impl Translator {
+    pub fn new() -> Translator {
+        Translator::default()
+    }
+
+    /// `file_name` is the name of the file without its extension, which is used to name its statics
+    pub fn translate_file(&mut self, file_name: &str, source: &str) -> Result<(), Diagnostic> {
+        self.file_name = file_name.to_string();
+        self.function_name = file_name.to_string();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let command = line.split("//").next().unwrap_or_default().trim();
+            if command.is_empty() {
+                continue;
+            }
+            let start = line.find(command).unwrap_or_default();
+            let span = Span::new(
+                Position::new(line_index + 1, line[..start].chars().count() + 1),
+                Position::new(
+                    line_index + 1,
+                    line[..start].chars().count() + command.chars().count() + 1,
+                ),
+            );
+            self.output.push(format!("// {}", command));
+            self.translate_command(command)
+                .map_err(|message| Diagnostic::error("E0500", message).with_span(span))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the assembly for the whole program
+    pub fn finish(self) -> Result<Vec<String>, Diagnostic> {
+        if !self.functions.contains("Sys.init") {
+            return Err(Diagnostic::error(
+                "E0501",
+                "the program does not define `Sys.init`",
+            )
+            .with_note("programs start by calling `Sys.init`, which is part of the operating system")
+            .with_help("add the OS `.vm` files to the output directory"));
+        }
+
+        let mut bootstrap = Translator::new();
+        bootstrap.function_name = "Sys.bootstrap".to_string();
+        bootstrap.push_lines(&[&format!("@{}", STACK_BASE), "D=A", "@SP", "M=D"]);
+        bootstrap.call("Sys.init", 0);
+        bootstrap.output.extend(self.output);
+        Ok(bootstrap.output)
+    }
+
+    /// Returns the assembly without the bootstrap code, for code that runs with the stack and
+    /// segments already set up, like the test scripts of the nand2tetris course do
+    pub fn finish_without_bootstrap(self) -> Vec<String> {
+        self.output
+    }
+
+    fn translate_command(&mut self, command: &str) -> Result<(), String> {
+        let parts: Vec<&str> = command.split_whitespace().collect();
+        let index = |position: usize| -> Result<u16, String> {
+            parts
+                .get(position)
+                .ok_or_else(|| format!("`{}` is missing an argument", parts[0]))?
+                .parse()
+                .map_err(|_| format!("invalid number in `{}`", command))
+        };
+        let name = |position: usize| -> Result<&str, String> {
+            parts
+                .get(position)
+                .copied()
+                .ok_or_else(|| format!("`{}` is missing an argument", parts[0]))
+        };
+        let expected_len = match parts[0] {
+            "push" | "pop" | "function" | "call" => 3,
+            "label" | "goto" | "if-goto" => 2,
+            "add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not" | "return" => 1,
+            other => return Err(format!("unknown VM command `{}`", other)),
+        };
+        if parts.len() > expected_len {
+            return Err(format!("too many arguments in `{}`", command));
+        }
+
+        match parts[0] {
+            "push" => self.push(name(1)?, index(2)?)?,
+            "pop" => self.pop(name(1)?, index(2)?)?,
+            "add" => self.binary("M=D+M"),
+            "sub" => self.binary("M=M-D"),
+            "and" => self.binary("M=D&M"),
+            "or" => self.binary("M=D|M"),
+            "neg" => self.push_lines(&["@SP", "A=M-1", "M=-M"]),
+            "not" => self.push_lines(&["@SP", "A=M-1", "M=!M"]),
+            "eq" => self.compare("JEQ"),
+            "gt" => self.compare("JGT"),
+            "lt" => self.compare("JLT"),
+            "label" => {
+                let label = self.label(name(1)?);
+                self.output.push(format!("({})", label));
+            }
+            "goto" => {
+                let label = self.label(name(1)?);
+                self.push_lines(&[&format!("@{}", label), "0;JMP"]);
+            }
+            "if-goto" => {
+                let label = self.label(name(1)?);
+                self.push_lines(&["@SP", "AM=M-1", "D=M", &format!("@{}", label), "D;JNE"]);
+            }
+            "function" => {
+                let function_name = name(1)?.to_string();
+                let num_locals = index(2)?;
+                if !self.functions.insert(function_name.clone()) {
+                    return Err(format!(
+                        "function `{}` is defined more than once",
+                        function_name
+                    ));
+                }
+                self.output.push(format!("({})", function_name));
+                for _ in 0..num_locals {
+                    self.push_lines(&["@SP", "A=M", "M=0", "@SP", "M=M+1"]);
+                }
+                self.function_name = function_name;
+            }
+            "call" => self.call(name(1)?, index(2)?),
+            "return" => self.push_lines(&[
+                // The frame starts at LCL and the return address is 5 words below it
+                "@LCL", "D=M", "@R13", "M=D", "@5", "A=D-A", "D=M", "@R14", "M=D",
+                // Move the return value to where the caller's stack ends and restore its frame
+                "@SP", "AM=M-1", "D=M", "@ARG", "A=M", "M=D", "@ARG", "D=M+1", "@SP", "M=D", "@R13",
+                "AM=M-1", "D=M", "@THAT", "M=D", "@R13", "AM=M-1", "D=M", "@THIS", "M=D", "@R13",
+                "AM=M-1", "D=M", "@ARG", "M=D", "@R13", "AM=M-1", "D=M", "@LCL", "M=D", "@R14",
+                "A=M", "0;JMP",
+            ]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn push_lines(&mut self, lines: &[&str]) {
+        self.output
+            .extend(lines.iter().map(|line| line.to_string()));
+    }
+
+    /// Pushes the D register
+    fn push_d(&mut self) {
+        self.push_lines(&["@SP", "A=M", "M=D", "@SP", "M=M+1"]);
+    }
+
+    /// The address of a fixed segment entry, or None for segments based on a pointer
+    fn address(&self, segment: &str, index: u16) -> Result<Option<String>, String> {
+        let address = match segment {
+            "static" => format!("{}.{}", self.file_name, index),
+            "temp" if index < 8 => format!("R{}", TEMP_BASE + index),
+            "pointer" if index < 2 => (if index == 0 { "THIS" } else { "THAT" }).to_string(),
+            "temp" | "pointer" => {
+                return Err(format!("index {} is out of range for `{}`", index, segment))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(address))
+    }
+
+    fn base(segment: &str) -> Result<&'static str, String> {
+        match segment {
+            "local" => Ok("LCL"),
+            "argument" => Ok("ARG"),
+            "this" => Ok("THIS"),
+            "that" => Ok("THAT"),
+            _ => Err(format!("unknown segment `{}`", segment)),
+        }
+    }
+
+    fn push(&mut self, segment: &str, index: u16) -> Result<(), String> {
+        if segment == "constant" {
+            self.push_lines(&[&format!("@{}", index), "D=A"]);
+        } else if let Some(address) = self.address(segment, index)? {
+            self.push_lines(&[&format!("@{}", address), "D=M"]);
+        } else {
+            let base = Translator::base(segment)?;
+            self.push_lines(&[
+                &format!("@{}", index),
+                "D=A",
+                &format!("@{}", base),
+                "A=D+M",
+                "D=M",
+            ]);
+        }
+        self.push_d();
+        Ok(())
+    }
+
+    fn pop(&mut self, segment: &str, index: u16) -> Result<(), String> {
+        if let Some(address) = self.address(segment, index)? {
+            self.push_lines(&["@SP", "AM=M-1", "D=M", &format!("@{}", address), "M=D"]);
+        } else {
+            let base = Translator::base(segment)
+                .map_err(|_| format!("cannot pop to segment `{}`", segment))?;
+            self.push_lines(&[
+                &format!("@{}", index),
+                "D=A",
+                &format!("@{}", base),
+                "D=D+M",
+                "@R13",
+                "M=D",
+                "@SP",
+                "AM=M-1",
+                "D=M",
+                "@R13",
+                "A=M",
+                "M=D",
+            ]);
+        }
+        Ok(())
+    }
+
+    /// Pops y into D and points A at x, then runs `operation`
+    fn binary(&mut self, operation: &str) {
+        self.push_lines(&["@SP", "AM=M-1", "D=M", "A=A-1", operation]);
+    }
+
+    fn compare(&mut self, jump: &str) {
+        self.label_count += 1;
+        let is_true = format!("{}$compare.{}.true", self.function_name, self.label_count);
+        let end = format!("{}$compare.{}.end", self.function_name, self.label_count);
+        self.binary("D=M-D");
+        self.push_lines(&[
+            &format!("@{}", is_true),
+            &format!("D;{}", jump),
+            "@SP",
+            "A=M-1",
+            "M=0",
+            &format!("@{}", end),
+            "0;JMP",
+            &format!("({})", is_true),
+            "@SP",
+            "A=M-1",
+            "M=-1",
+            &format!("({})", end),
+        ]);
+    }
+
+    fn call(&mut self, function_name: &str, num_args: u16) {
+        self.label_count += 1;
+        let return_label = format!("{}$ret.{}", self.function_name, self.label_count);
+        self.push_lines(&[&format!("@{}", return_label), "D=A"]);
+        self.push_d();
+        for pointer in &["LCL", "ARG", "THIS", "THAT"] {
+            self.push_lines(&[&format!("@{}", pointer), "D=M"]);
+            self.push_d();
+        }
+        self.push_lines(&[
+            // ARG = SP - 5 - num_args
+            "@SP",
+            "D=M",
+            &format!("@{}", 5 + u32::from(num_args)),
+            "D=D-A",
+            "@ARG",
+            "M=D",
+            // LCL = SP
+            "@SP",
+            "D=M",
+            "@LCL",
+            "M=D",
+            &format!("@{}", function_name),
+            "0;JMP",
+            &format!("({})", return_label),
+        ]);
+    }
+
+    /// Labels are scoped to the function they are declared in
+    fn label(&self, label: &str) -> String {
+        format!("{}${}", self.function_name, label)
+    }
+}
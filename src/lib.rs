@@ -1,6 +1,16 @@
+// Diagnostics are large, but they are only built on the error path
+#![allow(clippy::result_large_err)]
+
+mod extensions;
+mod lints;
 mod util;
 
-pub use util::ToXml;
+pub use extensions::{Extension, Extensions};
+pub use lints::{Lint, LintLevel, Lints};
+pub use util::{ToXml, XmlWriter};
 pub mod compiler;
+pub mod diagnostics;
+pub mod hack;
 pub mod parser;
+pub mod project;
 pub mod tokenizer;
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A check for code that compiles but is probably a mistake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    /// A local variable that is declared but never used. Allowed unless a manifest's `[lints]`
+    /// table sets it.
+    UnusedVariable,
+}
+
+impl Lint {
+    pub const NAMES: &'static [&'static str] = &["unused-variable"];
+
+    /// The code of the diagnostics the lint reports
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "W0001",
+        }
+    }
+
+    pub fn default_level(self) -> LintLevel {
+        match self {
+            Lint::UnusedVariable => LintLevel::Allow,
+        }
+    }
+}
+
+impl AsRef<str> for Lint {
+    fn as_ref(&self) -> &str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+        }
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Lint, Self::Err> {
+        match s {
+            "unused-variable" => Ok(Lint::UnusedVariable),
+            _ => Err(format!("Invalid lint: {}", s)),
+        }
+    }
+}
+
+/// What happens when a lint finds something
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl AsRef<str> for LintLevel {
+    fn as_ref(&self) -> &str {
+        match self {
+            LintLevel::Allow => "allow",
+            LintLevel::Warn => "warn",
+            LintLevel::Deny => "deny",
+        }
+    }
+}
+
+impl FromStr for LintLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LintLevel, Self::Err> {
+        match s {
+            "allow" => Ok(LintLevel::Allow),
+            "warn" => Ok(LintLevel::Warn),
+            "deny" => Ok(LintLevel::Deny),
+            _ => Err(format!("Invalid lint level: {}", s)),
+        }
+    }
+}
+
+/// The levels of the lints for a compilation. Lints that aren't set have their default level.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lints(BTreeMap<Lint, LintLevel>);
+
+impl Lints {
+    pub fn new() -> Lints {
+        Lints::default()
+    }
+
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.0.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.0
+            .get(&lint)
+            .copied()
+            .unwrap_or_else(|| lint.default_level())
+    }
+
+    /// Whether the level of a lint was set rather than left at its default
+    pub fn is_set(&self, lint: Lint) -> bool {
+        self.0.contains_key(&lint)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Comma-separated `name=level` pairs
+impl fmt::Display for Lints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (lint, level)) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", lint.as_ref(), level.as_ref())?;
+        }
+        Ok(())
+    }
+}
@@ -1,127 +1,1239 @@
-use clap::{App, Arg};
-use jack_compiler::{compiler::compile_class, parser::parse, tokenizer::tokenize, ToXml};
-use std::fs::{create_dir_all, File};
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::iter::Iterator;
-use std::path::Path;
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+use jack_compiler::{
+    compiler::{compile_in_program, CompiledClass, Program},
+    diagnostics::{ColorChoice, Diagnostic, Emitter, ErrorFormat, Span},
+    hack::{assemble, Translator},
+    parser::{from_json, parse_with_extensions, to_json, Class},
+    project::{
+        dependencies, find_files, find_sources, hash, signature, Artifact, Cache, CacheEntry,
+        Layout, Manifest, Pattern, MANIFEST_NAME,
+    },
+    tokenizer::tokenize_with_extensions,
+    Extension, Extensions, Lints, ToXml, XmlWriter,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, create_dir_all, read_to_string, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The process exit code. When several files fail, the highest one wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Success = 0,
+    CompileError = 1,
+    UsageError = 2,
+    IoError = 3,
+}
+
+struct Options {
+    /// The artifacts to write, without duplicates
+    emit: Vec<Artifact>,
+    use_cache: bool,
+    /// How many files to compile at once
+    jobs: usize,
+    extensions: Extensions,
+    opt_level: u8,
+    lints: Lints,
+}
+
+/// The status and formatted diagnostic for a file that couldn't be compiled
+type Failure = (Status, String);
+
+enum Outcome {
+    /// The outputs are already up to date with this source
+    Unchanged(String),
+    /// The source's hash and the result of compiling it
+    Compiled(u64, Result<Compiled, Failure>),
+}
+
+/// What the compiler learned about a class that compiled successfully
+struct Compiled {
+    class_name: String,
+    signature: u64,
+    dependencies: BTreeSet<String>,
+    outputs: Vec<PathBuf>,
+    vm: Vec<String>,
+    /// The warnings found while compiling it, already formatted
+    warnings: String,
+}
+
+/// The outputs of each source compiled by an earlier build in this process. Watch mode uses
+/// them to remove the outputs of deleted sources, which the cache only knows when it is used.
+type PreviousOutputs = BTreeMap<PathBuf, Vec<PathBuf>>;
+
+/// The VM code of the classes compiled into each output directory, by file name
+type Programs = BTreeMap<PathBuf, BTreeMap<String, Vec<String>>>;
 
 fn main() {
     let matches = App::new("jackc")
         .about("Jack compiler")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .after_help(
+            "EXIT STATUS:
+    0  All files compiled successfully
+    1  At least one file had compile errors
+    2  Invalid command line arguments or project manifest
+    3  A file or directory could not be read or written",
+        )
         .arg(
             Arg::with_name("input_path")
                 .index(1)
-                .help("Jack file or directory with jack files to compile")
+                .help("Jack file or directory to search for jack files to compile, or - to compile stdin to stdout")
                 .required(true),
         )
         .arg(
             Arg::with_name("tokenize")
                 .short("t")
                 .long("tokenize")
-                .help("Output an XML file with the unparsed input tokens"),
+                .global(true)
+                .help("Output an XML file with the unparsed input tokens (the same as adding `--emit tokens-xml`)"),
         )
         .arg(
             Arg::with_name("parse")
                 .short("p")
                 .long("parse")
-                .help("Output an XML file with the parsed program"),
+                .global(true)
+                .help("Output an XML file with the parsed program (the same as adding `--emit ast-xml`)"),
+        )
+        .arg(
+            Arg::with_name("emit")
+                .long("emit")
+                .global(true)
+                .help("Comma-separated list of artifacts to write (defaults to vm, or the manifest's target for `build`)")
+                .takes_value(true)
+                .multiple(true)
+                .require_delimiter(true)
+                .possible_values(Artifact::NAMES),
         )
         .arg(
             Arg::with_name("output_dir")
                 .short("o")
-                .long("output_dir")
-                .help("Specify the output directory for the compiled files")
+                .long("emit-dir")
+                .alias("output_dir")
+                .global(true)
+                .help("Directory to write the artifacts to (defaults to the input directory, or the manifest's output for `build`)")
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("extensions")
+                .long("extensions")
+                .global(true)
+                .help("Comma-separated list of language extensions to enable (added to the manifest's extensions for `build`)")
+                .takes_value(true)
+                .multiple(true)
+                .require_delimiter(true)
+                .possible_values(Extension::NAMES),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .help("Whether to mirror the input directories in the output directory or write every file into it directly")
+                .takes_value(true)
+                .possible_values(&["mirror", "flat"])
+                .default_value("mirror"),
+        )
+        .arg(
+            Arg::with_name("input_format")
+                .long("input-format")
+                .help("What stdin contains: Jack source, or an AST written by `--emit ast-json`")
+                .takes_value(true)
+                .possible_values(&["jack", "ast-json"])
+                .default_value("jack"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .help("Skip files and directories matching this glob (can be repeated)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|pattern| Pattern::from_str(&pattern).map(|_| ())),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .global(true)
+                .help("Whether to color diagnostics")
+                .takes_value(true)
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::with_name("error_format")
+                .long("error-format")
+                .global(true)
+                .help("How to print errors")
+                .takes_value(true)
+                .possible_values(&["human", "json"])
+                .default_value("human"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .global(true)
+                .help("Keep running and recompile whenever a source file changes"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .global(true)
+                .help("Number of files to compile in parallel (defaults to the number of CPUs)")
+                .takes_value(true)
+                .validator(|jobs| match jobs.parse::<usize>() {
+                    Ok(jobs) if jobs > 0 => Ok(()),
+                    _ => Err("must be a positive number".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("no_cache")
+                .long("no-cache")
+                .global(true)
+                .help("Recompile every file instead of skipping the ones that haven't changed. Without it, successful builds save a `.jackc-cache.json` file in the output directory (which is the input directory unless `-o` is given)"),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Build the project described by a Jack.toml manifest")
+                .arg(
+                    Arg::with_name("manifest_path")
+                        .long("manifest-path")
+                        .help("Path to the manifest (defaults to the nearest Jack.toml in the current directory or its parents)")
+                        .takes_value(true),
+                ),
+        )
+        .get_matches_safe()
+        .unwrap_or_else(|error| match error.kind {
+            // Help and version output exit successfully
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => error.exit(),
+            _ => {
+                eprintln!("{}", error.message);
+                process::exit(Status::UsageError as i32)
+            }
+        });
+
+    let status = match matches.subcommand_matches("build") {
+        None if matches.value_of("input_path") == Some("-") => compile_stdin(&matches),
+        None if matches.value_of("input_format") == Some("ast-json") => {
+            let diagnostic = Diagnostic::error(
+                "E0302",
+                "`--input-format ast-json` can only be used with stdin",
+            )
+            .with_help("pass `-` as the input path and pipe the JSON AST in");
+            let path = matches.value_of("input_path").unwrap();
+            eprint!("{}", emitter(&matches).emit(&diagnostic, path, ""));
+            Status::UsageError
+        }
+        Some(matches) if matches.is_present("watch") => watch(
+            || build_watch_paths(matches),
+            |previous| build(matches, previous),
+        ),
+        Some(matches) => build(matches, &mut PreviousOutputs::new()),
+        None if matches.is_present("watch") => watch(
+            || vec![PathBuf::from(matches.value_of("input_path").unwrap())],
+            |previous| run(&matches, previous),
+        ),
+        None => run(&matches, &mut PreviousOutputs::new()),
+    };
+    process::exit(status as i32);
+}
+
+/// `default_emit` is used unless the artifacts are chosen with `--emit`.
+/// `--tokenize` and `--parse` add to the artifacts either way.
+fn options(matches: &ArgMatches, default_emit: Artifact) -> Options {
+    let mut emit: Vec<Artifact> = match matches.values_of("emit") {
+        Some(artifacts) => artifacts
+            .map(|artifact| Artifact::from_str(artifact).unwrap())
+            .collect(),
+        None => vec![default_emit],
+    };
+    if matches.is_present("tokenize") {
+        emit.push(Artifact::TokensXml);
+    }
+    if matches.is_present("parse") {
+        emit.push(Artifact::AstXml);
+    }
+    emit.sort();
+    emit.dedup();
+
+    Options {
+        emit,
+        use_cache: !matches.is_present("no_cache"),
+        jobs: match matches.value_of("jobs") {
+            Some(jobs) => jobs.parse().unwrap(),
+            None => thread::available_parallelism().map_or(1, usize::from),
+        },
+        extensions: matches
+            .values_of("extensions")
+            .map(|extensions| {
+                extensions
+                    .map(|extension| Extension::from_str(extension).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        opt_level: 0,
+        lints: Lints::new(),
+    }
+}
 
+fn emitter(matches: &ArgMatches) -> Emitter {
+    Emitter::new(
+        ErrorFormat::from_str(matches.value_of("error_format").unwrap()).unwrap(),
+        ColorChoice::from_str(matches.value_of("color").unwrap()).unwrap(),
+    )
+}
+
+fn run(matches: &ArgMatches, previous: &mut PreviousOutputs) -> Status {
+    let options = options(matches, Artifact::Vm);
+    let emitter = emitter(matches);
     let path = matches.value_of("input_path").unwrap();
-    let output_tokens = matches.is_present("tokenize");
-    let output_parsed = matches.is_present("parse");
-    let output_dir = Path::new(matches.value_of("output_dir").unwrap_or(path));
-    println!("output dir {}", output_dir.to_str().unwrap());
-    create_dir_all(output_dir).expect("Error creating output directory");
-
-    let path = Path::new(path);
-    let files = if path.is_dir() {
-        path.read_dir()
-            .expect(&format!(
-                "Cannot read directory: {}",
-                path.to_str().unwrap()
-            ))
-            .map(|dir_entry| dir_entry.unwrap().path())
-            .collect()
-    } else {
-        vec![path.to_owned()]
+    let input_root = Path::new(path);
+    let output_root = Path::new(matches.value_of("output_dir").unwrap_or(path));
+    let layout = Layout::from_str(matches.value_of("layout").unwrap()).unwrap();
+    let exclude: Vec<Pattern> = matches
+        .values_of("exclude")
+        .map(|patterns| patterns.map(Pattern::new).collect())
+        .unwrap_or_default();
+
+    println!("output dir {}", output_root.to_string_lossy());
+    if let Err(error) = create_dir_all(output_root) {
+        return report_io_error(
+            &emitter,
+            "cannot create output directory",
+            output_root,
+            error,
+        );
+    }
+
+    let files = match find_sources(input_root, &exclude) {
+        Ok(files) => files,
+        Err(error) => {
+            return report_io_error(&emitter, "cannot read directory", input_root, error);
+        }
     };
 
-    for file_path in files {
-        if file_path.extension().and_then(|s| s.to_str()) != Some("jack") {
-            continue;
+    if layout == Layout::Flat {
+        if let Some(diagnostic) = find_output_collision(&files) {
+            let diagnostic =
+                diagnostic.with_help("use `--layout mirror` or `--exclude` one of the files");
+            eprint!("{}", emitter.emit(&diagnostic, path, ""));
+            return Status::UsageError;
         }
+    }
 
-        let file =
-            File::open(&file_path).expect(&format!("Cannot open file: {}", path.to_str().unwrap()));
-        let reader = BufReader::new(file);
-        let lines = reader.lines().map(|line| line.expect("Error reading line"));
+    let jobs: Vec<(PathBuf, PathBuf)> = files
+        .into_iter()
+        .map(|file_path| {
+            let output_dir = layout.output_dir(input_root, output_root, &file_path);
+            (file_path, output_dir)
+        })
+        .collect();
+    compile_all(&jobs, output_root, &options, &emitter, previous)
+}
 
-        let mut output_tokens_file = if output_tokens {
-            let output_path = &output_dir.join(format!(
-                "{}T.xml",
-                file_path.file_stem().and_then(|p| p.to_str()).unwrap()
+/// Builds every source and library in a project into a single output directory,
+/// which is the layout the VM emulator expects
+fn build(matches: &ArgMatches, previous: &mut PreviousOutputs) -> Status {
+    let emitter = emitter(matches);
+    let manifest_path = match find_manifest(matches) {
+        Some(path) => path,
+        None => {
+            let diagnostic = Diagnostic::error(
+                "E0403",
+                format!(
+                    "could not find `{}` in the current directory or any parent directory",
+                    MANIFEST_NAME
+                ),
+            );
+            eprint!("{}", emitter.emit(&diagnostic, MANIFEST_NAME, ""));
+            return Status::UsageError;
+        }
+    };
+
+    let source = match read_to_string(&manifest_path) {
+        Ok(source) => source,
+        Err(error) => {
+            return report_io_error(&emitter, "cannot read manifest", &manifest_path, error)
+        }
+    };
+    let root = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut manifest = match Manifest::parse(&source, root) {
+        Ok(manifest) => manifest,
+        Err(diagnostic) => {
+            let path = manifest_path.to_string_lossy();
+            eprint!("{}", emitter.emit(&diagnostic, &path, &source));
+            return Status::UsageError;
+        }
+    };
+    if let Some(output) = matches.value_of("output_dir") {
+        manifest.output = PathBuf::from(output);
+    }
+    let mut options = options(matches, manifest.target.into());
+    options.extensions.extend(&manifest.extensions);
+    options.opt_level = manifest.opt_level;
+    options.lints = manifest.lints.clone();
+
+    let mut sources = Vec::new();
+    let mut libraries = Vec::new();
+    for (dir, is_library) in manifest
+        .sources
+        .iter()
+        .map(|dir| (dir, false))
+        .chain(manifest.libraries.iter().map(|dir| (dir, true)))
+    {
+        let found = find_files(dir, "jack", &manifest.exclude).and_then(|found| {
+            sources.extend(found);
+            if is_library {
+                libraries.extend(find_files(dir, "vm", &manifest.exclude)?);
+            }
+            Ok(())
+        });
+        if let Err(error) = found {
+            return report_io_error(&emitter, "cannot read directory", dir, error);
+        }
+    }
+
+    let outputs: Vec<PathBuf> = sources.iter().chain(libraries.iter()).cloned().collect();
+    if let Some(diagnostic) = find_output_collision(&outputs) {
+        let path = manifest_path.to_string_lossy();
+        eprint!("{}", emitter.emit(&diagnostic, &path, &source));
+        return Status::UsageError;
+    }
+    if let Some(entry) = &manifest.entry {
+        if !sources
+            .iter()
+            .any(|path| path.file_stem().and_then(|s| s.to_str()) == Some(entry))
+        {
+            let diagnostic = Diagnostic::error(
+                "E0404",
+                format!("entry class `{}` is not defined by any source file", entry),
+            )
+            .with_help(format!(
+                "add a `{}.jack` file to the project sources",
+                entry
             ));
-            let output_file = File::create(output_path).expect("Unable to create file");
-            let mut writer = BufWriter::new(output_file);
-            write!(writer, "<tokens>\n").expect("Error writing to tokens file");
-            Some(writer)
+            let path = manifest_path.to_string_lossy();
+            eprint!("{}", emitter.emit(&diagnostic, &path, &source));
+            return Status::UsageError;
+        }
+    }
+
+    println!("output dir {}", manifest.output.to_string_lossy());
+    if let Err(error) = create_dir_all(&manifest.output) {
+        return report_io_error(
+            &emitter,
+            "cannot create output directory",
+            &manifest.output,
+            error,
+        );
+    }
+
+    // Libraries are copied first so they can be linked with the sources
+    let mut status = Status::Success;
+    for file_path in libraries {
+        let output_path = manifest
+            .output
+            .join(file_path.file_name().unwrap_or_default());
+        if let Err(error) = fs::copy(&file_path, &output_path) {
+            status = status.max(report_io_error(
+                &emitter,
+                "cannot copy library file",
+                &file_path,
+                error,
+            ));
+        }
+    }
+
+    let jobs: Vec<(PathBuf, PathBuf)> = sources
+        .into_iter()
+        .map(|file_path| (file_path, manifest.output.clone()))
+        .collect();
+    status.max(compile_all(
+        &jobs,
+        &manifest.output,
+        &options,
+        &emitter,
+        previous,
+    ))
+}
+
+fn find_manifest(matches: &ArgMatches) -> Option<PathBuf> {
+    match matches.value_of("manifest_path") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::current_dir().ok().and_then(|dir| Manifest::find(&dir)),
+    }
+}
+
+/// The manifest and every directory a project's sources can come from
+fn build_watch_paths(matches: &ArgMatches) -> Vec<PathBuf> {
+    let manifest_path = match find_manifest(matches) {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+    let root = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut paths = vec![manifest_path.clone(), root.to_path_buf()];
+    if let Some(manifest) = read_to_string(&manifest_path)
+        .ok()
+        .and_then(|source| Manifest::parse(&source, root).ok())
+    {
+        paths.extend(manifest.sources);
+        paths.extend(manifest.libraries);
+    }
+    paths
+}
+
+/// Runs `compile` and then runs it again every time one of the
+/// `.jack` files (or other files) in the watched paths changes
+fn watch(
+    paths: impl Fn() -> Vec<PathBuf>,
+    mut compile: impl FnMut(&mut PreviousOutputs) -> Status,
+) -> ! {
+    let mut last_snapshot = None;
+    let mut previous = PreviousOutputs::new();
+    loop {
+        let snapshot = snapshot(&paths());
+        if last_snapshot.as_ref() != Some(&snapshot) {
+            compile(&mut previous);
+            println!("watching for changes...");
+            last_snapshot = Some(snapshot);
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// The size and modification time of each watched file. Directories are searched
+/// for `.jack` files and any other path is watched as it is.
+fn snapshot(paths: &[PathBuf]) -> BTreeMap<PathBuf, (u64, Option<SystemTime>)> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(find_files(path, "jack", &[]).unwrap_or_default());
         } else {
-            None
+            files.push(path.clone());
+        }
+    }
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let metadata = fs::metadata(&file).ok()?;
+            Some((file, (metadata.len(), metadata.modified().ok())))
+        })
+        .collect()
+}
+
+/// Compiles each source file into the output directory paired with it.
+///
+/// Sources that haven't changed since the last build are skipped, unless they depend on a class
+/// whose signature changed. The cache that tracks this is kept in `cache_dir`.
+///
+/// Every source is compiled when a program level artifact like `asm` is requested,
+/// since those need the VM code of every class.
+fn compile_all(
+    jobs: &[(PathBuf, PathBuf)],
+    cache_dir: &Path,
+    options: &Options,
+    emitter: &Emitter,
+    previous: &mut PreviousOutputs,
+) -> Status {
+    let is_linked = options
+        .emit
+        .iter()
+        .any(|artifact| artifact.is_program_level());
+    let mut programs = Programs::new();
+    let programs_by_dir = program_declarations(jobs, options);
+    let no_program = new_program(None, options);
+    let program_for = |output_dir: &Path| programs_by_dir.get(output_dir).unwrap_or(&no_program);
+    let mut cache = if options.use_cache {
+        Cache::load(cache_dir)
+    } else {
+        Cache::default()
+    };
+    // Forget sources that are no longer part of the build,
+    // and delete the outputs of the ones that no longer exist
+    let mut status = Status::Success;
+    let removed: BTreeSet<PathBuf> = cache
+        .sources()
+        .chain(previous.keys().map(PathBuf::as_path))
+        .filter(|source| !jobs.iter().any(|(file_path, _)| file_path == source))
+        .map(Path::to_path_buf)
+        .collect();
+    for source in removed {
+        let mut outputs: BTreeSet<PathBuf> =
+            previous.remove(&source).into_iter().flatten().collect();
+        outputs.extend(
+            cache
+                .remove(&source)
+                .into_iter()
+                .flat_map(|entry| entry.outputs),
+        );
+        if source.exists() {
+            continue;
+        }
+        for output in outputs {
+            match fs::remove_file(&output) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    status = status.max(report_io_error(
+                        emitter,
+                        "cannot remove output of deleted source",
+                        &output,
+                        error,
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Diagnostics are printed as results are recorded, which happens in the order of `jobs`
+    let record = |cache: &mut Cache,
+                  programs: &mut Programs,
+                  file_path: &Path,
+                  output_dir: &Path,
+                  source_hash: u64,
+                  result: Result<Compiled, Failure>| match result {
+        Ok(mut compiled) => {
+            eprint!("{}", compiled.warnings);
+            if is_linked {
+                let file_stem = file_path.file_stem().unwrap_or_default();
+                programs
+                    .entry(output_dir.to_path_buf())
+                    .or_default()
+                    .insert(
+                        file_stem.to_string_lossy().into_owned(),
+                        mem::take(&mut compiled.vm),
+                    );
+            }
+            let entry = CacheEntry {
+                hash: source_hash,
+                class_name: compiled.class_name,
+                signature: compiled.signature,
+                // The signatures are filled in once every changed class has been compiled
+                dependencies: compiled
+                    .dependencies
+                    .into_iter()
+                    .map(|class_name| (class_name, 0))
+                    .collect(),
+                output_dir: output_dir.to_path_buf(),
+                outputs: compiled.outputs,
+                has_warnings: !compiled.warnings.is_empty(),
+            };
+            cache.insert(file_path.to_path_buf(), entry);
+            Status::Success
+        }
+        Err((file_status, diagnostic)) => {
+            eprint!("{}", diagnostic);
+            cache.remove(file_path);
+            file_status
+        }
+    };
+
+    // Keep going after a file fails so that every error is reported.
+    // First compile the sources that changed, then the ones that depend on a changed signature
+    let outcomes = parallel_map(jobs, options.jobs, |(file_path, output_dir)| {
+        let source = match read_to_string(file_path) {
+            Ok(source) => source,
+            Err(error) => {
+                let diagnostic = render_io_error(emitter, "cannot read file", file_path, error);
+                return Outcome::Compiled(0, Err((Status::IoError, diagnostic)));
+            }
         };
+        let source_hash = source_hash(&source, options);
+        let outputs_exist = requested_outputs(file_path, output_dir, options)
+            .iter()
+            .all(|output| output.is_file());
+        if !is_linked
+            && outputs_exist
+            && cache.is_fresh(file_path, source_hash, &Default::default())
+        {
+            Outcome::Unchanged(source)
+        } else {
+            let program = program_for(output_dir);
+            let result = compile_file(file_path, &source, output_dir, program, options, emitter);
+            Outcome::Compiled(source_hash, result)
+        }
+    });
 
-        let tokens = tokenize(lines).inspect(|token| {
-            if let Some(ref mut writer) = output_tokens_file {
-                write!(writer, "{}\n", token.to_xml()).expect("Error writing token to file");
+    let mut compiled = BTreeSet::new();
+    let mut unchanged = Vec::new();
+    for ((file_path, output_dir), outcome) in jobs.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Unchanged(source) => unchanged.push((file_path, output_dir, source)),
+            Outcome::Compiled(source_hash, result) => {
+                status = status.max(record(
+                    &mut cache,
+                    &mut programs,
+                    file_path,
+                    output_dir,
+                    source_hash,
+                    result,
+                ));
+                compiled.insert(file_path);
             }
+        }
+    }
+
+    // Compiling a dependent can change its own signature (a subclass's signature has the fields
+    // it inherits), so keep going until every class is up to date with the ones it depends on
+    loop {
+        let (dependents, rest): (Vec<_>, Vec<_>) =
+            unchanged
+                .into_iter()
+                .partition(|(file_path, output_dir, source)| {
+                    !cache.is_fresh(
+                        file_path,
+                        source_hash(source, options),
+                        &cache.signatures(output_dir),
+                    )
+                });
+        unchanged = rest;
+        if dependents.is_empty() {
+            break;
+        }
+        let results = parallel_map(
+            &dependents,
+            options.jobs,
+            |(file_path, output_dir, source)| {
+                let program = program_for(output_dir);
+                compile_file(file_path, source, output_dir, program, options, emitter)
+            },
+        );
+        for ((file_path, output_dir, source), result) in dependents.iter().zip(results) {
+            let source_hash = source_hash(source, options);
+            status = status.max(record(
+                &mut cache,
+                &mut programs,
+                file_path,
+                output_dir,
+                source_hash,
+                result,
+            ));
+            compiled.insert(file_path);
+        }
+    }
+
+    // Record the signatures that the newly compiled classes were compiled against
+    for file_path in compiled {
+        if let Some(mut entry) = cache.remove(file_path) {
+            let signatures = cache.signatures(&entry.output_dir);
+            entry.dependencies = entry
+                .dependencies
+                .into_keys()
+                .filter_map(|class_name| {
+                    let signature = *signatures.get(&class_name)?;
+                    Some((class_name, signature))
+                })
+                .collect();
+            cache.insert(file_path.clone(), entry);
+        }
+    }
+
+    // Remember the outputs for watch mode. A source that failed keeps the ones it had before,
+    // which are still on disk
+    for (file_path, _) in jobs {
+        if let Some(entry) = cache.get(file_path) {
+            previous.insert(file_path.clone(), entry.outputs.clone());
+        }
+    }
+
+    // A failed build leaves the previous cache, so nothing is skipped because of a partial build
+    if options.use_cache && status == Status::Success {
+        if let Err(error) = cache.save(cache_dir) {
+            status = status.max(report_io_error(
+                emitter,
+                "cannot write build cache",
+                cache_dir,
+                error,
+            ));
+        }
+    }
+
+    // Errors in the classes would be reported again while linking, so only link if they all compiled
+    if status == Status::Success {
+        for (output_dir, classes) in programs {
+            status = status.max(link(&output_dir, classes, options, emitter));
+        }
+    }
+    status
+}
+
+/// The declarations of the classes compiled into each output directory, which can be used by
+/// every class in the directory. Sources are parsed here even if they are up to date, and any
+/// errors are left to be reported when they are compiled.
+fn program_declarations(
+    jobs: &[(PathBuf, PathBuf)],
+    options: &Options,
+) -> BTreeMap<PathBuf, Program> {
+    if !options
+        .extensions
+        .into_iter()
+        .any(|extension| extension.needs_program())
+    {
+        return BTreeMap::new();
+    }
+    let classes = parallel_map(jobs, options.jobs, |(file_path, _)| {
+        let source = read_to_string(file_path).ok()?;
+        let tokens =
+            tokenize_with_extensions(source.lines().map(String::from), &options.extensions);
+        parse_with_extensions(tokens, &options.extensions).ok()
+    });
+    let mut classes_by_dir: BTreeMap<&Path, Vec<Class>> = BTreeMap::new();
+    for ((_, output_dir), class) in jobs.iter().zip(classes) {
+        if let Some(class) = class {
+            classes_by_dir.entry(output_dir).or_default().push(class);
+        }
+    }
+    classes_by_dir
+        .into_iter()
+        .map(|(output_dir, classes)| {
+            let program = new_program(classes.iter(), options);
+            (output_dir.to_path_buf(), program)
+        })
+        .collect()
+}
+
+/// A program made of `classes`, compiled with the settings in `options`
+fn new_program<'ast>(classes: impl IntoIterator<Item = &'ast Class>, options: &Options) -> Program {
+    let mut program = Program::new(classes, &options.extensions);
+    program.opt_level = options.opt_level;
+    program.lints = options.lints.clone();
+    program
+}
+
+/// Writes the program level artifacts for `output_dir`. The program is made of the classes that
+/// were just compiled plus any other `.vm` files already in the directory, like the OS.
+fn link(
+    output_dir: &Path,
+    classes: BTreeMap<String, Vec<String>>,
+    options: &Options,
+    emitter: &Emitter,
+) -> Status {
+    let entries = match fs::read_dir(output_dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            return report_io_error(emitter, "cannot read directory", output_dir, error);
+        }
+    };
+    let mut files = BTreeMap::new();
+    for entry in entries.flatten() {
+        let file_path = entry.path();
+        let file_stem = file_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if file_path.extension() != Some(OsStr::new("vm"))
+            || !file_path.is_file()
+            || classes.contains_key(&file_stem)
+        {
+            continue;
+        }
+        match read_to_string(&file_path) {
+            Ok(source) => files.insert(file_stem, (file_path, source)),
+            Err(error) => return report_io_error(emitter, "cannot read file", &file_path, error),
+        };
+    }
+    for (file_stem, vm) in classes {
+        let file_path = Artifact::Vm.output_path(Path::new(&file_stem), output_dir);
+        files.insert(file_stem, (file_path, vm.join("\n")));
+    }
+
+    let report = |diagnostic: Diagnostic, path: &Path, source: &str| {
+        eprint!(
+            "{}",
+            emitter.emit(&diagnostic, &path.to_string_lossy(), source)
+        );
+        Status::CompileError
+    };
+    let mut translator = Translator::new();
+    for (file_stem, (file_path, source)) in files.iter() {
+        if let Err(diagnostic) = translator.translate_file(file_stem, source) {
+            return report(diagnostic, file_path, source);
+        }
+    }
+    let asm = match translator.finish() {
+        Ok(asm) => asm,
+        Err(diagnostic) => return report(diagnostic, output_dir, ""),
+    };
+
+    let write = |artifact: Artifact, lines: &[String]| {
+        let output_path = artifact.output_path(output_dir, output_dir);
+        match File::create(&output_path)
+            .and_then(|output_file| write_lines(BufWriter::new(output_file), lines))
+        {
+            Ok(()) => Status::Success,
+            Err(error) => report_io_error(
+                emitter,
+                &format!("cannot write {} file", artifact.as_ref()),
+                &output_path,
+                error,
+            ),
+        }
+    };
+    let mut status = Status::Success;
+    if options.emit.contains(&Artifact::Asm) {
+        status = status.max(write(Artifact::Asm, &asm));
+    }
+    if options.emit.contains(&Artifact::Hack) {
+        let asm_path = Artifact::Asm.output_path(output_dir, output_dir);
+        status = status.max(match assemble(&asm.join("\n")) {
+            Ok(hack) => write(Artifact::Hack, &hack),
+            Err(diagnostic) => report(diagnostic, &asm_path, &asm.join("\n")),
         });
+    }
+    status
+}
+
+/// Compiles a single file. If it fails, the diagnostic is returned already
+/// formatted so that files compiled in parallel can be reported in order.
+fn compile_file(
+    file_path: &Path,
+    source: &str,
+    output_dir: &Path,
+    program: &Program,
+    options: &Options,
+    emitter: &Emitter,
+) -> Result<Compiled, Failure> {
+    let report_io_error = |message: &str, path: &Path, error| {
+        (
+            Status::IoError,
+            render_io_error(emitter, message, path, error),
+        )
+    };
+    let report = |diagnostic: Diagnostic| {
+        let path = file_path.to_string_lossy();
+        (
+            Status::CompileError,
+            emitter.emit(&diagnostic, &path, source),
+        )
+    };
+    create_dir_all(output_dir)
+        .map_err(|error| report_io_error("cannot create output directory", output_dir, error))?;
 
-        let class = parse(tokens).expect("Error compiling class");
-        if let Some(ref mut writer) = output_tokens_file {
-            write!(writer, "</tokens>").expect("Error writing to tokens file");
+    // Each artifact is written as soon as what it is made from is ready
+    let mut outputs = Vec::new();
+    let mut write = |artifact: Artifact,
+                     write: &dyn Fn(&mut Vec<u8>) -> io::Result<()>|
+     -> Result<(), Failure> {
+        if !options.emit.contains(&artifact) {
+            return Ok(());
         }
+        let output_path = artifact.output_path(file_path, output_dir);
+        // Written to memory first, so that an error doesn't leave half a file behind
+        let mut buffer = Vec::new();
+        write(&mut buffer)
+            .and_then(|()| fs::write(&output_path, buffer))
+            .map_err(|error| {
+                report_io_error(
+                    &format!("cannot write {} file", artifact.as_ref()),
+                    &output_path,
+                    error,
+                )
+            })?;
+        outputs.push(output_path);
+        Ok(())
+    };
 
-        if output_parsed {
-            let output_path = &output_dir.join(
-                file_path
-                    .with_extension("xml")
-                    .file_name()
-                    .and_then(|p| p.to_str())
-                    .unwrap(),
-            );
-            let mut output_file = File::create(output_path).expect("Unable to create file");
-            let output_string = class.to_xml();
-            // Remove empty lines
-            // (this is less efficient but simpler than ensuring we exactly
-            // match the spacing expected by the nand2tetris compare file)
-            let output_string = output_string
-                .split("\n")
-                .filter(|line| !line.chars().all(|c| c.is_whitespace()))
-                .collect::<Vec<&str>>()
-                .join("\n");
-            write!(output_file, "{}", output_string).expect("Error writing parsed tokens to file");
-        }
-
-        let vm_output_path = output_dir.join(
-            file_path
-                .with_extension("vm")
-                .file_name()
-                .and_then(|p| p.to_str())
-                .unwrap(),
+    let tokens: Vec<_> =
+        tokenize_with_extensions(source.lines().map(String::from), &options.extensions).collect();
+    // The parser reports the first invalid token, and the tokens before it aren't written
+    if tokens.iter().all(Result::is_ok) {
+        write(Artifact::TokensXml, &|writer| write_tokens(writer, &tokens))?;
+    }
+
+    let class = parse_with_extensions(tokens.into_iter(), &options.extensions).map_err(report)?;
+    write(Artifact::AstXml, &|writer| write_parse_tree(writer, &class))?;
+    write(Artifact::AstJson, &|writer| write_ast_json(writer, &class))?;
+
+    let class_name = class.class_name.name.clone();
+    let signature = signature(&class, program);
+    let dependencies = dependencies(&class, program);
+    let compiled = compile_in_program(&class, program).map_err(report)?;
+    write(Artifact::Vm, &|writer| write_lines(writer, &compiled.vm))?;
+    write(Artifact::Symbols, &|writer| {
+        write_json(writer, &compiled.symbols)
+    })?;
+    write(Artifact::Map, &|writer| {
+        write_json(writer, &source_map(&file_path.to_string_lossy(), &compiled))
+    })?;
+
+    let path = file_path.to_string_lossy();
+    let warnings = compiled
+        .warnings
+        .iter()
+        .map(|warning| emitter.emit(warning, &path, source))
+        .collect();
+    Ok(Compiled {
+        class_name,
+        signature,
+        dependencies,
+        outputs,
+        vm: compiled.vm,
+        warnings,
+    })
+}
+
+/// The extensions, optimization level and lints change how a source compiles,
+/// so they are part of its hash
+fn source_hash(source: &str, options: &Options) -> u64 {
+    if options.extensions.is_empty() && options.opt_level == 0 && options.lints.is_empty() {
+        hash(source.as_bytes())
+    } else {
+        hash(
+            format!(
+                "{}\n{}\n{}\n{}",
+                options.extensions, options.opt_level, options.lints, source
+            )
+            .as_bytes(),
+        )
+    }
+}
+
+/// The per-class files that compiling `file_path` with the given options should produce
+fn requested_outputs(file_path: &Path, output_dir: &Path, options: &Options) -> Vec<PathBuf> {
+    options
+        .emit
+        .iter()
+        .filter(|artifact| !artifact.is_program_level())
+        .map(|artifact| artifact.output_path(file_path, output_dir))
+        .collect()
+}
+
+/// Compiles a single class read from stdin and writes one artifact to stdout,
+/// so the compiler can be used as a filter
+fn compile_stdin(matches: &ArgMatches) -> Status {
+    let emitter = emitter(matches);
+    let mut options = options(matches, Artifact::Vm);
+    let path = "<stdin>";
+    let usage_error = |message: &str| {
+        eprint!(
+            "{}",
+            emitter.emit(&Diagnostic::error("E0302", message), path, "")
         );
-        let vm_output_file = File::create(vm_output_path).expect("Unable to create vm file");
-        let mut writer = BufWriter::new(vm_output_file);
-        let compiled = compile_class(class);
-        for string in compiled {
-            write!(writer, "{}\n", string).expect("Error writing to vm file");
+        Status::UsageError
+    };
+    // `--tokenize` and `--parse` replace the default instead of adding to it
+    if matches.values_of("emit").is_none() && options.emit.len() > 1 {
+        options.emit.retain(|artifact| *artifact != Artifact::Vm);
+    }
+    let artifact = match options.emit.as_slice() {
+        [artifact] if artifact.is_program_level() => {
+            return usage_error(&format!(
+                "`{}` is built from a whole program, so it can't be written for stdin",
+                artifact.as_ref()
+            ));
+        }
+        [artifact] => *artifact,
+        _ => return usage_error("only one artifact can be written to stdout"),
+    };
+    if matches.is_present("watch") {
+        return usage_error("`--watch` can't be used with stdin");
+    }
+    let is_json = matches.value_of("input_format") == Some("ast-json");
+    if is_json && artifact == Artifact::TokensXml {
+        return usage_error("`tokens-xml` can't be written from a JSON AST");
+    }
+
+    let mut source = String::new();
+    if let Err(error) = io::stdin().read_to_string(&mut source) {
+        return report_io_error(&emitter, "cannot read", Path::new(path), error);
+    }
+    let report = |diagnostic: Diagnostic| {
+        eprint!("{}", emitter.emit(&diagnostic, path, &source));
+        Status::CompileError
+    };
+
+    let stdout = io::stdout();
+    let writer = BufWriter::new(stdout.lock());
+    let tokens = || -> Vec<_> {
+        tokenize_with_extensions(source.lines().map(String::from), &options.extensions).collect()
+    };
+    let result = if artifact == Artifact::TokensXml {
+        let tokens = tokens();
+        match tokens.iter().find_map(|token| token.as_ref().err()) {
+            Some(diagnostic) => return report(diagnostic.clone()),
+            None => write_tokens(writer, &tokens),
         }
+    } else {
+        let class = if is_json {
+            from_json(&source)
+        } else {
+            parse_with_extensions(tokens().into_iter(), &options.extensions)
+        };
+        let class = match class {
+            Ok(class) => class,
+            Err(diagnostic) => return report(diagnostic),
+        };
+        match artifact {
+            Artifact::AstXml => write_parse_tree(writer, &class),
+            Artifact::AstJson => write_ast_json(writer, &class),
+            _ => {
+                let program = new_program(std::iter::once(&class), &options);
+                let compiled = match compile_in_program(&class, &program) {
+                    Ok(compiled) => compiled,
+                    Err(diagnostic) => return report(diagnostic),
+                };
+                for warning in compiled.warnings.iter() {
+                    eprint!("{}", emitter.emit(warning, path, &source));
+                }
+                match artifact {
+                    Artifact::Symbols => write_json(writer, &compiled.symbols),
+                    Artifact::Map => write_json(writer, &source_map(path, &compiled)),
+                    _ => write_lines(writer, &compiled.vm),
+                }
+            }
+        }
+    };
+    match result {
+        Ok(()) => Status::Success,
+        Err(error) => report_io_error(&emitter, "cannot write", Path::new("<stdout>"), error),
+    }
+}
+
+fn write_parse_tree(writer: impl Write, class: &Class) -> io::Result<()> {
+    let mut xml = XmlWriter::new(writer);
+    class.write_xml(&mut xml)?;
+    xml.flush()
+}
+
+fn write_lines(mut writer: impl Write, lines: &[String]) -> io::Result<()> {
+    for line in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()
+}
+
+fn write_ast_json(mut writer: impl Write, class: &Class) -> io::Result<()> {
+    writeln!(writer, "{}", to_json(class))?;
+    writer.flush()
+}
+
+fn write_json(mut writer: impl Write, value: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// Maps each line of a class's `.vm` file (by index) to the span of the source it came from
+#[derive(Serialize)]
+struct SourceMap<'a> {
+    source: &'a str,
+    spans: &'a [Option<Span>],
+}
+
+fn source_map<'a>(path: &'a str, compiled: &'a CompiledClass) -> SourceMap<'a> {
+    SourceMap {
+        source: path,
+        spans: &compiled.source_map,
+    }
+}
+
+/// Writes the tokens that were read successfully, even if the tokenizer also reported errors
+fn write_tokens<T: ToXml, S>(
+    writer: impl Write,
+    tokens: &[Result<(T, S), Diagnostic>],
+) -> io::Result<()> {
+    let mut xml = XmlWriter::unindented(writer);
+    xml.open("tokens")?;
+    for (token, _) in tokens.iter().flatten() {
+        token.write_xml(&mut xml)?;
+    }
+    xml.close("tokens")?;
+    xml.flush()
+}
+
+/// Two files with the same name would overwrite each other's outputs in a single directory
+fn find_output_collision(files: &[PathBuf]) -> Option<Diagnostic> {
+    let mut seen: HashMap<&OsStr, &Path> = HashMap::new();
+    for file_path in files {
+        let file_stem = file_path.file_stem().unwrap_or_default();
+        if let Some(previous) = seen.insert(file_stem, file_path) {
+            return Some(Diagnostic::error(
+                "E0301",
+                format!(
+                    "`{}` and `{}` would both be compiled to `{}.vm`",
+                    previous.display(),
+                    file_path.display(),
+                    file_stem.to_string_lossy()
+                ),
+            ));
+        }
+    }
+    None
+}
+
+fn report_io_error(emitter: &Emitter, message: &str, path: &Path, error: io::Error) -> Status {
+    eprint!("{}", render_io_error(emitter, message, path, error));
+    Status::IoError
+}
+
+fn render_io_error(emitter: &Emitter, message: &str, path: &Path, error: io::Error) -> String {
+    let path = path.to_string_lossy();
+    let diagnostic = Diagnostic::error("E0300", format!("{} `{}`: {}", message, path, error));
+    emitter.emit(&diagnostic, &path, "")
+}
+
+/// Maps `f` over `items` on up to `threads` threads. The results are in the same order as the items.
+fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    threads: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    if threads <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..threads.min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = f(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parallel_map_keeps_the_order_of_the_items() {
+        // The first items take the longest, so they finish last
+        let items: Vec<u64> = (0..16).collect();
+        let results = parallel_map(&items, 4, |item| {
+            thread::sleep(Duration::from_millis(16 - item));
+            item * 2
+        });
+        assert_eq!(
+            results,
+            items.iter().map(|item| item * 2).collect::<Vec<_>>()
+        );
     }
 }
@@ -0,0 +1,97 @@
+use super::{Class, NodeMap};
+use crate::diagnostics::{Diagnostic, Position, Span};
+
+/// Writes the AST as JSON. Unlike the XML, this includes the span of every identifier and
+/// expression, and it can be read back with `from_json`.
+pub fn to_json(class: &Class) -> String {
+    serde_json::to_string_pretty(class).expect("the AST can always be serialized")
+}
+
+/// Reads an AST written by `to_json`, like `jackc - --input-format ast-json` does
+pub fn from_json(json: &str) -> Result<Class, Diagnostic> {
+    let class: Class = serde_json::from_str(json).map_err(|error| {
+        // The position is reported as a span instead of being part of the message
+        let message = error.to_string();
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_string(),
+            None => message,
+        };
+        let diagnostic = Diagnostic::error("E0102", format!("invalid JSON AST: {}", message));
+        if error.line() == 0 {
+            return diagnostic;
+        }
+        let start = Position::new(error.line(), error.column().max(1));
+        diagnostic.with_span(Span::new(
+            start,
+            Position::new(start.line, start.column + 1),
+        ))
+    })?;
+    // Lookups by id would find the wrong node
+    if let Some(id) = NodeMap::new(&class).duplicate() {
+        return Err(Diagnostic::error(
+            "E0102",
+            format!("invalid JSON AST: more than one node has the id {}", id.0),
+        )
+        .with_note("every node needs its own `id`"));
+    }
+    Ok(class)
+}
+
+/// The id of every node in the JSON of `class`. Every field is written, so unlike a visitor
+/// this can't miss a node.
+#[cfg(test)]
+pub(super) fn node_ids(class: &Class) -> Vec<super::NodeId> {
+    fn find(value: &serde_json::Value, ids: &mut Vec<super::NodeId>) {
+        match value {
+            serde_json::Value::Object(object) => {
+                if let Some(id) = object.get("id").and_then(serde_json::Value::as_u64) {
+                    ids.push(super::NodeId(id as u32));
+                }
+                object.values().for_each(|value| find(value, ids));
+            }
+            serde_json::Value::Array(values) => values.iter().for_each(|value| find(value, ids)),
+            _ => {}
+        }
+    }
+    let mut ids = Vec::new();
+    find(&serde_json::to_value(class).unwrap(), &mut ids);
+    ids.sort();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_source, EVERY_KIND_OF_NODE};
+    use crate::ToXml;
+
+    #[test]
+    fn round_trip() {
+        let class = parse_source(EVERY_KIND_OF_NODE);
+        let json = to_json(&class);
+        let read = from_json(&json).unwrap();
+        assert_eq!(to_json(&read), json);
+        assert_eq!(read.to_xml(), class.to_xml());
+    }
+
+    #[test]
+    fn invalid_json_has_span() {
+        let diagnostic = from_json("{\n  \"class_name\": 1\n}").unwrap_err();
+        assert_eq!(diagnostic.code, "E0102");
+        assert_eq!(diagnostic.span.unwrap().start, Position::new(2, 17));
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let class = parse_source("class A { function int f() { return 1 + 2; } }");
+        let json = to_json(&class);
+        assert!(from_json(&json).is_ok());
+        let json = json.replace("\"id\": 4,", "\"id\": 3,");
+        let diagnostic = from_json(&json).unwrap_err();
+        assert_eq!(diagnostic.code, "E0102");
+        assert_eq!(
+            diagnostic.message,
+            "invalid JSON AST: more than one node has the id 3"
+        );
+    }
+}
@@ -1,5 +1,14 @@
+mod json;
+mod node;
+#[allow(clippy::module_inception)]
 mod parser;
 mod types;
+pub mod visit;
 
-pub use parser::parse;
-pub use types::*;
\ No newline at end of file
+pub use json::{from_json, to_json};
+pub use node::{Node, NodeId, NodeMap};
+pub use parser::{parse, parse_with_extensions};
+#[cfg(test)]
+pub(crate) use parser::{parse_source, EVERY_KIND_OF_NODE};
+pub use types::*;
+pub use visit::{Visitor, VisitorMut};
@@ -0,0 +1,219 @@
+use super::types::*;
+use super::visit::{self, Visitor};
+use crate::{ToXml, XmlWriter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Identifies a node within the class it was parsed from.
+/// The parser numbers the nodes in the order they start in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NodeId(pub u32);
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// A reference to any node that has a `NodeId`
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'ast> {
+    ClassVarDeclaration(&'ast ClassVarDeclaration),
+    ConstantDeclaration(&'ast ConstantDeclaration),
+    EnumDeclaration(&'ast EnumDeclaration),
+    SubroutineDeclaration(&'ast SubroutineDeclaration),
+    VarDeclaration(&'ast VarDeclaration),
+    Statement(&'ast Statement),
+    Expression(&'ast Expression),
+    Term(&'ast Term),
+}
+
+impl<'ast> Node<'ast> {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Node::ClassVarDeclaration(declaration) => declaration.id,
+            Node::ConstantDeclaration(declaration) => declaration.id,
+            Node::EnumDeclaration(declaration) => declaration.id,
+            Node::SubroutineDeclaration(subroutine) => subroutine.id,
+            Node::VarDeclaration(declaration) => declaration.id,
+            Node::Statement(statement) => statement.id(),
+            Node::Expression(expression) => expression.id,
+            Node::Term(term) => term.id,
+        }
+    }
+}
+
+impl ToXml for Node<'_> {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        match self {
+            Node::ClassVarDeclaration(declaration) => declaration.write_xml(xml),
+            Node::ConstantDeclaration(declaration) => declaration.write_xml(xml),
+            Node::EnumDeclaration(declaration) => declaration.write_xml(xml),
+            Node::SubroutineDeclaration(subroutine) => subroutine.write_xml(xml),
+            Node::VarDeclaration(declaration) => declaration.write_xml(xml),
+            Node::Statement(statement) => statement.write_xml(xml),
+            Node::Expression(expression) => expression.write_xml(xml),
+            Node::Term(term) => term.write_xml(xml),
+        }
+    }
+}
+
+/// Side table for looking up nodes by id, and finding the parent and enclosing subroutine of each
+#[derive(Debug, Default)]
+pub struct NodeMap<'ast> {
+    nodes: HashMap<NodeId, Node<'ast>>,
+    parents: HashMap<NodeId, NodeId>,
+    subroutines: HashMap<NodeId, NodeId>,
+    // The nodes currently being visited, innermost last
+    stack: Vec<NodeId>,
+    subroutine: Option<NodeId>,
+    duplicate: Option<NodeId>,
+}
+
+impl<'ast> NodeMap<'ast> {
+    pub fn new(class: &'ast Class) -> NodeMap<'ast> {
+        let mut map = NodeMap::default();
+        map.visit_class(class);
+        map
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<Node<'ast>> {
+        self.nodes.get(&id).copied()
+    }
+
+    /// The first id that more than one node has, which only an AST that wasn't made by the
+    /// parser can have. Lookups of the id find the last of its nodes.
+    pub fn duplicate(&self) -> Option<NodeId> {
+        self.duplicate
+    }
+
+    /// The closest node with an id that contains this one.
+    /// Class and subroutine level declarations have no parent.
+    pub fn parent(&self, id: NodeId) -> Option<Node<'ast>> {
+        self.parents.get(&id).and_then(|parent| self.get(*parent))
+    }
+
+    /// The subroutine the node is part of, which is `None` for the subroutine itself
+    pub fn subroutine(&self, id: NodeId) -> Option<&'ast SubroutineDeclaration> {
+        match self.subroutines.get(&id).and_then(|id| self.get(*id)) {
+            Some(Node::SubroutineDeclaration(subroutine)) => Some(subroutine),
+            _ => None,
+        }
+    }
+
+    /// Records the node, then visits its children with it as their parent
+    fn enter(&mut self, node: Node<'ast>, walk: impl FnOnce(&mut Self)) {
+        let id = node.id();
+        if self.nodes.insert(id, node).is_some() && self.duplicate.is_none() {
+            self.duplicate = Some(id);
+        }
+        if let Some(parent) = self.stack.last() {
+            self.parents.insert(id, *parent);
+        }
+        if let Some(subroutine) = self.subroutine {
+            self.subroutines.insert(id, subroutine);
+        }
+        self.stack.push(id);
+        walk(self);
+        self.stack.pop();
+    }
+}
+
+impl<'ast> Visitor<'ast> for NodeMap<'ast> {
+    fn visit_class_var_declaration(&mut self, declaration: &'ast ClassVarDeclaration) {
+        self.enter(Node::ClassVarDeclaration(declaration), |map| {
+            visit::walk_class_var_declaration(map, declaration)
+        })
+    }
+
+    fn visit_constant_declaration(&mut self, declaration: &'ast ConstantDeclaration) {
+        self.enter(Node::ConstantDeclaration(declaration), |map| {
+            visit::walk_constant_declaration(map, declaration)
+        })
+    }
+
+    fn visit_enum_declaration(&mut self, declaration: &'ast EnumDeclaration) {
+        self.enter(Node::EnumDeclaration(declaration), |map| {
+            visit::walk_enum_declaration(map, declaration)
+        })
+    }
+
+    fn visit_subroutine_declaration(&mut self, subroutine: &'ast SubroutineDeclaration) {
+        self.enter(Node::SubroutineDeclaration(subroutine), |map| {
+            map.subroutine = Some(subroutine.id);
+            visit::walk_subroutine_declaration(map, subroutine);
+            map.subroutine = None;
+        })
+    }
+
+    fn visit_var_declaration(&mut self, declaration: &'ast VarDeclaration) {
+        self.enter(Node::VarDeclaration(declaration), |map| {
+            visit::walk_var_declaration(map, declaration)
+        })
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        self.enter(Node::Statement(statement), |map| {
+            visit::walk_statement(map, statement)
+        })
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        self.enter(Node::Expression(expression), |map| {
+            visit::walk_expression(map, expression)
+        })
+    }
+
+    fn visit_term(&mut self, term: &'ast Term) {
+        self.enter(Node::Term(term), |map| visit::walk_term(map, term))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::json::node_ids;
+    use crate::parser::{parse_source, EVERY_KIND_OF_NODE};
+
+    #[test]
+    fn finds_every_node_by_its_id() {
+        let class = parse_source(EVERY_KIND_OF_NODE);
+        let map = NodeMap::new(&class);
+        assert_eq!(map.duplicate(), None);
+        for id in node_ids(&class) {
+            assert_eq!(map.get(id).map(|node| node.id()), Some(id));
+        }
+        assert!(map.get(NodeId(u32::MAX)).is_none());
+    }
+
+    #[test]
+    fn ids_follow_source_order() {
+        let class = parse_source(EVERY_KIND_OF_NODE);
+        let map = NodeMap::new(&class);
+        let ids = node_ids(&class);
+        // A node starts before (or where) its children do
+        for id in ids.iter() {
+            if let Some(parent) = map.parent(*id) {
+                assert!(parent.id() < *id, "{} is inside {}", id, parent.id());
+            }
+            if let Some(subroutine) = map.subroutine(*id) {
+                assert!(subroutine.id < *id, "{} is inside {}", id, subroutine.id);
+            }
+        }
+        // Expressions have spans, so their order can be checked against the source
+        let starts: Vec<_> = ids
+            .iter()
+            .filter_map(|id| match map.get(*id) {
+                Some(Node::Expression(expression)) => {
+                    Some((expression.span.start.line, expression.span.start.column))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut sorted = starts.clone();
+        sorted.sort();
+        assert_eq!(starts, sorted);
+    }
+}
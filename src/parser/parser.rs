@@ -1,120 +1,297 @@
 use std::iter::Peekable;
 
 use super::types::*;
+use super::visit::{walk_expression_mut, walk_term_mut};
+use super::{NodeId, VisitorMut};
+use crate::diagnostics::{Diagnostic, Span};
 use crate::tokenizer::{Keyword, Symbol, Token};
+use crate::{Extension, Extensions};
 
-pub fn parse<I: Iterator<Item = Token>>(tokens: I) -> Result<Class, String> {
-    let mut parser = Parser::new(tokens);
+pub fn parse<I: Iterator<Item = Result<(Token, Span), Diagnostic>>>(
+    tokens: I,
+) -> Result<Class, Diagnostic> {
+    parse_with_extensions(tokens, &Extensions::new())
+}
+
+/// Like `parse`, but also accepts the syntax of the given language extensions
+pub fn parse_with_extensions<I: Iterator<Item = Result<(Token, Span), Diagnostic>>>(
+    tokens: I,
+    extensions: &Extensions,
+) -> Result<Class, Diagnostic> {
+    let mut parser = Parser::new(tokens, extensions.clone());
     parser.parse_class()
 }
 
-struct Parser<I: Iterator<Item = Token>> {
+/// Uses every extension and has every kind of node
+#[cfg(test)]
+pub(crate) const EVERY_KIND_OF_NODE: &str = r#"
+class Shape extends Base {
+    const int SIDES = 4 * 2;
+    enum Dir { Up, Down }
+    static int count;
+    field int x, y;
+    field Array points;
+
+    constructor Shape new(int ax, int ay) {
+        let x = ax;
+        let y = ay;
+        let count += 1;
+        return this;
+    }
+
+    method int area(Shape other) {
+        var int i, total;
+        var char c;
+        let c = 'a';
+        let total = 0x10 + 0b11 - ((x % 3) << 1 >> 2);
+        for (let i = 0; i <= SIDES; let i += 1) {
+            if (i = 2) {
+                continue;
+            } else if ((i >= 5) && (~(i != 6) || false)) {
+                break;
+            } else {
+                let total = total * -i;
+            }
+        }
+        while (total > 0) {
+            let total = total - 1;
+        }
+        let points[i] = other.x;
+        let other.y = points[0];
+        do Output.printString("a\"b\n");
+        do other.area(this).area(null);
+        let total = other.area(null).area(-this.area(other));
+        do super.draw();
+        return total + Dir.Up + Shape.SIDES;
+    }
+}
+"#;
+
+/// Parses `source` with every extension enabled, panicking if it has an error
+#[cfg(test)]
+pub(crate) fn parse_source(source: &str) -> Class {
+    use crate::tokenizer::tokenize_with_extensions;
+    use std::str::FromStr;
+
+    let extensions: Extensions = Extension::NAMES
+        .iter()
+        .map(|name| Extension::from_str(name).unwrap())
+        .collect();
+    let tokens = tokenize_with_extensions(source.lines().map(String::from), &extensions);
+    parse_with_extensions(tokens, &extensions).unwrap()
+}
+
+struct Parser<I: Iterator<Item = Result<(Token, Span), Diagnostic>>> {
     tokens: Peekable<I>,
+    // Span of the most recently consumed token
+    last_span: Span,
+    next_id: u32,
+    extensions: Extensions,
 }
 
 impl<I> Parser<I>
 where
-    I: Iterator<Item = Token>,
+    I: Iterator<Item = Result<(Token, Span), Diagnostic>>,
 {
-    fn new(tokens: I) -> Parser<I> {
+    fn new(tokens: I, extensions: Extensions) -> Parser<I> {
         Parser {
             tokens: tokens.peekable(),
+            last_span: Span::default(),
+            next_id: 0,
+            extensions,
         }
     }
 
-    fn expect_token(&mut self, token: Token) -> Result<(), String> {
-        let next = self
-            .tokens
-            .next()
-            .ok_or(String::from("Unexpected end of input"))?;
-        if next == token {
-            Ok(())
-        } else {
-            Err(format!(
-                "Expected next token to be: {:?} but got: {:?}",
-                token, next
-            ))
+    /// Nodes take their id before their children are parsed, so ids follow source order
+    fn node_id(&mut self) -> NodeId {
+        self.next_id += 1;
+        NodeId(self.next_id - 1)
+    }
+
+    /// Look at the next token without consuming it.
+    /// Tokenizer errors are only reported once the token is consumed.
+    fn peek(&mut self) -> Option<&Token> {
+        match self.tokens.peek() {
+            Some(Ok((token, _))) => Some(token),
+            _ => None,
         }
     }
 
-    fn expect_identifier(&mut self) -> Result<String, String> {
-        match self.tokens.next() {
-            None => Err(format!("Unexpected end of input, expected identifier")),
-            Some(Token::Identifier(identifier)) => Ok(identifier),
-            Some(token) => Err(format!(
-                "Unexpected token: {:?}, expected identifier",
-                token
-            )),
+    fn peek_span(&mut self) -> Span {
+        match self.tokens.peek() {
+            Some(Ok((_, span))) => *span,
+            _ => Span::new(self.last_span.end, self.last_span.end),
         }
     }
 
-    fn expect_var_type(&mut self) -> Result<VarType, String> {
+    fn next_token(&mut self) -> Result<Option<(Token, Span)>, Diagnostic> {
         match self.tokens.next() {
-            None => Err(format!("Unexpected end of input, expected var type")),
-            Some(Token::Keyword(Keyword::Int)) => Ok(VarType::Int),
-            Some(Token::Keyword(Keyword::Char)) => Ok(VarType::Char),
-            Some(Token::Keyword(Keyword::Boolean)) => Ok(VarType::Boolean),
-            Some(Token::Identifier(class_name)) => Ok(VarType::ClassName(class_name)),
-            Some(token) => Err(format!(
-                "Unexpected token: {:?}, expected identifier",
-                token
-            )),
+            Some(Ok((token, span))) => {
+                self.last_span = span;
+                Ok(Some((token, span)))
+            }
+            Some(Err(diagnostic)) => Err(diagnostic),
+            None => Ok(None),
         }
     }
 
-    fn parse_class(&mut self) -> Result<Class, String> {
+    fn unexpected_end(&self, expected: &str) -> Diagnostic {
+        Diagnostic::error(
+            "E0101",
+            format!("unexpected end of input, expected {}", expected),
+        )
+        .with_span(Span::new(self.last_span.end, self.last_span.end))
+    }
+
+    fn unexpected_token(&self, token: &Token, span: Span, expected: &str) -> Diagnostic {
+        Diagnostic::error("E0100", format!("expected {}, found `{}`", expected, token))
+            .with_span(span)
+            .with_label(format!("expected {}", expected))
+    }
+
+    fn expect_token(&mut self, token: Token) -> Result<(), Diagnostic> {
+        // Where a missing semicolon would go, if one is missing
+        let end_of_previous = Span::new(self.last_span.end, self.last_span.end);
+        let diagnostic = match self.next_token()? {
+            None => self.unexpected_end(&format!("`{}`", token)),
+            Some((next, _)) if next == token => return Ok(()),
+            Some((next, span)) => self.unexpected_token(&next, span, &format!("`{}`", token)),
+        };
+        if token == Token::Symbol(Symbol::Semicolon) {
+            Err(diagnostic.with_suggestion("add a semicolon", end_of_previous, ";"))
+        } else {
+            Err(diagnostic)
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<Identifier, Diagnostic> {
+        match self.next_token()? {
+            None => Err(self.unexpected_end("identifier")),
+            Some((Token::Identifier(identifier), span)) => Ok(Identifier::new(identifier, span)),
+            Some((token, span)) => Err(self.unexpected_token(&token, span, "identifier")),
+        }
+    }
+
+    fn expect_var_type(&mut self) -> Result<VarType, Diagnostic> {
+        match self.next_token()? {
+            None => Err(self.unexpected_end("var type")),
+            Some((Token::Keyword(Keyword::Int), _)) => Ok(VarType::Int),
+            Some((Token::Keyword(Keyword::Char), _)) => Ok(VarType::Char),
+            Some((Token::Keyword(Keyword::Boolean), _)) => Ok(VarType::Boolean),
+            Some((Token::Identifier(class_name), span)) => {
+                Ok(VarType::ClassName(Identifier::new(class_name, span)))
+            }
+            Some((token, span)) => Err(self.unexpected_token(&token, span, "var type")),
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Class, Diagnostic> {
         self.expect_token(Token::Keyword(Keyword::Class))?;
         let class_name = self.expect_identifier()?;
+        let superclass = match self.peek() {
+            Some(Token::Keyword(Keyword::Extends)) => {
+                self.next_token()?;
+                Some(self.expect_identifier()?)
+            }
+            Some(Token::Identifier(name)) if name == "extends" => {
+                return Err(self
+                    .expect_token(Token::Symbol(Symbol::CurlyOpen))
+                    .unwrap_err()
+                    .with_help("`extends` needs the `inheritance` extension"));
+            }
+            _ => None,
+        };
         self.expect_token(Token::Symbol(Symbol::CurlyOpen))?;
-        let class_var_declarations = self.parse_class_var_declarations()?;
+        let mut class_var_declarations = Vec::new();
+        let mut constant_declarations = Vec::new();
+        let mut enum_declarations = Vec::new();
+        loop {
+            match self.peek() {
+                Some(&Token::Keyword(Keyword::Static)) | Some(&Token::Keyword(Keyword::Field)) => {
+                    class_var_declarations.push(self.parse_class_var_declaration()?)
+                }
+                Some(&Token::Keyword(Keyword::Const)) => {
+                    constant_declarations.push(self.parse_constant_declaration()?)
+                }
+                Some(&Token::Keyword(Keyword::Enum)) => {
+                    enum_declarations.push(self.parse_enum_declaration()?)
+                }
+                _ => break,
+            }
+        }
         let subroutine_declarations = self.parse_subroutine_declarations()?;
         self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
 
         Ok(Class {
             class_name,
+            superclass,
             class_var_declarations,
+            constant_declarations,
+            enum_declarations,
             subroutine_declarations,
         })
     }
 
-    fn parse_class_var_declarations(&mut self) -> Result<Vec<ClassVarDeclaration>, String> {
-        let mut declarations = Vec::new();
-        loop {
-            match self.tokens.peek() {
-                Some(&Token::Keyword(Keyword::Static)) | Some(&Token::Keyword(Keyword::Field)) => {
-                    declarations.push(self.parse_class_var_declaration()?)
-                }
-                _ => break,
+    fn parse_class_var_declaration(&mut self) -> Result<ClassVarDeclaration, Diagnostic> {
+        let id = self.node_id();
+        let static_or_field = match self.next_token()? {
+            Some((Token::Keyword(Keyword::Static), _)) => StaticOrField::Static,
+            Some((Token::Keyword(Keyword::Field), _)) => StaticOrField::Field,
+            Some((token, span)) => {
+                return Err(self.unexpected_token(&token, span, "`static` or `field`"))
             }
-        }
-        Ok(declarations)
-    }
-
-    fn parse_class_var_declaration(&mut self) -> Result<ClassVarDeclaration, String> {
-        let static_or_field = match self.tokens.next() {
-            Some(Token::Keyword(Keyword::Static)) => StaticOrField::Static,
-            Some(Token::Keyword(Keyword::Field)) => StaticOrField::Field,
-            _ => return Err(String::from("Expected keyword: 'static' or 'field'")),
+            None => return Err(self.unexpected_end("`static` or `field`")),
         };
         let var_type = self.expect_var_type()?;
         let mut var_names = vec![self.expect_identifier()?];
-        while let Some(&Token::Symbol(Symbol::Comma)) = self.tokens.peek() {
+        while let Some(&Token::Symbol(Symbol::Comma)) = self.peek() {
             self.expect_token(Token::Symbol(Symbol::Comma))?;
             var_names.push(self.expect_identifier()?);
         }
         self.expect_token(Token::Symbol(Symbol::Semicolon))?;
 
         Ok(ClassVarDeclaration {
+            id,
             static_or_field,
             var_type,
             var_names,
         })
     }
 
-    fn parse_subroutine_declarations(&mut self) -> Result<Vec<SubroutineDeclaration>, String> {
+    fn parse_constant_declaration(&mut self) -> Result<ConstantDeclaration, Diagnostic> {
+        let id = self.node_id();
+        self.expect_token(Token::Keyword(Keyword::Const))?;
+        let var_type = self.expect_var_type()?;
+        let name = self.expect_identifier()?;
+        self.expect_token(Token::Symbol(Symbol::Equals))?;
+        let expression = self.parse_expression()?;
+        self.expect_token(Token::Symbol(Symbol::Semicolon))?;
+        Ok(ConstantDeclaration {
+            id,
+            var_type,
+            name,
+            expression,
+        })
+    }
+
+    fn parse_enum_declaration(&mut self) -> Result<EnumDeclaration, Diagnostic> {
+        let id = self.node_id();
+        self.expect_token(Token::Keyword(Keyword::Enum))?;
+        let name = self.expect_identifier()?;
+        self.expect_token(Token::Symbol(Symbol::CurlyOpen))?;
+        let mut variants = vec![self.expect_identifier()?];
+        while self.peek() == Some(&Token::Symbol(Symbol::Comma)) {
+            self.next_token()?;
+            variants.push(self.expect_identifier()?);
+        }
+        self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
+        Ok(EnumDeclaration { id, name, variants })
+    }
+
+    fn parse_subroutine_declarations(&mut self) -> Result<Vec<SubroutineDeclaration>, Diagnostic> {
         let mut declarations = Vec::new();
         loop {
-            let next = self.tokens.peek();
+            let next = self.peek();
             match next {
                 Some(&Token::Keyword(Keyword::Constructor))
                 | Some(&Token::Keyword(Keyword::Function))
@@ -127,22 +304,24 @@ where
         Ok(declarations)
     }
 
-    fn parse_subroutine_declaration(&mut self) -> Result<SubroutineDeclaration, String> {
-        let subroutine_type = match self.tokens.next() {
-            None => return Err(format!("Unexpected end of input, expected subroutine type")),
-            Some(Token::Keyword(Keyword::Constructor)) => SubroutineType::Constructor,
-            Some(Token::Keyword(Keyword::Function)) => SubroutineType::Function,
-            Some(Token::Keyword(Keyword::Method)) => SubroutineType::Method,
-            Some(token) => {
-                return Err(format!(
-                    "Expected keyword constructor, function or method; got: {:?}",
-                    token
+    fn parse_subroutine_declaration(&mut self) -> Result<SubroutineDeclaration, Diagnostic> {
+        let id = self.node_id();
+        let subroutine_type = match self.next_token()? {
+            None => return Err(self.unexpected_end("subroutine type")),
+            Some((Token::Keyword(Keyword::Constructor), _)) => SubroutineType::Constructor,
+            Some((Token::Keyword(Keyword::Function), _)) => SubroutineType::Function,
+            Some((Token::Keyword(Keyword::Method), _)) => SubroutineType::Method,
+            Some((token, span)) => {
+                return Err(self.unexpected_token(
+                    &token,
+                    span,
+                    "`constructor`, `function` or `method`",
                 ))
             }
         };
 
-        let return_type = if let Some(&Token::Keyword(Keyword::Void)) = self.tokens.peek() {
-            self.tokens.next();
+        let return_type = if let Some(&Token::Keyword(Keyword::Void)) = self.peek() {
+            self.next_token()?;
             // None represents void
             None
         } else {
@@ -156,6 +335,7 @@ where
         let body = self.parse_subroutine_body()?;
 
         Ok(SubroutineDeclaration {
+            id,
             subroutine_type,
             return_type,
             name,
@@ -164,10 +344,10 @@ where
         })
     }
 
-    fn parse_parameter_list(&mut self) -> Result<Vec<Parameter>, String> {
+    fn parse_parameter_list(&mut self) -> Result<Vec<Parameter>, Diagnostic> {
         let mut parameters = Vec::new();
 
-        match self.tokens.peek() {
+        match self.peek() {
             Some(&Token::Keyword(Keyword::Int))
             | Some(&Token::Keyword(Keyword::Char))
             | Some(&Token::Keyword(Keyword::Boolean))
@@ -176,8 +356,8 @@ where
                 let var_name = self.expect_identifier()?;
                 parameters.push((var_type, var_name));
 
-                while self.tokens.peek() == Some(&Token::Symbol(Symbol::Comma)) {
-                    self.tokens.next();
+                while self.peek() == Some(&Token::Symbol(Symbol::Comma)) {
+                    self.next_token()?;
                     let var_type = self.expect_var_type()?;
                     let var_name = self.expect_identifier()?;
                     parameters.push((var_type, var_name));
@@ -189,7 +369,7 @@ where
         Ok(parameters)
     }
 
-    fn parse_subroutine_body(&mut self) -> Result<SubroutineBody, String> {
+    fn parse_subroutine_body(&mut self) -> Result<SubroutineBody, Diagnostic> {
         self.expect_token(Token::Symbol(Symbol::CurlyOpen))?;
         let var_declarations = self.parse_var_declarations()?;
         let statements = self.parse_statements()?;
@@ -200,19 +380,21 @@ where
         })
     }
 
-    fn parse_var_declarations(&mut self) -> Result<Vec<VarDeclaration>, String> {
+    fn parse_var_declarations(&mut self) -> Result<Vec<VarDeclaration>, Diagnostic> {
         let mut declarations = Vec::new();
-        while self.tokens.peek() == Some(&Token::Keyword(Keyword::Var)) {
-            self.tokens.next();
+        while self.peek() == Some(&Token::Keyword(Keyword::Var)) {
+            let id = self.node_id();
+            self.next_token()?;
             let var_type = self.expect_var_type()?;
             let mut var_names = vec![self.expect_identifier()?];
-            while self.tokens.peek() == Some(&Token::Symbol(Symbol::Comma)) {
-                self.tokens.next();
+            while self.peek() == Some(&Token::Symbol(Symbol::Comma)) {
+                self.next_token()?;
                 var_names.push(self.expect_identifier()?);
             }
             self.expect_token(Token::Symbol(Symbol::Semicolon))?;
 
             declarations.push(VarDeclaration {
+                id,
                 var_type,
                 var_names,
             })
@@ -221,11 +403,11 @@ where
         Ok(declarations)
     }
 
-    fn parse_statements(&mut self) -> Result<Vec<Statement>, String> {
+    fn parse_statements(&mut self) -> Result<Vec<Statement>, Diagnostic> {
         let mut statements = Vec::new();
 
         loop {
-            let statement = match self.tokens.peek() {
+            let statement = match self.peek() {
                 Some(Token::Keyword(Keyword::Let)) => Statement::Let(self.parse_let_statement()?),
                 Some(Token::Keyword(Keyword::If)) => Statement::If(self.parse_if_statement()?),
                 Some(Token::Keyword(Keyword::While)) => {
@@ -235,6 +417,21 @@ where
                 Some(Token::Keyword(Keyword::Return)) => {
                     Statement::Return(self.parse_return_statement()?)
                 }
+                Some(Token::Keyword(Keyword::For)) => Statement::For(self.parse_for_statement()?),
+                Some(Token::Keyword(Keyword::Break)) => {
+                    let id = self.node_id();
+                    let span = self.peek_span();
+                    self.next_token()?;
+                    self.expect_token(Token::Symbol(Symbol::Semicolon))?;
+                    Statement::Break(BreakStatement { id, span })
+                }
+                Some(Token::Keyword(Keyword::Continue)) => {
+                    let id = self.node_id();
+                    let span = self.peek_span();
+                    self.next_token()?;
+                    self.expect_token(Token::Symbol(Symbol::Semicolon))?;
+                    Statement::Continue(ContinueStatement { id, span })
+                }
                 _ => break,
             };
             statements.push(statement);
@@ -243,32 +440,56 @@ where
         Ok(statements)
     }
 
-    fn parse_let_statement(&mut self) -> Result<LetStatement, String> {
+    fn parse_let_statement(&mut self) -> Result<LetStatement, Diagnostic> {
+        let statement = self.parse_unterminated_let_statement()?;
+        self.expect_token(Token::Symbol(Symbol::Semicolon))?;
+        Ok(statement)
+    }
+
+    /// A `let` statement without the `;`, like the initializer and update of a `for` loop
+    fn parse_unterminated_let_statement(&mut self) -> Result<LetStatement, Diagnostic> {
+        let id = self.node_id();
         self.expect_token(Token::Keyword(Keyword::Let))?;
         let var_name = self.expect_identifier()?;
 
-        let left_side_expression =
-            if self.tokens.peek() == Some(&Token::Symbol(Symbol::BracketOpen)) {
-                self.tokens.next();
-                let expression = self.parse_expression()?;
-                self.expect_token(Token::Symbol(Symbol::BracketClose))?;
-                Some(expression)
-            } else {
-                None
-            };
+        let left_side_expression = if self.peek() == Some(&Token::Symbol(Symbol::BracketOpen)) {
+            self.next_token()?;
+            let expression = self.parse_expression()?;
+            self.expect_token(Token::Symbol(Symbol::BracketClose))?;
+            Some(expression)
+        } else {
+            None
+        };
+        let field = if left_side_expression.is_none()
+            && self.peek() == Some(&Token::Symbol(Symbol::Period))
+            && self.extensions.is_enabled(Extension::FieldAccess)
+        {
+            self.next_token()?;
+            Some(self.expect_identifier()?)
+        } else {
+            None
+        };
 
-        self.expect_token(Token::Symbol(Symbol::Equals))?;
+        let op = self.peek().and_then(compound_assignment_op);
+        if op.is_some() {
+            self.next_token()?;
+        } else {
+            self.expect_token(Token::Symbol(Symbol::Equals))?;
+        }
         let right_side_expression = self.parse_expression()?;
-        self.expect_token(Token::Symbol(Symbol::Semicolon))?;
 
         Ok(LetStatement {
+            id,
             var_name,
             left_side_expression,
+            field,
+            op,
             right_side_expression,
         })
     }
 
-    fn parse_if_statement(&mut self) -> Result<IfStatement, String> {
+    fn parse_if_statement(&mut self) -> Result<IfStatement, Diagnostic> {
+        let id = self.node_id();
         self.expect_token(Token::Keyword(Keyword::If))?;
         self.expect_token(Token::Symbol(Symbol::ParenOpen))?;
         let expression = self.parse_expression()?;
@@ -277,24 +498,39 @@ where
         let if_statements = self.parse_statements()?;
         self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
 
-        let else_statements = if self.tokens.peek() == Some(&Token::Keyword(Keyword::Else)) {
-            self.tokens.next();
-            self.expect_token(Token::Symbol(Symbol::CurlyOpen))?;
-            let statements = self.parse_statements()?;
-            self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
-            Some(statements)
+        let else_statements = if self.peek() == Some(&Token::Keyword(Keyword::Else)) {
+            self.next_token()?;
+            if self.peek() == Some(&Token::Keyword(Keyword::If)) {
+                if !self.extensions.is_enabled(Extension::ElseIf) {
+                    return Err(self
+                        .expect_token(Token::Symbol(Symbol::CurlyOpen))
+                        .unwrap_err()
+                        .with_help("`else if` chains need the `else-if` extension"));
+                }
+                let mut statement = self.parse_if_statement()?;
+                statement.else_if = true;
+                Some(vec![Statement::If(statement)])
+            } else {
+                self.expect_token(Token::Symbol(Symbol::CurlyOpen))?;
+                let statements = self.parse_statements()?;
+                self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
+                Some(statements)
+            }
         } else {
             None
         };
 
         Ok(IfStatement {
+            id,
             expression,
             if_statements,
             else_statements,
+            else_if: false,
         })
     }
 
-    fn parse_while_statement(&mut self) -> Result<WhileStatement, String> {
+    fn parse_while_statement(&mut self) -> Result<WhileStatement, Diagnostic> {
+        let id = self.node_id();
         self.expect_token(Token::Keyword(Keyword::While))?;
         self.expect_token(Token::Symbol(Symbol::ParenOpen))?;
         let expression = self.parse_expression()?;
@@ -303,169 +539,343 @@ where
         let statements = self.parse_statements()?;
         self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
         Ok(WhileStatement {
+            id,
             expression,
             statements,
         })
     }
 
-    fn parse_do_statement(&mut self) -> Result<DoStatement, String> {
+    fn parse_for_statement(&mut self) -> Result<ForStatement, Diagnostic> {
+        let id = self.node_id();
+        self.expect_token(Token::Keyword(Keyword::For))?;
+        self.expect_token(Token::Symbol(Symbol::ParenOpen))?;
+        let initializer = if self.peek() == Some(&Token::Symbol(Symbol::Semicolon)) {
+            None
+        } else {
+            let statement = self.parse_unterminated_let_statement()?;
+            Some(Box::new(Statement::Let(statement)))
+        };
+        self.expect_token(Token::Symbol(Symbol::Semicolon))?;
+        let condition = if self.peek() == Some(&Token::Symbol(Symbol::Semicolon)) {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        self.expect_token(Token::Symbol(Symbol::Semicolon))?;
+        let update = if self.peek() == Some(&Token::Symbol(Symbol::ParenClose)) {
+            None
+        } else {
+            let statement = self.parse_unterminated_let_statement()?;
+            Some(Box::new(Statement::Let(statement)))
+        };
+        self.expect_token(Token::Symbol(Symbol::ParenClose))?;
+        self.expect_token(Token::Symbol(Symbol::CurlyOpen))?;
+        let statements = self.parse_statements()?;
+        self.expect_token(Token::Symbol(Symbol::CurlyClose))?;
+        Ok(ForStatement {
+            id,
+            initializer,
+            condition,
+            update,
+            statements,
+        })
+    }
+
+    fn parse_do_statement(&mut self) -> Result<DoStatement, Diagnostic> {
+        let id = self.node_id();
         self.expect_token(Token::Keyword(Keyword::Do))?;
-        let identifier = self.expect_identifier()?;
-        let subroutine_call = self.parse_subroutine_call(identifier)?;
+        let subroutine_call = if self.extensions.is_enabled(Extension::CallChains)
+            || self.peek() == Some(&Token::Keyword(Keyword::Super))
+        {
+            // The call can be made on any term, so the whole term is parsed first
+            match self.parse_term()?.kind {
+                TermKind::SubroutineCall(subroutine_call) => subroutine_call,
+                _ => {
+                    return Err(self
+                        .expect_token(Token::Symbol(Symbol::Period))
+                        .unwrap_err()
+                        .with_help("a `do` statement has to call a subroutine"));
+                }
+            }
+        } else {
+            let identifier = self.expect_identifier()?;
+            self.parse_subroutine_call(identifier)?
+        };
         self.expect_token(Token::Symbol(Symbol::Semicolon))?;
-        Ok(DoStatement(subroutine_call))
+        Ok(DoStatement {
+            id,
+            subroutine_call,
+        })
     }
 
-    fn parse_return_statement(&mut self) -> Result<ReturnStatement, String> {
+    fn parse_return_statement(&mut self) -> Result<ReturnStatement, Diagnostic> {
+        let id = self.node_id();
         self.expect_token(Token::Keyword(Keyword::Return))?;
-        let expression = match self.tokens.peek() {
+        let expression = match self.peek() {
             Some(Token::Symbol(Symbol::Semicolon)) => None,
             Some(_) => Some(self.parse_expression()?),
             None => {
-                return Err(format!(
-                    "Unexpected end of input, expected ';' or expression"
-                ))
+                // Report any tokenizer error before the end of input
+                self.next_token()?;
+                return Err(self.unexpected_end("`;` or expression"));
             }
         };
         self.expect_token(Token::Symbol(Symbol::Semicolon))?;
-        Ok(ReturnStatement(expression))
+        Ok(ReturnStatement { id, expression })
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, String> {
+    fn parse_expression(&mut self) -> Result<Expression, Diagnostic> {
+        let id = self.node_id();
+        let start = self.peek_span();
         let term = self.parse_term()?;
         let mut ops = Vec::new();
-        loop {
-            match self.tokens.peek() {
-                Some(&Token::Symbol(Symbol::Plus)) => {
-                    self.tokens.next();
-                    ops.push((Op::Plus, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::Minus)) => {
-                    self.tokens.next();
-                    ops.push((Op::Minus, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::Asterix)) => {
-                    self.tokens.next();
-                    ops.push((Op::Asterix, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::Slash)) => {
-                    self.tokens.next();
-                    ops.push((Op::Slash, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::Ampersand)) => {
-                    self.tokens.next();
-                    ops.push((Op::Ampersand, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::VerticalBar)) => {
-                    self.tokens.next();
-                    ops.push((Op::VerticalBar, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::LessThan)) => {
-                    self.tokens.next();
-                    ops.push((Op::LessThan, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::GreaterThan)) => {
-                    self.tokens.next();
-                    ops.push((Op::GreaterThan, self.parse_term()?))
-                }
-                Some(&Token::Symbol(Symbol::Equals)) => {
-                    self.tokens.next();
-                    ops.push((Op::Equals, self.parse_term()?))
-                }
-                _ => break,
-            }
+        while let Some(op) = self.peek().and_then(binary_op) {
+            self.next_token()?;
+            ops.push((op, self.parse_term()?))
         }
-        Ok(Expression { term, ops })
+        Ok(Expression {
+            id,
+            term,
+            ops,
+            span: start.to(self.last_span),
+        })
     }
 
-    fn parse_term(&mut self) -> Result<Term, String> {
-        let next = self
-            .tokens
-            .next()
-            .ok_or(String::from("Unexpected end of input, expected term"))?;
-        let term = match next {
+    fn parse_term(&mut self) -> Result<Term, Diagnostic> {
+        let id = self.node_id();
+        let (next, span) = self
+            .next_token()?
+            .ok_or_else(|| self.unexpected_end("term"))?;
+        let kind = match next {
             // integerConstant
-            Token::IntegerConstant(int) => Term::IntegerConstant(int),
+            Token::IntegerConstant(int) => TermKind::IntegerConstant(int),
             // stringConstant
-            Token::StringConstant(string) => Term::StringConstant(string),
+            Token::StringConstant(string) => TermKind::StringConstant(string),
             // keywordConstant
-            Token::Keyword(Keyword::True) => Term::KeywordConstant(KeywordConstant::True),
-            Token::Keyword(Keyword::False) => Term::KeywordConstant(KeywordConstant::False),
-            Token::Keyword(Keyword::Null) => Term::KeywordConstant(KeywordConstant::Null),
-            Token::Keyword(Keyword::This) => Term::KeywordConstant(KeywordConstant::This),
+            Token::Keyword(Keyword::True) => TermKind::KeywordConstant(KeywordConstant::True),
+            Token::Keyword(Keyword::False) => TermKind::KeywordConstant(KeywordConstant::False),
+            Token::Keyword(Keyword::Null) => TermKind::KeywordConstant(KeywordConstant::Null),
+            Token::Keyword(Keyword::This) => TermKind::KeywordConstant(KeywordConstant::This),
+            Token::Keyword(Keyword::Super) => TermKind::KeywordConstant(KeywordConstant::Super),
             // different possibilities:
-            Token::Identifier(var_name) => match self.tokens.peek() {
-              // varName[expression]
-              Some(&Token::Symbol(Symbol::BracketOpen)) => {
-                self.tokens.next();
-                let expression = self.parse_expression()?;
-                self.expect_token(Token::Symbol(Symbol::BracketClose))?;
-                Term::VarNameExpression((var_name, Box::new(expression)))
-              }
-              // subroutineName(expressionList)
-              Some(&Token::Symbol(Symbol::ParenOpen))
-              // classOrVarName.subroutineName
-              | Some(&Token::Symbol(Symbol::Period)) => Term::SubroutineCall(self.parse_subroutine_call(var_name)?),
-              // varName
-              _ => Term::VarName(var_name)
-            },
+            Token::Identifier(var_name) => {
+                let var_name = Identifier::new(var_name, span);
+                match self.peek() {
+                    // varName[expression]
+                    Some(&Token::Symbol(Symbol::BracketOpen)) => {
+                        self.next_token()?;
+                        let expression = self.parse_expression()?;
+                        self.expect_token(Token::Symbol(Symbol::BracketClose))?;
+                        TermKind::VarNameExpression((var_name, Box::new(expression)))
+                    }
+                    // subroutineName(expressionList)
+                    Some(&Token::Symbol(Symbol::ParenOpen)) => {
+                        TermKind::SubroutineCall(self.parse_subroutine_call(var_name)?)
+                    }
+                    // classOrVarName.subroutineName(expressionList) or name.member
+                    Some(&Token::Symbol(Symbol::Period)) => {
+                        self.next_token()?;
+                        let member = self.expect_identifier()?;
+                        if self.peek() == Some(&Token::Symbol(Symbol::ParenOpen)) {
+                            TermKind::SubroutineCall(
+                                self.parse_call_arguments(Some(var_name), member)?,
+                            )
+                        } else if self.extensions.is_enabled(Extension::Constants)
+                            || self.extensions.is_enabled(Extension::FieldAccess)
+                        {
+                            TermKind::MemberAccess((var_name, member))
+                        } else {
+                            return Err(self
+                                .expect_token(Token::Symbol(Symbol::ParenOpen))
+                                .unwrap_err()
+                                .with_help(
+                                    "constants like `Foo.NAME` need the `constants` extension, \
+                                     and fields like `obj.field` need the `field-access` extension",
+                                ));
+                        }
+                    }
+                    // varName
+                    _ => TermKind::VarName(var_name),
+                }
+            }
             // (expression)
             Token::Symbol(Symbol::ParenOpen) => {
                 let expression = self.parse_expression()?;
                 self.expect_token(Token::Symbol(Symbol::ParenClose))?;
-                Term::Expression(Box::new(expression))
+                TermKind::Expression(Box::new(expression))
             }
             // unaryOp term
             Token::Symbol(Symbol::Minus) => {
-                Term::UnaryOpTerm((UnaryOp::Minus, Box::new(self.parse_term()?)))
+                TermKind::UnaryOpTerm((UnaryOp::Minus, Box::new(self.parse_term()?)))
             }
             Token::Symbol(Symbol::Tilde) => {
-                Term::UnaryOpTerm((UnaryOp::Tilde, Box::new(self.parse_term()?)))
+                TermKind::UnaryOpTerm((UnaryOp::Tilde, Box::new(self.parse_term()?)))
             }
-            token => return Err(format!("Unexpected token: {:?}, expected term", token)),
+            token => return Err(self.unexpected_token(&token, span, "term")),
         };
+        let mut term = Term { id, kind };
+        // term.subroutineName(expressionList), where a unary op applies to the whole chain
+        if !matches!(term.kind, TermKind::UnaryOpTerm(_)) {
+            while self.peek() == Some(&Token::Symbol(Symbol::Period)) {
+                let span = self.peek_span();
+                self.next_token()?;
+                // `super.name()` is the only call on a term that doesn't need call chains
+                let is_super =
+                    matches!(term.kind, TermKind::KeywordConstant(KeywordConstant::Super));
+                if !self.extensions.is_enabled(Extension::CallChains) && !is_super {
+                    return Err(self
+                        .unexpected_token(&Token::Symbol(Symbol::Period), span, "an operator")
+                        .with_help("calls like `a.b().c()` need the `call-chains` extension"));
+                }
+                // The call starts where its receiver does, so it takes the receiver's id and the
+                // receiver's nodes, which have the ids after it, move up by one
+                let id = term.id;
+                ShiftIds.visit_term_mut(&mut term);
+                self.node_id();
+                let subroutine_name = self.expect_identifier()?;
+                let call = self.parse_call_arguments(None, subroutine_name)?;
+                term = Term {
+                    id,
+                    kind: TermKind::SubroutineCall(SubroutineCall {
+                        receiver: Some(Box::new(term)),
+                        ..call
+                    }),
+                };
+            }
+        }
         Ok(term)
     }
 
-    fn parse_subroutine_call(&mut self, identifier: String) -> Result<SubroutineCall, String> {
-        let (class_or_var_name, subroutine_name) = match self.tokens.next() {
-            Some(Token::Symbol(Symbol::ParenOpen)) => (None, identifier),
+    fn parse_subroutine_call(
+        &mut self,
+        identifier: Identifier,
+    ) -> Result<SubroutineCall, Diagnostic> {
+        match self.peek() {
+            Some(Token::Symbol(Symbol::ParenOpen)) => self.parse_call_arguments(None, identifier),
             Some(Token::Symbol(Symbol::Period)) => {
+                self.next_token()?;
                 let subroutine_name = self.expect_identifier()?;
-                self.expect_token(Token::Symbol(Symbol::ParenOpen))?;
-                let class_or_var_name = identifier;
-                (Some(class_or_var_name), subroutine_name)
-            }
-            Some(token) => {
-                return Err(format!(
-                    "Unexpected token: {:?}, expected '(' or '.' for subroutine call",
-                    token
-                ))
-            }
-            None => {
-                return Err(String::from(
-                    "Unexpected end of input, expected subroutine call",
-                ))
+                self.parse_call_arguments(Some(identifier), subroutine_name)
             }
-        };
+            _ => match self.next_token()? {
+                Some((token, span)) => {
+                    Err(self.unexpected_token(&token, span, "`(` or `.` for subroutine call"))
+                }
+                None => Err(self.unexpected_end("subroutine call")),
+            },
+        }
+    }
 
+    /// The `(expressionList)` of a call whose name has already been parsed
+    fn parse_call_arguments(
+        &mut self,
+        class_or_var_name: Option<Identifier>,
+        subroutine_name: Identifier,
+    ) -> Result<SubroutineCall, Diagnostic> {
+        self.expect_token(Token::Symbol(Symbol::ParenOpen))?;
         // expression list
-        let expression_list = if self.tokens.peek() == Some(&Token::Symbol(Symbol::ParenClose)) {
-            self.tokens.next();
+        let expression_list = if self.peek() == Some(&Token::Symbol(Symbol::ParenClose)) {
+            self.next_token()?;
             Vec::new()
         } else {
             let mut expression_list = vec![self.parse_expression()?];
-            while self.tokens.peek() == Some(&Token::Symbol(Symbol::Comma)) {
-                self.tokens.next();
+            while self.peek() == Some(&Token::Symbol(Symbol::Comma)) {
+                self.next_token()?;
                 expression_list.push(self.parse_expression()?);
             }
             self.expect_token(Token::Symbol(Symbol::ParenClose))?;
             expression_list
         };
         Ok(SubroutineCall {
+            receiver: None,
             class_or_var_name,
             subroutine_name,
             expression_list,
         })
     }
 }
+
+/// The operator that a token stands for between two terms
+fn binary_op(token: &Token) -> Option<Op> {
+    match token {
+        Token::Symbol(Symbol::Plus) => Some(Op::Plus),
+        Token::Symbol(Symbol::Minus) => Some(Op::Minus),
+        Token::Symbol(Symbol::Asterix) => Some(Op::Asterix),
+        Token::Symbol(Symbol::Slash) => Some(Op::Slash),
+        Token::Symbol(Symbol::Ampersand) => Some(Op::Ampersand),
+        Token::Symbol(Symbol::VerticalBar) => Some(Op::VerticalBar),
+        Token::Symbol(Symbol::LessThan) => Some(Op::LessThan),
+        Token::Symbol(Symbol::GreaterThan) => Some(Op::GreaterThan),
+        Token::Symbol(Symbol::Equals) => Some(Op::Equals),
+        Token::Symbol(Symbol::LessThanEquals) => Some(Op::LessThanEquals),
+        Token::Symbol(Symbol::GreaterThanEquals) => Some(Op::GreaterThanEquals),
+        Token::Symbol(Symbol::NotEquals) => Some(Op::NotEquals),
+        Token::Symbol(Symbol::DoubleAmpersand) => Some(Op::DoubleAmpersand),
+        Token::Symbol(Symbol::DoubleVerticalBar) => Some(Op::DoubleVerticalBar),
+        Token::Symbol(Symbol::Percent) => Some(Op::Percent),
+        Token::Symbol(Symbol::DoubleLessThan) => Some(Op::DoubleLessThan),
+        Token::Symbol(Symbol::DoubleGreaterThan) => Some(Op::DoubleGreaterThan),
+        _ => None,
+    }
+}
+
+/// The operator that a compound assignment like `+=` applies
+fn compound_assignment_op(token: &Token) -> Option<Op> {
+    match token {
+        Token::Symbol(Symbol::PlusEquals) => Some(Op::Plus),
+        Token::Symbol(Symbol::MinusEquals) => Some(Op::Minus),
+        Token::Symbol(Symbol::AsterixEquals) => Some(Op::Asterix),
+        Token::Symbol(Symbol::SlashEquals) => Some(Op::Slash),
+        Token::Symbol(Symbol::AmpersandEquals) => Some(Op::Ampersand),
+        Token::Symbol(Symbol::VerticalBarEquals) => Some(Op::VerticalBar),
+        _ => None,
+    }
+}
+
+/// Adds one to the id of every node in a term, to make room for a node that comes before it
+struct ShiftIds;
+
+impl VisitorMut for ShiftIds {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        expression.id.0 += 1;
+        walk_expression_mut(self, expression)
+    }
+
+    fn visit_term_mut(&mut self, term: &mut Term) {
+        term.id.0 += 1;
+        walk_term_mut(self, term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chained_calls_take_their_id_before_their_receiver() {
+        let class = parse_source("class A { function int f() { return a.b(1).c(2); } }");
+        let statement = &class.subroutine_declarations[0].body.statements[0];
+        let expression = match statement {
+            Statement::Return(statement) => statement.expression.as_ref().unwrap(),
+            _ => panic!("not a return statement"),
+        };
+        let call = &expression.term;
+        let receiver = match &call.kind {
+            TermKind::SubroutineCall(call) => call.receiver.as_ref().unwrap(),
+            kind => panic!("not a call: {:?}", kind),
+        };
+        assert_eq!(expression.id, NodeId(2));
+        assert_eq!(call.id, NodeId(3));
+        // `a.b(1)` and its argument, then the argument of `c`
+        assert_eq!(receiver.id, NodeId(4));
+        let ids: Vec<u32> = match &call.kind {
+            TermKind::SubroutineCall(call) => call
+                .expression_list
+                .iter()
+                .map(|expression| expression.id.0)
+                .collect(),
+            _ => unreachable!(),
+        };
+        assert_eq!(ids, vec![7]);
+    }
+}
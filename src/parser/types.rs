@@ -1,59 +1,106 @@
-use crate::ToXml;
-use std::iter;
+use super::{Node, NodeId};
+use crate::diagnostics::Span;
+use crate::{ToXml, XmlWriter};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+impl Identifier {
+    pub fn new(name: impl Into<String>, span: Span) -> Identifier {
+        Identifier {
+            name: name.into(),
+            span,
+        }
+    }
+}
+
+// Identifiers are compared by name only, so the same type
+// written in two different places is still the same type
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Identifier) -> bool {
+        self.name == other.name
+    }
+}
 
-pub type Identifier = String;
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
 
 impl ToXml for Identifier {
-    fn to_xml(&self) -> String {
-        format!("<identifier> {} </identifier>", self)
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.leaf("identifier", &self.name)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Class {
     pub class_name: Identifier,
+    /// The class this one extends
+    pub superclass: Option<Identifier>,
     pub class_var_declarations: Vec<ClassVarDeclaration>,
+    pub constant_declarations: Vec<ConstantDeclaration>,
+    pub enum_declarations: Vec<EnumDeclaration>,
     pub subroutine_declarations: Vec<SubroutineDeclaration>,
 }
 
 impl ToXml for Class {
-    fn to_xml(&self) -> String {
-        format!(
-            "<class>
-    <keyword> class </keyword>
-    {}
-    <symbol> {{ </symbol>
-      {}
-      {}
-    <symbol> }} </symbol>
-  </class>",
-            self.class_name.to_xml(),
-            self.class_var_declarations.to_xml(),
-            self.subroutine_declarations.to_xml()
-        )
-    }
-}
-
-#[derive(PartialEq, Debug, Clone, Copy)]
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("class")?;
+        xml.keyword("class")?;
+        self.class_name.write_xml(xml)?;
+        if let Some(superclass) = &self.superclass {
+            xml.keyword("extends")?;
+            superclass.write_xml(xml)?;
+        }
+        xml.symbol("{")?;
+        // The kinds of declarations can be mixed, and their ids are in source order
+        let mut declarations: Vec<Node> = self
+            .class_var_declarations
+            .iter()
+            .map(Node::ClassVarDeclaration)
+            .chain(
+                self.constant_declarations
+                    .iter()
+                    .map(Node::ConstantDeclaration),
+            )
+            .chain(self.enum_declarations.iter().map(Node::EnumDeclaration))
+            .collect();
+        declarations.sort_by_key(Node::id);
+        for declaration in declarations {
+            declaration.write_xml(xml)?;
+        }
+        for declaration in self.subroutine_declarations.iter() {
+            declaration.write_xml(xml)?;
+        }
+        xml.symbol("}")?;
+        xml.close("class")
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum StaticOrField {
     Static,
     Field,
 }
 
 impl ToXml for StaticOrField {
-    fn to_xml(&self) -> String {
-        format!(
-            "<keyword> {} </keyword>",
-            if self == &StaticOrField::Static {
-                "static"
-            } else {
-                "field"
-            }
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.keyword(match self {
+            StaticOrField::Static => "static",
+            StaticOrField::Field => "field",
+        })
     }
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum VarType {
     Int,
     Char,
@@ -61,47 +108,89 @@ pub enum VarType {
     ClassName(Identifier),
 }
 
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VarType::Int => f.write_str("int"),
+            VarType::Char => f.write_str("char"),
+            VarType::Boolean => f.write_str("boolean"),
+            VarType::ClassName(class_name) => class_name.fmt(f),
+        }
+    }
+}
+
 impl ToXml for VarType {
-    fn to_xml(&self) -> String {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
         match self {
-            VarType::Int => String::from("<keyword> int </keyword>"),
-            VarType::Char => String::from("<keyword> char </keyword>"),
-            VarType::Boolean => String::from("<keyword> boolean </keyword>"),
-            VarType::ClassName(class_name) => class_name.to_xml(),
+            VarType::ClassName(class_name) => class_name.write_xml(xml),
+            _ => xml.keyword(&self.to_string()),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ClassVarDeclaration {
+    pub id: NodeId,
     pub static_or_field: StaticOrField,
     pub var_type: VarType,
     pub var_names: Vec<Identifier>,
 }
 
 impl ToXml for ClassVarDeclaration {
-    fn to_xml(&self) -> String {
-        format!(
-            "<classVarDec>
-  {}
-  {}
-  {}
-  <symbol> ; </symbol>
-</classVarDec>",
-            self.static_or_field.to_xml(),
-            self.var_type.to_xml(),
-            intersperse_with(&self.var_names, "\n<symbol> , </symbol>\n"),
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("classVarDec")?;
+        self.static_or_field.write_xml(xml)?;
+        self.var_type.write_xml(xml)?;
+        write_separated(xml, &self.var_names)?;
+        xml.symbol(";")?;
+        xml.close("classVarDec")
     }
 }
 
-impl ToXml for Vec<ClassVarDeclaration> {
-    fn to_xml(&self) -> String {
-        intersperse_with(self, "\n")
+/** 'const' var_type name '=' expression ';' */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConstantDeclaration {
+    pub id: NodeId,
+    pub var_type: VarType,
+    pub name: Identifier,
+    /// Evaluated at compile time, so it can only be made of constants
+    pub expression: Expression,
+}
+
+impl ToXml for ConstantDeclaration {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("constDec")?;
+        xml.keyword("const")?;
+        self.var_type.write_xml(xml)?;
+        self.name.write_xml(xml)?;
+        xml.symbol("=")?;
+        self.expression.write_xml(xml)?;
+        xml.symbol(";")?;
+        xml.close("constDec")
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Copy)]
+/// `'enum' name '{' variant (',' variant)* '}'`, where the variants are numbered from 0
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnumDeclaration {
+    pub id: NodeId,
+    pub name: Identifier,
+    pub variants: Vec<Identifier>,
+}
+
+impl ToXml for EnumDeclaration {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("enumDec")?;
+        xml.keyword("enum")?;
+        self.name.write_xml(xml)?;
+        xml.symbol("{")?;
+        write_separated(xml, &self.variants)?;
+        xml.symbol("}")?;
+        xml.close("enumDec")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
 pub enum SubroutineType {
     Constructor,
     Function,
@@ -109,12 +198,12 @@ pub enum SubroutineType {
 }
 
 impl ToXml for SubroutineType {
-    fn to_xml(&self) -> String {
-        match self {
-            &SubroutineType::Constructor => String::from("<keyword> constructor </keyword>"),
-            &SubroutineType::Function => String::from("<keyword> function </keyword>"),
-            &SubroutineType::Method => String::from("<keyword> method </keyword>"),
-        }
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.keyword(match self {
+            SubroutineType::Constructor => "constructor",
+            SubroutineType::Function => "function",
+            SubroutineType::Method => "method",
+        })
     }
 }
 
@@ -122,23 +211,22 @@ pub type ParameterName = Identifier;
 pub type Parameter = (VarType, ParameterName);
 
 impl ToXml for Vec<Parameter> {
-    fn to_xml(&self) -> String {
-        let parameters = self
-            .iter()
-            .map(|p| format!("{}\n{}\n", p.0.to_xml(), p.1.to_xml()))
-            .collect::<Vec<String>>();
-        let parameters = parameters.join("\n<symbol> , </symbol>\n");
-        format!(
-            "<parameterList>
-  {}
-</parameterList>",
-            parameters
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("parameterList")?;
+        for (index, (var_type, name)) in self.iter().enumerate() {
+            if index > 0 {
+                xml.symbol(",")?;
+            }
+            var_type.write_xml(xml)?;
+            name.write_xml(xml)?;
+        }
+        xml.close("parameterList")
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SubroutineDeclaration {
+    pub id: NodeId,
     pub subroutine_type: SubroutineType,
     pub return_type: Option<VarType>,
     pub name: Identifier,
@@ -147,284 +235,355 @@ pub struct SubroutineDeclaration {
 }
 
 impl ToXml for SubroutineDeclaration {
-    fn to_xml(&self) -> String {
-        format!(
-            "<subroutineDec>
-  {subroutine_type}
-  {return_type}
-  {name}
-  <symbol> ( </symbol>
-  {parameter_list}
-  <symbol> ) </symbol>
-  {body}
-</subroutineDec>",
-            subroutine_type = self.subroutine_type.to_xml(),
-            return_type = if let Some(ref return_type) = self.return_type {
-                return_type.to_xml()
-            } else {
-                String::from("<keyword> void </keyword>")
-            },
-            name = self.name.to_xml(),
-            parameter_list = self.parameter_list.to_xml(),
-            body = self.body.to_xml()
-        )
-    }
-}
-
-impl ToXml for Vec<SubroutineDeclaration> {
-    fn to_xml(&self) -> String {
-        intersperse_with(&self, "\n")
-    }
-}
-
-#[derive(Debug)]
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("subroutineDec")?;
+        self.subroutine_type.write_xml(xml)?;
+        match &self.return_type {
+            Some(return_type) => return_type.write_xml(xml)?,
+            None => xml.keyword("void")?,
+        }
+        self.name.write_xml(xml)?;
+        xml.symbol("(")?;
+        self.parameter_list.write_xml(xml)?;
+        xml.symbol(")")?;
+        self.body.write_xml(xml)?;
+        xml.close("subroutineDec")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SubroutineBody {
     pub var_declarations: Vec<VarDeclaration>,
     pub statements: Vec<Statement>,
 }
 
 impl ToXml for SubroutineBody {
-    fn to_xml(&self) -> String {
-        format!(
-            "<subroutineBody>
-<symbol> {{ </symbol>
-{}
-{}
-<symbol> }} </symbol>
-</subroutineBody>",
-            self.var_declarations.to_xml(),
-            self.statements.to_xml()
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("subroutineBody")?;
+        xml.symbol("{")?;
+        for declaration in self.var_declarations.iter() {
+            declaration.write_xml(xml)?;
+        }
+        self.statements.write_xml(xml)?;
+        xml.symbol("}")?;
+        xml.close("subroutineBody")
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct VarDeclaration {
+    pub id: NodeId,
     pub var_type: VarType,
     pub var_names: Vec<Identifier>,
 }
 
 impl ToXml for VarDeclaration {
-    fn to_xml(&self) -> String {
-        format!(
-            "<varDec>
-<keyword> var </keyword>
-  {}
-  {}
-<symbol> ; </symbol>
-</varDec>
-",
-            self.var_type.to_xml(),
-            intersperse_with(&self.var_names, "\n<symbol> , </symbol>\n")
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("varDec")?;
+        xml.keyword("var")?;
+        self.var_type.write_xml(xml)?;
+        write_separated(xml, &self.var_names)?;
+        xml.symbol(";")?;
+        xml.close("varDec")
     }
 }
 
-impl ToXml for Vec<VarDeclaration> {
-    fn to_xml(&self) -> String {
-        self.iter()
-            .map(|v| v.to_xml())
-            .collect::<Vec<String>>()
-            .join("")
-    }
-}
-
-#[derive(Debug)]
+// Statements are built once and walked in place, so boxing the larger ones wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Statement {
     Let(LetStatement),
     If(IfStatement),
     While(WhileStatement),
     Do(DoStatement),
     Return(ReturnStatement),
+    For(ForStatement),
+    Break(BreakStatement),
+    Continue(ContinueStatement),
+}
+
+impl Statement {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Statement::Let(s) => s.id,
+            Statement::If(s) => s.id,
+            Statement::While(s) => s.id,
+            Statement::Do(s) => s.id,
+            Statement::Return(s) => s.id,
+            Statement::For(s) => s.id,
+            Statement::Break(s) => s.id,
+            Statement::Continue(s) => s.id,
+        }
+    }
 }
 
 impl ToXml for Statement {
-    fn to_xml(&self) -> String {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
         match self {
-            Statement::Let(s) => s.to_xml(),
-            Statement::If(s) => s.to_xml(),
-            Statement::While(s) => s.to_xml(),
-            Statement::Do(s) => s.to_xml(),
-            Statement::Return(s) => s.to_xml(),
+            Statement::Let(s) => s.write_xml(xml),
+            Statement::If(s) => s.write_xml(xml),
+            Statement::While(s) => s.write_xml(xml),
+            Statement::Do(s) => s.write_xml(xml),
+            Statement::Return(s) => s.write_xml(xml),
+            Statement::For(s) => s.write_xml(xml),
+            Statement::Break(s) => s.write_xml(xml),
+            Statement::Continue(s) => s.write_xml(xml),
         }
     }
 }
 
 impl ToXml for Vec<Statement> {
-    fn to_xml(&self) -> String {
-        iter::once(String::from("<statements>"))
-            .chain(self.iter().map(|s| s.to_xml()))
-            .chain(iter::once(String::from("</statements>")))
-            .collect::<Vec<String>>()
-            .join("\n")
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("statements")?;
+        for statement in self.iter() {
+            statement.write_xml(xml)?;
+        }
+        xml.close("statements")
     }
 }
 
-/** 'let' var_name ('[' left_side_expression ']')? '=' expression ';' */
-#[derive(Debug)]
+/** 'let' var_name ('[' left_side_expression ']' | '.' field)? op? '=' expression ';' */
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LetStatement {
+    pub id: NodeId,
     pub var_name: Identifier,
     pub left_side_expression: Option<Expression>,
+    /// The field that is assigned in the object `var_name` refers to
+    pub field: Option<Identifier>,
+    /// The operator of a compound assignment like `+=`, which combines the old value with the
+    /// right side
+    pub op: Option<Op>,
     pub right_side_expression: Expression,
 }
 
 impl ToXml for LetStatement {
-    fn to_xml(&self) -> String {
-        let left_side_expression = if let Some(ref expression) = self.left_side_expression {
-            format!(
-                "<symbol> [ </symbol>
-{}
-<symbol> ] </symbol>",
-                expression.to_xml()
-            )
-        } else {
-            String::new()
-        };
-        format!(
-            "<letStatement>
-  <keyword> let </keyword>
-  {}
-  {}
-  <symbol> = </symbol>
-  {}
-  <symbol> ; </symbol>
-</letStatement>",
-            self.var_name.to_xml(),
-            left_side_expression,
-            self.right_side_expression.to_xml()
-        )
-    }
-}
-
-#[derive(Debug)]
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        self.write_unterminated(xml)?;
+        xml.symbol(";")?;
+        xml.close("letStatement")
+    }
+}
+
+impl LetStatement {
+    /// Writes everything but the `;` and closing tag, so that the update of a `for` loop
+    /// can leave out the `;`
+    fn write_unterminated<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("letStatement")?;
+        xml.keyword("let")?;
+        self.var_name.write_xml(xml)?;
+        if let Some(expression) = &self.left_side_expression {
+            xml.symbol("[")?;
+            expression.write_xml(xml)?;
+            xml.symbol("]")?;
+        }
+        if let Some(field) = &self.field {
+            xml.symbol(".")?;
+            field.write_xml(xml)?;
+        }
+        match &self.op {
+            Some(op) => xml.symbol(&format!("{}=", op.as_ref()))?,
+            None => xml.symbol("=")?,
+        }
+        self.right_side_expression.write_xml(xml)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct IfStatement {
+    pub id: NodeId,
     pub expression: Expression,
     pub if_statements: Vec<Statement>,
     pub else_statements: Option<Vec<Statement>>,
+    /// Whether this statement was written as `else if`, in which case it is
+    /// the only statement in the else branch of the statement before it
+    pub else_if: bool,
+}
+
+impl IfStatement {
+    /// The next statement in an `else if` chain
+    pub fn next_else_if(&self) -> Option<&IfStatement> {
+        match self.else_statements.as_deref() {
+            Some([Statement::If(statement)]) if statement.else_if => Some(statement),
+            _ => None,
+        }
+    }
 }
 
 impl ToXml for IfStatement {
-    fn to_xml(&self) -> String {
-        let else_statements = if let Some(else_statements) = &self.else_statements {
-            format!(
-                "<keyword> else </keyword>
-<symbol> {{ </symbol>
-{}
-<symbol> }} </symbol>
-",
-                else_statements.to_xml()
-            )
-        } else {
-            String::new()
-        };
-
-        format!(
-            "<ifStatement>
-<keyword> if </keyword>
-  <symbol> ( </symbol>
-  {}
-  <symbol> ) </symbol>
-  <symbol> {{ </symbol>
-  {}
-  <symbol> }} </symbol>
-{}
-</ifStatement>",
-            self.expression.to_xml(),
-            if self.if_statements.is_empty() {
-                String::new()
-            } else {
-                self.if_statements.to_xml()
-            },
-            else_statements
-        )
-    }
-}
-
-#[derive(Debug)]
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("ifStatement")?;
+        xml.keyword("if")?;
+        xml.symbol("(")?;
+        self.expression.write_xml(xml)?;
+        xml.symbol(")")?;
+        xml.symbol("{")?;
+        self.if_statements.write_xml(xml)?;
+        xml.symbol("}")?;
+        // The spec has no `else if`, so a chain is written as an `if` nested in an `else` block
+        if let Some(else_statements) = &self.else_statements {
+            xml.keyword("else")?;
+            xml.symbol("{")?;
+            else_statements.write_xml(xml)?;
+            xml.symbol("}")?;
+        }
+        xml.close("ifStatement")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WhileStatement {
+    pub id: NodeId,
     pub expression: Expression,
     pub statements: Vec<Statement>,
 }
 
 impl ToXml for WhileStatement {
-    fn to_xml(&self) -> String {
-        format!(
-            "<whileStatement>
-  <keyword> while </keyword>
-  <symbol> ( </symbol>
-  {}
-  <symbol> ) </symbol>
-  <symbol> {{ </symbol>
-  {}
-  <symbol> }} </symbol>
-</whileStatement>",
-            self.expression.to_xml(),
-            self.statements.to_xml()
-        )
-    }
-}
-
-#[derive(Debug)]
-pub struct DoStatement(pub SubroutineCall);
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("whileStatement")?;
+        xml.keyword("while")?;
+        xml.symbol("(")?;
+        self.expression.write_xml(xml)?;
+        xml.symbol(")")?;
+        xml.symbol("{")?;
+        self.statements.write_xml(xml)?;
+        xml.symbol("}")?;
+        xml.close("whileStatement")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoStatement {
+    pub id: NodeId,
+    pub subroutine_call: SubroutineCall,
+}
 
 impl ToXml for DoStatement {
-    fn to_xml(&self) -> String {
-        format!(
-            "<doStatement>
-<keyword> do </keyword>
-{}
-<symbol> ; </symbol>
-</doStatement>",
-            self.0.to_xml()
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("doStatement")?;
+        xml.keyword("do")?;
+        self.subroutine_call.write_xml(xml)?;
+        xml.symbol(";")?;
+        xml.close("doStatement")
     }
 }
 
-#[derive(Debug)]
-pub struct ReturnStatement(pub Option<Expression>);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReturnStatement {
+    pub id: NodeId,
+    pub expression: Option<Expression>,
+}
 
 impl ToXml for ReturnStatement {
-    fn to_xml(&self) -> String {
-        format!(
-            "<returnStatement>
-  <keyword> return </keyword>
-  {}
-  <symbol> ; </symbol>
-</returnStatement>",
-            if let Some(expression) = &self.0 {
-                format!("{}\n", expression.to_xml())
-            } else {
-                String::new()
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("returnStatement")?;
+        xml.keyword("return")?;
+        if let Some(expression) = &self.expression {
+            expression.write_xml(xml)?;
+        }
+        xml.symbol(";")?;
+        xml.close("returnStatement")
+    }
+}
+
+/// `'for' '(' initializer? ';' condition? ';' update? ')' '{' statements '}'`,
+/// where the initializer and update are `let` statements without their `;`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForStatement {
+    pub id: NodeId,
+    pub initializer: Option<Box<Statement>>,
+    pub condition: Option<Expression>,
+    pub update: Option<Box<Statement>>,
+    pub statements: Vec<Statement>,
+}
+
+impl ToXml for ForStatement {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("forStatement")?;
+        xml.keyword("for")?;
+        xml.symbol("(")?;
+        // The initializer's `;` is the first separator
+        match &self.initializer {
+            Some(initializer) => initializer.write_xml(xml)?,
+            None => xml.symbol(";")?,
+        }
+        if let Some(condition) = &self.condition {
+            condition.write_xml(xml)?;
+        }
+        xml.symbol(";")?;
+        match self.update.as_deref() {
+            Some(Statement::Let(update)) => {
+                update.write_unterminated(xml)?;
+                xml.close("letStatement")?;
             }
-        )
+            Some(update) => update.write_xml(xml)?,
+            None => {}
+        }
+        xml.symbol(")")?;
+        xml.symbol("{")?;
+        self.statements.write_xml(xml)?;
+        xml.symbol("}")?;
+        xml.close("forStatement")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BreakStatement {
+    pub id: NodeId,
+    pub span: Span,
+}
+
+impl ToXml for BreakStatement {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("breakStatement")?;
+        xml.keyword("break")?;
+        xml.symbol(";")?;
+        xml.close("breakStatement")
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContinueStatement {
+    pub id: NodeId,
+    pub span: Span,
+}
+
+impl ToXml for ContinueStatement {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("continueStatement")?;
+        xml.keyword("continue")?;
+        xml.symbol(";")?;
+        xml.close("continueStatement")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Expression {
+    pub id: NodeId,
     pub term: Term,
     pub ops: Vec<(Op, Term)>,
+    pub span: Span,
 }
 
 impl ToXml for Expression {
-    fn to_xml(&self) -> String {
-        format!(
-            "<expression>
-{}
-{}
-</expression>",
-            self.term.to_xml(),
-            self.ops
-                .iter()
-                .flat_map(|(op, term)| vec![op.to_xml(), term.to_xml()].into_iter())
-                .collect::<Vec<String>>()
-                .join("\n")
-        )
-    }
-}
-
-#[derive(Debug)]
-pub enum Term {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("expression")?;
+        self.term.write_xml(xml)?;
+        for (op, term) in self.ops.iter() {
+            op.write_xml(xml)?;
+            term.write_xml(xml)?;
+        }
+        xml.close("expression")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Term {
+    pub id: NodeId,
+    pub kind: TermKind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum TermKind {
     IntegerConstant(u16),
     StringConstant(String),
     KeywordConstant(KeywordConstant),
@@ -433,76 +592,74 @@ pub enum Term {
     SubroutineCall(SubroutineCall),
     Expression(Box<Expression>),
     UnaryOpTerm((UnaryOp, Box<Term>)),
+    /// `name.member` without a call, which is a constant of another class, an enum variant,
+    /// or a field of the object in a variable
+    MemberAccess((Identifier, Identifier)),
 }
 
 impl ToXml for Term {
-    fn to_xml(&self) -> String {
-        let inner = match &self {
-            &Term::IntegerConstant(int) => format!("<integerConstant> {} </integerConstant>", int),
-            &Term::StringConstant(string) => {
-                format!("<stringConstant> {} </stringConstant>", string)
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("term")?;
+        match &self.kind {
+            TermKind::IntegerConstant(int) => xml.leaf("integerConstant", &int.to_string())?,
+            TermKind::StringConstant(string) => xml.leaf("stringConstant", string)?,
+            TermKind::KeywordConstant(keyword) => keyword.write_xml(xml)?,
+            TermKind::VarName(var_name) => var_name.write_xml(xml)?,
+            TermKind::VarNameExpression((var_name, expression)) => {
+                var_name.write_xml(xml)?;
+                xml.symbol("[")?;
+                expression.write_xml(xml)?;
+                xml.symbol("]")?;
+            }
+            TermKind::SubroutineCall(subroutine_call) => subroutine_call.write_xml(xml)?,
+            TermKind::Expression(expression) => {
+                xml.symbol("(")?;
+                expression.write_xml(xml)?;
+                xml.symbol(")")?;
             }
-            &Term::KeywordConstant(keyword) => keyword.to_xml(),
-            &Term::VarName(var_name) => var_name.to_xml(),
-            &Term::VarNameExpression((var_name, expression)) => format!(
-                "{}
-<symbol> [ </symbol>
-{}
-<symbol> ] </symbol>",
-                var_name.to_xml(),
-                expression.to_xml()
-            ),
-            &Term::SubroutineCall(subroutine_call) => subroutine_call.to_xml(),
-            &Term::Expression(expression) => format!(
-                "<symbol> ( </symbol>
-{}
-<symbol> ) </symbol>",
-                expression.to_xml()
-            ),
-            &Term::UnaryOpTerm((op, term)) => format!("{}\n{}", op.to_xml(), term.to_xml()),
-        };
-        format!("<term>\n{}\n</term>", inner)
-    }
-}
-
-#[derive(Debug)]
+            TermKind::UnaryOpTerm((op, term)) => {
+                op.write_xml(xml)?;
+                term.write_xml(xml)?;
+            }
+            TermKind::MemberAccess((name, member)) => {
+                name.write_xml(xml)?;
+                xml.symbol(".")?;
+                member.write_xml(xml)?;
+            }
+        }
+        xml.close("term")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SubroutineCall {
+    /// The term whose value the method is called on, as in `a.b().c()`
+    pub receiver: Option<Box<Term>>,
     pub class_or_var_name: Option<Identifier>,
     pub subroutine_name: Identifier,
     pub expression_list: Vec<Expression>,
 }
 
 impl ToXml for SubroutineCall {
-    fn to_xml(&self) -> String {
-        let mut string = if let Some(class_or_var_name) = &self.class_or_var_name {
-            format!(
-                "{}
-<symbol> . </symbol>
-",
-                class_or_var_name.to_xml()
-            )
-        } else {
-            String::new()
-        };
-        string.push_str(&format!(
-            "{}
-<symbol> ( </symbol>
-<expressionList>
-{}
-</expressionList>
-<symbol> ) </symbol>",
-            self.subroutine_name.to_xml(),
-            self.expression_list
-                .iter()
-                .map(|e| e.to_xml())
-                .collect::<Vec<String>>()
-                .join("\n<symbol> , </symbol>\n")
-        ));
-        string
-    }
-}
-
-#[derive(Debug, PartialEq)]
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        if let Some(receiver) = &self.receiver {
+            receiver.write_xml(xml)?;
+            xml.symbol(".")?;
+        }
+        if let Some(class_or_var_name) = &self.class_or_var_name {
+            class_or_var_name.write_xml(xml)?;
+            xml.symbol(".")?;
+        }
+        self.subroutine_name.write_xml(xml)?;
+        xml.symbol("(")?;
+        xml.open("expressionList")?;
+        write_separated(xml, &self.expression_list)?;
+        xml.close("expressionList")?;
+        xml.symbol(")")
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Op {
     Plus,
     Minus,
@@ -513,6 +670,20 @@ pub enum Op {
     LessThan,
     GreaterThan,
     Equals,
+    LessThanEquals,
+    GreaterThanEquals,
+    NotEquals,
+    /// `&&`, which is `true` when neither side is 0, and only evaluates its right side if the
+    /// left side is true
+    DoubleAmpersand,
+    /// `||`, which is `true` when either side isn't 0, and only evaluates its right side if the
+    /// left side is false
+    DoubleVerticalBar,
+    /// The remainder of dividing, which has the sign of the left side
+    Percent,
+    DoubleLessThan,
+    /// Shifts right, filling the high bits with zeros
+    DoubleGreaterThan,
 }
 
 impl AsRef<str> for Op {
@@ -527,22 +698,25 @@ impl AsRef<str> for Op {
             Op::LessThan => "<",
             Op::GreaterThan => ">",
             Op::Equals => "=",
+            Op::LessThanEquals => "<=",
+            Op::GreaterThanEquals => ">=",
+            Op::NotEquals => "!=",
+            Op::DoubleAmpersand => "&&",
+            Op::DoubleVerticalBar => "||",
+            Op::Percent => "%",
+            Op::DoubleLessThan => "<<",
+            Op::DoubleGreaterThan => ">>",
         }
     }
 }
 
 impl ToXml for Op {
-    fn to_xml(&self) -> String {
-        match self {
-            Op::LessThan => String::from("<symbol> &lt; </symbol>"),
-            Op::GreaterThan => String::from("<symbol> &gt; </symbol>"),
-            Op::Ampersand => String::from("<symbol> &amp; </symbol>"),
-            _ => format!("<symbol> {} </symbol>", self.as_ref()),
-        }
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.symbol(self.as_ref())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum UnaryOp {
     Minus,
     Tilde,
@@ -558,39 +732,76 @@ impl AsRef<str> for UnaryOp {
 }
 
 impl ToXml for UnaryOp {
-    fn to_xml(&self) -> String {
-        format!("<symbol> {} </symbol>", self.as_ref())
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.symbol(self.as_ref())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum KeywordConstant {
     True,
     False,
     Null,
     This,
+    /// Only valid to call a subroutine of the parent class, as in `super.draw()`
+    Super,
 }
 
 impl AsRef<str> for KeywordConstant {
     fn as_ref(&self) -> &str {
         match self {
-            &KeywordConstant::True => "true",
-            &KeywordConstant::False => "false",
-            &KeywordConstant::Null => "null",
-            &KeywordConstant::This => "this",
+            KeywordConstant::True => "true",
+            KeywordConstant::False => "false",
+            KeywordConstant::Null => "null",
+            KeywordConstant::This => "this",
+            KeywordConstant::Super => "super",
         }
     }
 }
 
 impl ToXml for KeywordConstant {
-    fn to_xml(&self) -> String {
-        format!("<keyword> {} </keyword>", self.as_ref())
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.keyword(self.as_ref())
     }
 }
 
-fn intersperse_with(vec: &Vec<impl ToXml>, separator: &str) -> String {
-    vec.iter()
-        .map(|v| v.to_xml())
-        .collect::<Vec<String>>()
-        .join(separator)
+/// Writes the items separated by commas
+fn write_separated<W: Write>(xml: &mut XmlWriter<W>, items: &[impl ToXml]) -> io::Result<()> {
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            xml.symbol(",")?;
+        }
+        item.write_xml(xml)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    #[test]
+    fn class_declarations_are_written_in_source_order() {
+        let class = parse_source(
+            "class A { const int X = 1; static int s; enum E { P } const int Y = 2; field int f; }",
+        );
+        let tags: Vec<String> = class
+            .to_xml()
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.ends_with("Dec>") && !line.starts_with("</"))
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                "<constDec>",
+                "<classVarDec>",
+                "<enumDec>",
+                "<constDec>",
+                "<classVarDec>"
+            ]
+        );
+    }
 }
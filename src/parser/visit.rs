@@ -0,0 +1,695 @@
+//! Traversal of the AST.
+//!
+//! Each `visit_*` method calls the matching `walk_*` function by default, which visits the
+//! node's children. Override the methods for the nodes you are interested in, and call the
+//! `walk_*` function from them to keep going into the children.
+
+use super::types::*;
+
+pub trait Visitor<'ast> {
+    fn visit_class(&mut self, class: &'ast Class) {
+        walk_class(self, class)
+    }
+
+    fn visit_class_var_declaration(&mut self, declaration: &'ast ClassVarDeclaration) {
+        walk_class_var_declaration(self, declaration)
+    }
+
+    fn visit_constant_declaration(&mut self, declaration: &'ast ConstantDeclaration) {
+        walk_constant_declaration(self, declaration)
+    }
+
+    fn visit_enum_declaration(&mut self, declaration: &'ast EnumDeclaration) {
+        walk_enum_declaration(self, declaration)
+    }
+
+    fn visit_subroutine_declaration(&mut self, subroutine: &'ast SubroutineDeclaration) {
+        walk_subroutine_declaration(self, subroutine)
+    }
+
+    fn visit_var_declaration(&mut self, declaration: &'ast VarDeclaration) {
+        walk_var_declaration(self, declaration)
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        walk_statement(self, statement)
+    }
+
+    fn visit_let_statement(&mut self, statement: &'ast LetStatement) {
+        walk_let_statement(self, statement)
+    }
+
+    fn visit_if_statement(&mut self, statement: &'ast IfStatement) {
+        walk_if_statement(self, statement)
+    }
+
+    fn visit_while_statement(&mut self, statement: &'ast WhileStatement) {
+        walk_while_statement(self, statement)
+    }
+
+    fn visit_do_statement(&mut self, statement: &'ast DoStatement) {
+        walk_do_statement(self, statement)
+    }
+
+    fn visit_return_statement(&mut self, statement: &'ast ReturnStatement) {
+        walk_return_statement(self, statement)
+    }
+
+    fn visit_for_statement(&mut self, statement: &'ast ForStatement) {
+        walk_for_statement(self, statement)
+    }
+
+    fn visit_break_statement(&mut self, _statement: &'ast BreakStatement) {}
+
+    fn visit_continue_statement(&mut self, _statement: &'ast ContinueStatement) {}
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        walk_expression(self, expression)
+    }
+
+    fn visit_term(&mut self, term: &'ast Term) {
+        walk_term(self, term)
+    }
+
+    fn visit_subroutine_call(&mut self, call: &'ast SubroutineCall) {
+        walk_subroutine_call(self, call)
+    }
+
+    fn visit_var_type(&mut self, var_type: &'ast VarType) {
+        walk_var_type(self, var_type)
+    }
+
+    fn visit_identifier(&mut self, _identifier: &'ast Identifier) {}
+}
+
+pub fn walk_class<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, class: &'ast Class) {
+    visitor.visit_identifier(&class.class_name);
+    if let Some(superclass) = &class.superclass {
+        visitor.visit_identifier(superclass);
+    }
+    for declaration in class.class_var_declarations.iter() {
+        visitor.visit_class_var_declaration(declaration);
+    }
+    for declaration in class.constant_declarations.iter() {
+        visitor.visit_constant_declaration(declaration);
+    }
+    for declaration in class.enum_declarations.iter() {
+        visitor.visit_enum_declaration(declaration);
+    }
+    for subroutine in class.subroutine_declarations.iter() {
+        visitor.visit_subroutine_declaration(subroutine);
+    }
+}
+
+pub fn walk_class_var_declaration<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    declaration: &'ast ClassVarDeclaration,
+) {
+    visitor.visit_var_type(&declaration.var_type);
+    for name in declaration.var_names.iter() {
+        visitor.visit_identifier(name);
+    }
+}
+
+pub fn walk_constant_declaration<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    declaration: &'ast ConstantDeclaration,
+) {
+    visitor.visit_var_type(&declaration.var_type);
+    visitor.visit_identifier(&declaration.name);
+    visitor.visit_expression(&declaration.expression);
+}
+
+pub fn walk_enum_declaration<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    declaration: &'ast EnumDeclaration,
+) {
+    visitor.visit_identifier(&declaration.name);
+    for variant in declaration.variants.iter() {
+        visitor.visit_identifier(variant);
+    }
+}
+
+pub fn walk_subroutine_declaration<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    subroutine: &'ast SubroutineDeclaration,
+) {
+    if let Some(return_type) = &subroutine.return_type {
+        visitor.visit_var_type(return_type);
+    }
+    visitor.visit_identifier(&subroutine.name);
+    for (var_type, name) in subroutine.parameter_list.iter() {
+        visitor.visit_var_type(var_type);
+        visitor.visit_identifier(name);
+    }
+    for declaration in subroutine.body.var_declarations.iter() {
+        visitor.visit_var_declaration(declaration);
+    }
+    for statement in subroutine.body.statements.iter() {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_var_declaration<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    declaration: &'ast VarDeclaration,
+) {
+    visitor.visit_var_type(&declaration.var_type);
+    for name in declaration.var_names.iter() {
+        visitor.visit_identifier(name);
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast Statement,
+) {
+    match statement {
+        Statement::Let(statement) => visitor.visit_let_statement(statement),
+        Statement::If(statement) => visitor.visit_if_statement(statement),
+        Statement::While(statement) => visitor.visit_while_statement(statement),
+        Statement::Do(statement) => visitor.visit_do_statement(statement),
+        Statement::Return(statement) => visitor.visit_return_statement(statement),
+        Statement::For(statement) => visitor.visit_for_statement(statement),
+        Statement::Break(statement) => visitor.visit_break_statement(statement),
+        Statement::Continue(statement) => visitor.visit_continue_statement(statement),
+    }
+}
+
+pub fn walk_let_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast LetStatement,
+) {
+    visitor.visit_identifier(&statement.var_name);
+    if let Some(expression) = &statement.left_side_expression {
+        visitor.visit_expression(expression);
+    }
+    if let Some(field) = &statement.field {
+        visitor.visit_identifier(field);
+    }
+    visitor.visit_expression(&statement.right_side_expression);
+}
+
+pub fn walk_if_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast IfStatement,
+) {
+    visitor.visit_expression(&statement.expression);
+    for statement in statement.if_statements.iter() {
+        visitor.visit_statement(statement);
+    }
+    for statement in statement.else_statements.iter().flatten() {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_while_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast WhileStatement,
+) {
+    visitor.visit_expression(&statement.expression);
+    for statement in statement.statements.iter() {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_do_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast DoStatement,
+) {
+    visitor.visit_subroutine_call(&statement.subroutine_call);
+}
+
+pub fn walk_return_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast ReturnStatement,
+) {
+    if let Some(expression) = &statement.expression {
+        visitor.visit_expression(expression);
+    }
+}
+
+pub fn walk_for_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast ForStatement,
+) {
+    if let Some(initializer) = &statement.initializer {
+        visitor.visit_statement(initializer);
+    }
+    if let Some(condition) = &statement.condition {
+        visitor.visit_expression(condition);
+    }
+    if let Some(update) = &statement.update {
+        visitor.visit_statement(update);
+    }
+    for statement in statement.statements.iter() {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    expression: &'ast Expression,
+) {
+    visitor.visit_term(&expression.term);
+    for (_, term) in expression.ops.iter() {
+        visitor.visit_term(term);
+    }
+}
+
+pub fn walk_term<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, term: &'ast Term) {
+    match &term.kind {
+        TermKind::VarName(var_name) => visitor.visit_identifier(var_name),
+        TermKind::VarNameExpression((var_name, expression)) => {
+            visitor.visit_identifier(var_name);
+            visitor.visit_expression(expression);
+        }
+        TermKind::SubroutineCall(call) => visitor.visit_subroutine_call(call),
+        TermKind::Expression(expression) => visitor.visit_expression(expression),
+        TermKind::UnaryOpTerm((_, term)) => visitor.visit_term(term),
+        TermKind::MemberAccess((name, member)) => {
+            visitor.visit_identifier(name);
+            visitor.visit_identifier(member);
+        }
+        TermKind::IntegerConstant(_)
+        | TermKind::StringConstant(_)
+        | TermKind::KeywordConstant(_) => {}
+    }
+}
+
+pub fn walk_subroutine_call<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    call: &'ast SubroutineCall,
+) {
+    if let Some(receiver) = &call.receiver {
+        visitor.visit_term(receiver);
+    }
+    if let Some(class_or_var_name) = &call.class_or_var_name {
+        visitor.visit_identifier(class_or_var_name);
+    }
+    visitor.visit_identifier(&call.subroutine_name);
+    for expression in call.expression_list.iter() {
+        visitor.visit_expression(expression);
+    }
+}
+
+pub fn walk_var_type<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, var_type: &'ast VarType) {
+    if let VarType::ClassName(class_name) = var_type {
+        visitor.visit_identifier(class_name);
+    }
+}
+
+/// Like `Visitor`, but the nodes can be changed in place
+pub trait VisitorMut {
+    fn visit_class_mut(&mut self, class: &mut Class) {
+        walk_class_mut(self, class)
+    }
+
+    fn visit_class_var_declaration_mut(&mut self, declaration: &mut ClassVarDeclaration) {
+        walk_class_var_declaration_mut(self, declaration)
+    }
+
+    fn visit_constant_declaration_mut(&mut self, declaration: &mut ConstantDeclaration) {
+        walk_constant_declaration_mut(self, declaration)
+    }
+
+    fn visit_enum_declaration_mut(&mut self, declaration: &mut EnumDeclaration) {
+        walk_enum_declaration_mut(self, declaration)
+    }
+
+    fn visit_subroutine_declaration_mut(&mut self, subroutine: &mut SubroutineDeclaration) {
+        walk_subroutine_declaration_mut(self, subroutine)
+    }
+
+    fn visit_var_declaration_mut(&mut self, declaration: &mut VarDeclaration) {
+        walk_var_declaration_mut(self, declaration)
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_let_statement_mut(&mut self, statement: &mut LetStatement) {
+        walk_let_statement_mut(self, statement)
+    }
+
+    fn visit_if_statement_mut(&mut self, statement: &mut IfStatement) {
+        walk_if_statement_mut(self, statement)
+    }
+
+    fn visit_while_statement_mut(&mut self, statement: &mut WhileStatement) {
+        walk_while_statement_mut(self, statement)
+    }
+
+    fn visit_do_statement_mut(&mut self, statement: &mut DoStatement) {
+        walk_do_statement_mut(self, statement)
+    }
+
+    fn visit_return_statement_mut(&mut self, statement: &mut ReturnStatement) {
+        walk_return_statement_mut(self, statement)
+    }
+
+    fn visit_for_statement_mut(&mut self, statement: &mut ForStatement) {
+        walk_for_statement_mut(self, statement)
+    }
+
+    fn visit_break_statement_mut(&mut self, _statement: &mut BreakStatement) {}
+
+    fn visit_continue_statement_mut(&mut self, _statement: &mut ContinueStatement) {}
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+
+    fn visit_term_mut(&mut self, term: &mut Term) {
+        walk_term_mut(self, term)
+    }
+
+    fn visit_subroutine_call_mut(&mut self, call: &mut SubroutineCall) {
+        walk_subroutine_call_mut(self, call)
+    }
+
+    fn visit_var_type_mut(&mut self, var_type: &mut VarType) {
+        walk_var_type_mut(self, var_type)
+    }
+
+    fn visit_identifier_mut(&mut self, _identifier: &mut Identifier) {}
+}
+
+pub fn walk_class_mut<V: VisitorMut + ?Sized>(visitor: &mut V, class: &mut Class) {
+    visitor.visit_identifier_mut(&mut class.class_name);
+    if let Some(superclass) = &mut class.superclass {
+        visitor.visit_identifier_mut(superclass);
+    }
+    for declaration in class.class_var_declarations.iter_mut() {
+        visitor.visit_class_var_declaration_mut(declaration);
+    }
+    for declaration in class.constant_declarations.iter_mut() {
+        visitor.visit_constant_declaration_mut(declaration);
+    }
+    for declaration in class.enum_declarations.iter_mut() {
+        visitor.visit_enum_declaration_mut(declaration);
+    }
+    for subroutine in class.subroutine_declarations.iter_mut() {
+        visitor.visit_subroutine_declaration_mut(subroutine);
+    }
+}
+
+pub fn walk_class_var_declaration_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    declaration: &mut ClassVarDeclaration,
+) {
+    visitor.visit_var_type_mut(&mut declaration.var_type);
+    for name in declaration.var_names.iter_mut() {
+        visitor.visit_identifier_mut(name);
+    }
+}
+
+pub fn walk_constant_declaration_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    declaration: &mut ConstantDeclaration,
+) {
+    visitor.visit_var_type_mut(&mut declaration.var_type);
+    visitor.visit_identifier_mut(&mut declaration.name);
+    visitor.visit_expression_mut(&mut declaration.expression);
+}
+
+pub fn walk_enum_declaration_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    declaration: &mut EnumDeclaration,
+) {
+    visitor.visit_identifier_mut(&mut declaration.name);
+    for variant in declaration.variants.iter_mut() {
+        visitor.visit_identifier_mut(variant);
+    }
+}
+
+pub fn walk_subroutine_declaration_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    subroutine: &mut SubroutineDeclaration,
+) {
+    if let Some(return_type) = &mut subroutine.return_type {
+        visitor.visit_var_type_mut(return_type);
+    }
+    visitor.visit_identifier_mut(&mut subroutine.name);
+    for (var_type, name) in subroutine.parameter_list.iter_mut() {
+        visitor.visit_var_type_mut(var_type);
+        visitor.visit_identifier_mut(name);
+    }
+    for declaration in subroutine.body.var_declarations.iter_mut() {
+        visitor.visit_var_declaration_mut(declaration);
+    }
+    for statement in subroutine.body.statements.iter_mut() {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_var_declaration_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    declaration: &mut VarDeclaration,
+) {
+    visitor.visit_var_type_mut(&mut declaration.var_type);
+    for name in declaration.var_names.iter_mut() {
+        visitor.visit_identifier_mut(name);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Let(statement) => visitor.visit_let_statement_mut(statement),
+        Statement::If(statement) => visitor.visit_if_statement_mut(statement),
+        Statement::While(statement) => visitor.visit_while_statement_mut(statement),
+        Statement::Do(statement) => visitor.visit_do_statement_mut(statement),
+        Statement::Return(statement) => visitor.visit_return_statement_mut(statement),
+        Statement::For(statement) => visitor.visit_for_statement_mut(statement),
+        Statement::Break(statement) => visitor.visit_break_statement_mut(statement),
+        Statement::Continue(statement) => visitor.visit_continue_statement_mut(statement),
+    }
+}
+
+pub fn walk_let_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    statement: &mut LetStatement,
+) {
+    visitor.visit_identifier_mut(&mut statement.var_name);
+    if let Some(expression) = &mut statement.left_side_expression {
+        visitor.visit_expression_mut(expression);
+    }
+    if let Some(field) = &mut statement.field {
+        visitor.visit_identifier_mut(field);
+    }
+    visitor.visit_expression_mut(&mut statement.right_side_expression);
+}
+
+pub fn walk_if_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut IfStatement) {
+    visitor.visit_expression_mut(&mut statement.expression);
+    for statement in statement.if_statements.iter_mut() {
+        visitor.visit_statement_mut(statement);
+    }
+    for statement in statement.else_statements.iter_mut().flatten() {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_while_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    statement: &mut WhileStatement,
+) {
+    visitor.visit_expression_mut(&mut statement.expression);
+    for statement in statement.statements.iter_mut() {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_do_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut DoStatement) {
+    visitor.visit_subroutine_call_mut(&mut statement.subroutine_call);
+}
+
+pub fn walk_return_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    statement: &mut ReturnStatement,
+) {
+    if let Some(expression) = &mut statement.expression {
+        visitor.visit_expression_mut(expression);
+    }
+}
+
+pub fn walk_for_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    statement: &mut ForStatement,
+) {
+    if let Some(initializer) = &mut statement.initializer {
+        visitor.visit_statement_mut(initializer);
+    }
+    if let Some(condition) = &mut statement.condition {
+        visitor.visit_expression_mut(condition);
+    }
+    if let Some(update) = &mut statement.update {
+        visitor.visit_statement_mut(update);
+    }
+    for statement in statement.statements.iter_mut() {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    visitor.visit_term_mut(&mut expression.term);
+    for (_, term) in expression.ops.iter_mut() {
+        visitor.visit_term_mut(term);
+    }
+}
+
+pub fn walk_term_mut<V: VisitorMut + ?Sized>(visitor: &mut V, term: &mut Term) {
+    match &mut term.kind {
+        TermKind::VarName(var_name) => visitor.visit_identifier_mut(var_name),
+        TermKind::VarNameExpression((var_name, expression)) => {
+            visitor.visit_identifier_mut(var_name);
+            visitor.visit_expression_mut(expression);
+        }
+        TermKind::SubroutineCall(call) => visitor.visit_subroutine_call_mut(call),
+        TermKind::Expression(expression) => visitor.visit_expression_mut(expression),
+        TermKind::UnaryOpTerm((_, term)) => visitor.visit_term_mut(term),
+        TermKind::MemberAccess((name, member)) => {
+            visitor.visit_identifier_mut(name);
+            visitor.visit_identifier_mut(member);
+        }
+        TermKind::IntegerConstant(_)
+        | TermKind::StringConstant(_)
+        | TermKind::KeywordConstant(_) => {}
+    }
+}
+
+pub fn walk_subroutine_call_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    call: &mut SubroutineCall,
+) {
+    if let Some(receiver) = &mut call.receiver {
+        visitor.visit_term_mut(receiver);
+    }
+    if let Some(class_or_var_name) = &mut call.class_or_var_name {
+        visitor.visit_identifier_mut(class_or_var_name);
+    }
+    visitor.visit_identifier_mut(&mut call.subroutine_name);
+    for expression in call.expression_list.iter_mut() {
+        visitor.visit_expression_mut(expression);
+    }
+}
+
+pub fn walk_var_type_mut<V: VisitorMut + ?Sized>(visitor: &mut V, var_type: &mut VarType) {
+    if let VarType::ClassName(class_name) = var_type {
+        visitor.visit_identifier_mut(class_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::json::node_ids;
+    use crate::parser::{parse_source, NodeId, EVERY_KIND_OF_NODE};
+
+    /// Records the id of every node it visits
+    #[derive(Default)]
+    struct Ids(Vec<NodeId>);
+
+    impl<'ast> Visitor<'ast> for Ids {
+        fn visit_class_var_declaration(&mut self, declaration: &'ast ClassVarDeclaration) {
+            self.0.push(declaration.id);
+            walk_class_var_declaration(self, declaration)
+        }
+
+        fn visit_constant_declaration(&mut self, declaration: &'ast ConstantDeclaration) {
+            self.0.push(declaration.id);
+            walk_constant_declaration(self, declaration)
+        }
+
+        fn visit_enum_declaration(&mut self, declaration: &'ast EnumDeclaration) {
+            self.0.push(declaration.id);
+            walk_enum_declaration(self, declaration)
+        }
+
+        fn visit_subroutine_declaration(&mut self, subroutine: &'ast SubroutineDeclaration) {
+            self.0.push(subroutine.id);
+            walk_subroutine_declaration(self, subroutine)
+        }
+
+        fn visit_var_declaration(&mut self, declaration: &'ast VarDeclaration) {
+            self.0.push(declaration.id);
+            walk_var_declaration(self, declaration)
+        }
+
+        fn visit_statement(&mut self, statement: &'ast Statement) {
+            self.0.push(statement.id());
+            walk_statement(self, statement)
+        }
+
+        fn visit_expression(&mut self, expression: &'ast Expression) {
+            self.0.push(expression.id);
+            walk_expression(self, expression)
+        }
+
+        fn visit_term(&mut self, term: &'ast Term) {
+            self.0.push(term.id);
+            walk_term(self, term)
+        }
+    }
+
+    #[test]
+    fn visits_every_node_once() {
+        let class = parse_source(EVERY_KIND_OF_NODE);
+        let mut ids = Ids::default();
+        ids.visit_class(&class);
+        ids.0.sort();
+        assert_eq!(ids.0, node_ids(&class));
+    }
+
+    impl VisitorMut for Ids {
+        fn visit_class_var_declaration_mut(&mut self, declaration: &mut ClassVarDeclaration) {
+            self.0.push(declaration.id);
+            walk_class_var_declaration_mut(self, declaration)
+        }
+
+        fn visit_constant_declaration_mut(&mut self, declaration: &mut ConstantDeclaration) {
+            self.0.push(declaration.id);
+            walk_constant_declaration_mut(self, declaration)
+        }
+
+        fn visit_enum_declaration_mut(&mut self, declaration: &mut EnumDeclaration) {
+            self.0.push(declaration.id);
+            walk_enum_declaration_mut(self, declaration)
+        }
+
+        fn visit_subroutine_declaration_mut(&mut self, subroutine: &mut SubroutineDeclaration) {
+            self.0.push(subroutine.id);
+            walk_subroutine_declaration_mut(self, subroutine)
+        }
+
+        fn visit_var_declaration_mut(&mut self, declaration: &mut VarDeclaration) {
+            self.0.push(declaration.id);
+            walk_var_declaration_mut(self, declaration)
+        }
+
+        fn visit_statement_mut(&mut self, statement: &mut Statement) {
+            self.0.push(statement.id());
+            walk_statement_mut(self, statement)
+        }
+
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            self.0.push(expression.id);
+            walk_expression_mut(self, expression)
+        }
+
+        fn visit_term_mut(&mut self, term: &mut Term) {
+            self.0.push(term.id);
+            walk_term_mut(self, term)
+        }
+    }
+
+    #[test]
+    fn visits_every_node_once_mutably() {
+        let mut class = parse_source(EVERY_KIND_OF_NODE);
+        let mut ids = Ids::default();
+        ids.visit_class_mut(&mut class);
+        ids.0.sort();
+        assert_eq!(ids.0, node_ids(&class));
+    }
+}
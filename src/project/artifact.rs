@@ -0,0 +1,111 @@
+use super::manifest::Target;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Something the compiler can write out, chosen with `--emit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Artifact {
+    /// `<Name>T.xml`, the tokens in the nand2tetris XML format
+    TokensXml,
+    /// `<Name>.xml`, the parse tree in the nand2tetris XML format
+    AstXml,
+    /// `<Name>.json`, the parse tree including source spans
+    AstJson,
+    /// `<Name>.vm`
+    Vm,
+    /// `<dir>.asm`, Hack assembly for every class in the output directory
+    Asm,
+    /// `<dir>.hack`, Hack machine code for every class in the output directory
+    Hack,
+    /// `<Name>.symbols.json`, the symbol table of the class and each of its subroutines
+    Symbols,
+    /// `<Name>.vm.map`, the source span each line of the `.vm` file was generated from
+    Map,
+}
+
+impl Artifact {
+    pub const NAMES: &'static [&'static str] = &[
+        "tokens-xml",
+        "ast-xml",
+        "ast-json",
+        "vm",
+        "asm",
+        "hack",
+        "symbols",
+        "map",
+    ];
+
+    /// Whether the artifact is made from the whole program rather than a single class
+    pub fn is_program_level(self) -> bool {
+        matches!(self, Artifact::Asm | Artifact::Hack)
+    }
+
+    /// Where the artifact for the class in `file_path` is written
+    /// (or, for program level artifacts, the artifact for the program in `output_dir`)
+    pub fn output_path(self, file_path: &Path, output_dir: &Path) -> PathBuf {
+        let suffix = match self {
+            Artifact::TokensXml => "T.xml",
+            Artifact::AstXml => ".xml",
+            Artifact::AstJson => ".json",
+            Artifact::Vm => ".vm",
+            Artifact::Asm => ".asm",
+            Artifact::Hack => ".hack",
+            Artifact::Symbols => ".symbols.json",
+            Artifact::Map => ".vm.map",
+        };
+        let name = if self.is_program_level() {
+            // The program is named after the directory, like the nand2tetris tools do
+            output_dir
+                .canonicalize()
+                .ok()
+                .and_then(|dir| dir.file_name().map(|name| name.to_owned()))
+                .unwrap_or_else(|| "program".into())
+        } else {
+            file_path.file_stem().unwrap_or_default().to_owned()
+        };
+        output_dir.join(format!("{}{}", name.to_string_lossy(), suffix))
+    }
+}
+
+impl AsRef<str> for Artifact {
+    fn as_ref(&self) -> &str {
+        match self {
+            Artifact::TokensXml => "tokens-xml",
+            Artifact::AstXml => "ast-xml",
+            Artifact::AstJson => "ast-json",
+            Artifact::Vm => "vm",
+            Artifact::Asm => "asm",
+            Artifact::Hack => "hack",
+            Artifact::Symbols => "symbols",
+            Artifact::Map => "map",
+        }
+    }
+}
+
+impl FromStr for Artifact {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Artifact, Self::Err> {
+        match s {
+            "tokens-xml" => Ok(Artifact::TokensXml),
+            "ast-xml" => Ok(Artifact::AstXml),
+            "ast-json" => Ok(Artifact::AstJson),
+            "vm" => Ok(Artifact::Vm),
+            "asm" => Ok(Artifact::Asm),
+            "hack" => Ok(Artifact::Hack),
+            "symbols" => Ok(Artifact::Symbols),
+            "map" => Ok(Artifact::Map),
+            _ => Err(format!("Invalid artifact: {}", s)),
+        }
+    }
+}
+
+impl From<Target> for Artifact {
+    fn from(target: Target) -> Artifact {
+        match target {
+            Target::Vm => Artifact::Vm,
+            Target::Asm => Artifact::Asm,
+            Target::Hack => Artifact::Hack,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use crate::compiler::Program;
+use crate::parser::visit::{
+    walk_class_var_declaration, walk_subroutine_call, walk_subroutine_declaration, walk_term,
+    walk_var_declaration,
+};
+use crate::parser::{
+    Class, ClassVarDeclaration, Identifier, SubroutineCall, SubroutineDeclaration, Term, TermKind,
+    VarDeclaration, VarType, Visitor,
+};
+use crate::{Extension, ToXml};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CACHE_NAME: &str = ".jackc-cache.json";
+
+/// What is remembered about a class that compiled successfully
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Hash of the source file
+    pub hash: u64,
+    pub class_name: String,
+    /// Hash of the subroutine declarations, constants and accessible fields, which is all
+    /// other classes can see
+    pub signature: u64,
+    /// The classes this one refers to and their signatures when it was compiled
+    pub dependencies: BTreeMap<String, u64>,
+    /// Classes can only refer to classes compiled into the same directory
+    pub output_dir: PathBuf,
+    pub outputs: Vec<PathBuf>,
+    /// Classes with warnings are compiled again on every build, so the warnings are reported
+    #[serde(default)]
+    pub has_warnings: bool,
+}
+
+/// Remembers which sources have already been compiled so unchanged classes can be skipped.
+///
+/// The cache is stored in the output directory as `.jackc-cache.json`, so plain `jackc <dir>`
+/// runs without `-o` put it next to the sources, and `jackc build` puts it in the manifest's
+/// output directory. It is only saved after a build without errors, and is thrown away
+/// whenever it was written by a different version of the compiler.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cache {
+    version: String,
+    entries: BTreeMap<PathBuf, CacheEntry>,
+}
+
+impl Cache {
+    /// Loads the cache from `dir`, or starts an empty one if there isn't a usable cache there
+    pub fn load(dir: &Path) -> Cache {
+        fs::read_to_string(dir.join(CACHE_NAME))
+            .ok()
+            .and_then(|json| serde_json::from_str::<Cache>(&json).ok())
+            .filter(|cache| cache.version == env!("CARGO_PKG_VERSION"))
+            .unwrap_or_else(|| Cache {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                entries: BTreeMap::new(),
+            })
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(dir.join(CACHE_NAME), json)
+    }
+
+    pub fn get(&self, source: &Path) -> Option<&CacheEntry> {
+        self.entries.get(source)
+    }
+
+    pub fn insert(&mut self, source: PathBuf, entry: CacheEntry) {
+        self.entries.insert(source, entry);
+    }
+
+    pub fn remove(&mut self, source: &Path) -> Option<CacheEntry> {
+        self.entries.remove(source)
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &Path> {
+        self.entries.keys().map(PathBuf::as_path)
+    }
+
+    /// Checks whether the outputs for `source` are up to date with its contents
+    /// and with the signatures of the classes it depends on
+    pub fn is_fresh(&self, source: &Path, hash: u64, signatures: &BTreeMap<String, u64>) -> bool {
+        match self.entries.get(source) {
+            Some(entry) => {
+                entry.hash == hash
+                    && !entry.has_warnings
+                    && entry.outputs.iter().all(|output| output.is_file())
+                    && entry.dependencies.iter().all(|(class_name, signature)| {
+                        signatures
+                            .get(class_name)
+                            .is_none_or(|current| current == signature)
+                    })
+            }
+            None => false,
+        }
+    }
+
+    /// The current signature of every class in the cache that is compiled into `output_dir`
+    pub fn signatures(&self, output_dir: &Path) -> BTreeMap<String, u64> {
+        self.entries
+            .values()
+            .filter(|entry| entry.output_dir == output_dir)
+            .map(|entry| (entry.class_name.clone(), entry.signature))
+            .collect()
+    }
+}
+
+/// A 64-bit FNV-1a hash, which (unlike the standard library's hasher) is the same on every run
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// `program` has the values of the class's constants, which can depend on other classes.
+/// Fields are only part of the signature when other classes can access or inherit them, and
+/// then inherited fields are too. So are inherited subroutines, which calls resolve to.
+pub fn signature(class: &Class, program: &Program) -> u64 {
+    let mut declarations: String = class
+        .subroutine_declarations
+        .iter()
+        .map(|subroutine| {
+            format!(
+                "{}{}{}{}",
+                subroutine.subroutine_type.to_xml(),
+                subroutine
+                    .return_type
+                    .as_ref()
+                    .map_or_else(|| "void".to_string(), |return_type| return_type.to_xml()),
+                subroutine.name.to_xml(),
+                subroutine.parameter_list.to_xml()
+            )
+        })
+        .collect();
+    for declaration in class.constant_declarations.iter() {
+        let value = program
+            .constants
+            .get(&class.class_name.name, &declaration.name.name);
+        declarations.push_str(&format!("{}={:?}", declaration.name, value));
+    }
+    for declaration in class.enum_declarations.iter() {
+        declarations.push_str(&declaration.to_xml());
+    }
+    let class_name = &class.class_name.name;
+    if program.is_enabled(Extension::FieldAccess) || program.is_enabled(Extension::Inheritance) {
+        for (name, var_type) in program.fields(class_name).unwrap_or_default() {
+            declarations.push_str(&format!("{} {};", var_type, name));
+        }
+    }
+    for ancestor in program.ancestors(class_name) {
+        declarations.push_str(ancestor);
+        for (name, subroutine_type, return_type) in program.subroutines(ancestor) {
+            declarations.push_str(&format!(
+                "{}{}{};",
+                subroutine_type.to_xml(),
+                return_type.map_or_else(|| "void".to_string(), |return_type| return_type.to_xml()),
+                name
+            ));
+        }
+    }
+    hash(declarations.as_bytes())
+}
+
+/// Finds the names of the other classes that `class` uses.
+///
+/// A call like `a.b()` is counted as a dependency on `a` unless there is a variable named `a`
+/// (in which case the variable's type is already a dependency), so this may include a few
+/// names that aren't classes. Constants like `Dir.Up` are a dependency on the class that
+/// declares them, which for an enum is found in `program`. The class that a chained call
+/// like `a.b().c()` goes to depends on what `b` returns, so every class in `program` that
+/// declares a subroutine named `c` counts. A class also depends on the class it extends.
+pub fn dependencies(class: &Class, program: &Program) -> BTreeSet<String> {
+    let mut references = References::default();
+    references.visit_class(class);
+
+    let mut dependencies: BTreeSet<String> = references
+        .types
+        .into_iter()
+        .filter_map(|var_type| match var_type {
+            VarType::ClassName(class_name) => Some(class_name.name.clone()),
+            _ => None,
+        })
+        .collect();
+    let var_names = references.var_names;
+    dependencies.extend(
+        references
+            .calls
+            .iter()
+            .filter(|call| call.receiver.is_some())
+            .flat_map(|call| program.classes_declaring(&call.subroutine_name.name))
+            .map(String::from),
+    );
+    dependencies.extend(
+        references
+            .calls
+            .into_iter()
+            .filter_map(|call| call.class_or_var_name.as_ref())
+            .filter(|name| !var_names.contains(name.name.as_str()))
+            .map(|name| name.name.clone()),
+    );
+    dependencies.extend(
+        references
+            .members
+            .into_iter()
+            .filter(|name| !var_names.contains(name.name.as_str()))
+            .map(|name| {
+                program
+                    .constants
+                    .declaring_class(&name.name)
+                    .unwrap_or(&name.name)
+                    .to_string()
+            }),
+    );
+    dependencies.extend(class.superclass.iter().map(|parent| parent.name.clone()));
+    dependencies.remove(&class.class_name.name);
+    dependencies
+}
+
+/// The types, variables, calls and `name.member` terms in a class
+#[derive(Default)]
+struct References<'ast> {
+    types: Vec<&'ast VarType>,
+    var_names: BTreeSet<&'ast str>,
+    calls: Vec<&'ast SubroutineCall>,
+    /// The names before the `.`
+    members: Vec<&'ast Identifier>,
+}
+
+impl<'ast> Visitor<'ast> for References<'ast> {
+    fn visit_class_var_declaration(&mut self, declaration: &'ast ClassVarDeclaration) {
+        self.var_names
+            .extend(declaration.var_names.iter().map(|name| name.name.as_str()));
+        walk_class_var_declaration(self, declaration);
+    }
+
+    fn visit_subroutine_declaration(&mut self, subroutine: &'ast SubroutineDeclaration) {
+        self.var_names.extend(
+            subroutine
+                .parameter_list
+                .iter()
+                .map(|(_, name)| name.name.as_str()),
+        );
+        walk_subroutine_declaration(self, subroutine);
+    }
+
+    fn visit_var_declaration(&mut self, declaration: &'ast VarDeclaration) {
+        self.var_names
+            .extend(declaration.var_names.iter().map(|name| name.name.as_str()));
+        walk_var_declaration(self, declaration);
+    }
+
+    fn visit_subroutine_call(&mut self, call: &'ast SubroutineCall) {
+        self.calls.push(call);
+        walk_subroutine_call(self, call);
+    }
+
+    fn visit_term(&mut self, term: &'ast Term) {
+        if let TermKind::MemberAccess((name, _)) = &term.kind {
+            self.members.push(name);
+        }
+        walk_term(self, term);
+    }
+
+    fn visit_var_type(&mut self, var_type: &'ast VarType) {
+        self.types.push(var_type);
+    }
+}
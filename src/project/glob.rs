@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+/// A shell-style glob used to exclude files from a build.
+///
+/// `*` and `?` match within a single path component and `**` matches any number of
+/// directories. Patterns without a `/` match any component of the path (so `tests`
+/// excludes every directory called `tests`), while patterns with one are matched
+/// against the whole path relative to the directory being searched: the input directory,
+/// or each of a manifest's `sources` and `libraries`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pattern: Vec<char>,
+    anchored: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        Pattern {
+            anchored: pattern.contains('/'),
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    /// Checks a relative path that uses `/` as its separator
+    pub fn matches(&self, path: &str) -> bool {
+        if self.anchored {
+            let path: Vec<char> = path.chars().collect();
+            matches(&self.pattern, &path)
+        } else {
+            path.split('/').any(|component| {
+                let component: Vec<char> = component.chars().collect();
+                matches(&self.pattern, &component)
+            })
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Pattern, Self::Err> {
+        if s.trim().is_empty() {
+            Err("Empty glob pattern".to_string())
+        } else {
+            Ok(Pattern::new(s))
+        }
+    }
+}
+
+fn matches(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            // "**/" can also match no directories at all
+            if rest.first() == Some(&'/') && matches(&rest[1..], path) {
+                return true;
+            }
+            (0..=path.len()).any(|i| matches(rest, &path[i..]))
+        }
+        Some('*') => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| matches(&pattern[1..], &path[i..])),
+        Some('?') => match path.first() {
+            Some(c) if *c != '/' => matches(&pattern[1..], &path[1..]),
+            _ => false,
+        },
+        Some(c) => path.first() == Some(c) && matches(&pattern[1..], &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(cases: &[(&str, &str, bool)]) {
+        for (pattern, path, expected) in cases {
+            assert_eq!(
+                Pattern::new(pattern).matches(path),
+                *expected,
+                "`{}` against `{}`",
+                pattern,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_directories() {
+        check(&[
+            ("**/Test.jack", "Test.jack", true),
+            ("**/Test.jack", "a/Test.jack", true),
+            ("**/Test.jack", "a/b/c/Test.jack", true),
+            ("**/Test.jack", "a/MyTest.jack", false),
+            ("src/**/*.jack", "src/Main.jack", true),
+            ("src/**/*.jack", "src/a/b/Main.jack", true),
+            ("src/**/*.jack", "lib/a/Main.jack", false),
+            ("a/**", "a/b/c", true),
+        ]);
+    }
+
+    #[test]
+    fn patterns_with_a_slash_match_the_whole_path() {
+        check(&[
+            ("gen/*.jack", "gen/Main.jack", true),
+            ("gen/*.jack", "src/gen/Main.jack", false),
+            ("gen/*.jack", "gen/a/Main.jack", false),
+            ("./gen/Main.jack", "gen/Main.jack", true),
+            ("gen/", "gen", true),
+            ("gen/Ma?n.jack", "gen/Main.jack", true),
+            ("gen/Ma?n.jack", "gen/Man.jack", false),
+            ("gen?Main.jack", "gen/Main.jack", false),
+        ]);
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_any_component() {
+        check(&[
+            ("tests", "tests", true),
+            ("tests", "a/tests/Main.jack", true),
+            ("tests", "a/tests2/Main.jack", false),
+            ("*Test.jack", "a/b/MainTest.jack", true),
+            ("*Test.jack", "a/MainTest.jack/b", true),
+            ("*Test.jack", "a/Main.jack", false),
+            ("?ain.jack", "src/Main.jack", true),
+            ("*", "a/b", true),
+        ]);
+    }
+
+    #[test]
+    fn empty_patterns_are_rejected() {
+        assert!(Pattern::from_str("  ").is_err());
+        assert!(Pattern::from_str("*.jack").is_ok());
+    }
+}
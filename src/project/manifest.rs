@@ -0,0 +1,417 @@
+use super::glob::Pattern;
+use crate::diagnostics::{closest_match, Diagnostic, Position, Span};
+use crate::{Extension, Extensions, Lint, LintLevel, Lints};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const MANIFEST_NAME: &str = "Jack.toml";
+
+const PROJECT_KEYS: &[&str] = &[
+    "name",
+    "entry",
+    "sources",
+    "libraries",
+    "output",
+    "exclude",
+    "target",
+    "opt-level",
+    "extensions",
+];
+const TABLES: &[&str] = &["project", "lints"];
+const MAX_OPT_LEVEL: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Vm,
+    Asm,
+    Hack,
+}
+
+impl AsRef<str> for Target {
+    fn as_ref(&self) -> &str {
+        match self {
+            Target::Vm => "vm",
+            Target::Asm => "asm",
+            Target::Hack => "hack",
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, Self::Err> {
+        match s {
+            "vm" => Ok(Target::Vm),
+            "asm" => Ok(Target::Asm),
+            "hack" => Ok(Target::Hack),
+            _ => Err(format!("Invalid target: {}", s)),
+        }
+    }
+}
+
+/// The build settings for a project, read from a `Jack.toml` file like this one:
+///
+/// ```toml
+/// [project]
+/// name = "Pong"
+/// entry = "Main"
+/// sources = ["src"]
+/// libraries = ["../lib", "../os"]
+/// output = "build"
+/// exclude = ["**/scratch"]
+/// target = "vm"
+/// opt-level = 1
+/// extensions = ["else-if"]
+///
+/// [lints]
+/// unused-variable = "deny"
+/// ```
+///
+/// Paths are relative to the directory containing the manifest. `exclude` patterns are
+/// matched against paths relative to each of the `sources` and `libraries`, so `src/scratch`
+/// excludes nothing, but `scratch` does.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(skip)]
+    pub root: PathBuf,
+    pub name: Option<String>,
+    /// The class that must define `function void main()`
+    pub entry: Option<String>,
+    pub sources: Vec<PathBuf>,
+    /// Shared classes that are compiled into the output alongside the sources.
+    /// `.vm` files in them (like the OS) are copied as they are.
+    pub libraries: Vec<PathBuf>,
+    pub output: PathBuf,
+    #[serde(deserialize_with = "patterns")]
+    pub exclude: Vec<Pattern>,
+    /// What `jackc build` writes when `--emit` isn't given
+    #[serde(deserialize_with = "named")]
+    pub target: Target,
+    /// How much the code generator optimizes, from 0 (not at all) to 1
+    #[serde(deserialize_with = "opt_level")]
+    pub opt_level: u8,
+    /// Language extensions enabled for every source
+    #[serde(deserialize_with = "extensions")]
+    pub extensions: Extensions,
+    /// The levels of lints that don't use their default level, from the `[lints]` table
+    #[serde(skip)]
+    pub lints: Lints,
+}
+
+impl Default for Manifest {
+    fn default() -> Manifest {
+        Manifest {
+            root: PathBuf::new(),
+            name: None,
+            entry: None,
+            sources: vec![PathBuf::from("src")],
+            libraries: Vec::new(),
+            output: PathBuf::from("build"),
+            exclude: Vec::new(),
+            target: Target::Vm,
+            opt_level: 0,
+            extensions: Extensions::new(),
+            lints: Lints::new(),
+        }
+    }
+}
+
+/// The tables of a manifest file
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    project: Manifest,
+    lints: BTreeMap<Named<Lint>, Named<LintLevel>>,
+}
+
+impl Manifest {
+    /// Looks for a manifest in `dir` and then in each of its parents
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(MANIFEST_NAME))
+            .find(|path| path.is_file())
+    }
+
+    pub fn parse(source: &str, root: &Path) -> Result<Manifest, Diagnostic> {
+        // Checking the syntax first tells syntax errors apart from invalid settings
+        toml::from_str::<toml::Table>(source)
+            .map_err(|error| diagnostic("E0400", &error, source))?;
+        let file: File = toml::from_str(source).map_err(|error| match error.span() {
+            Some(range) if error.message().starts_with("unknown field") => {
+                unknown_key(source, range)
+            }
+            _ => diagnostic("E0402", &error, source),
+        })?;
+
+        let mut manifest = file.project;
+        manifest.root = root.to_path_buf();
+        for path in manifest
+            .sources
+            .iter_mut()
+            .chain(manifest.libraries.iter_mut())
+        {
+            *path = root.join(&path);
+        }
+        manifest.output = root.join(&manifest.output);
+        for (Named(lint), Named(level)) in file.lints {
+            manifest.lints.set(lint, level);
+        }
+        Ok(manifest)
+    }
+}
+
+fn unknown_key(source: &str, range: Range<usize>) -> Diagnostic {
+    let span = span(source, range.clone());
+    let key = source[range.clone()].trim_matches(|c| c == '"' || c == '\'');
+    let line_start = source[..range.start]
+        .rfind('\n')
+        .map_or(0, |index| index + 1);
+    if source[line_start..range.start]
+        .trim_start()
+        .starts_with('[')
+    {
+        let mut diagnostic =
+            Diagnostic::error("E0401", format!("unknown table `[{}]`", key)).with_span(span);
+        if let Some(suggestion) = closest_match(key, TABLES.iter().copied()) {
+            diagnostic =
+                diagnostic.with_suggestion("a table with a similar name exists", span, suggestion);
+        }
+        return diagnostic;
+    }
+
+    let mut diagnostic =
+        Diagnostic::error("E0401", format!("unknown key `{}`", key)).with_span(span);
+    if PROJECT_KEYS.contains(&key) {
+        diagnostic = diagnostic.with_help("project settings go in the `[project]` table");
+    } else if let Some(suggestion) = closest_match(key, PROJECT_KEYS.iter().copied()) {
+        diagnostic =
+            diagnostic.with_suggestion("a key with a similar name exists", span, suggestion);
+    }
+    diagnostic
+}
+
+fn diagnostic(code: &'static str, error: &toml::de::Error, source: &str) -> Diagnostic {
+    // Some messages have a line with more detail
+    let message = error.message().trim_end().replace('\n', ": ");
+    let diagnostic = Diagnostic::error(code, message);
+    match error.span() {
+        Some(range) => diagnostic.with_span(span(source, range)),
+        None => diagnostic,
+    }
+}
+
+/// Converts a range of bytes in `source` to lines and columns
+fn span(source: &str, range: Range<usize>) -> Span {
+    let position = |offset: usize| {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Position::new(
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    };
+    Span::new(position(range.start), position(range.end))
+}
+
+/// A setting that is one of a fixed set of names
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Named<T>(T);
+
+trait Names: FromStr {
+    /// What the names are of, like "target"
+    const KIND: &'static str;
+    const NAMES: &'static [&'static str];
+}
+
+impl Names for Target {
+    const KIND: &'static str = "target";
+    const NAMES: &'static [&'static str] = &["vm", "asm", "hack"];
+}
+
+impl Names for Extension {
+    const KIND: &'static str = "extension";
+    const NAMES: &'static [&'static str] = Extension::NAMES;
+}
+
+impl Names for Lint {
+    const KIND: &'static str = "lint";
+    const NAMES: &'static [&'static str] = Lint::NAMES;
+}
+
+impl Names for LintLevel {
+    const KIND: &'static str = "lint level";
+    const NAMES: &'static [&'static str] = &["allow", "warn", "deny"];
+}
+
+impl<'de, T: Names> Deserialize<'de> for Named<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Named<T>, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        T::from_str(&name).map(Named).map_err(|_| {
+            let mut message = format!(
+                "unknown {} `{}`, expected one of `{}`",
+                T::KIND,
+                name,
+                T::NAMES.join("`, `")
+            );
+            if let Some(suggestion) = closest_match(&name, T::NAMES.iter().copied()) {
+                message.push_str(&format!(" (did you mean `{}`?)", suggestion));
+            }
+            de::Error::custom(message)
+        })
+    }
+}
+
+fn named<'de, D: Deserializer<'de>, T: Names>(deserializer: D) -> Result<T, D::Error> {
+    Named::deserialize(deserializer).map(|Named(value)| value)
+}
+
+fn extensions<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Extensions, D::Error> {
+    let extensions: Vec<Named<Extension>> = Vec::deserialize(deserializer)?;
+    Ok(extensions
+        .into_iter()
+        .map(|Named(extension)| extension)
+        .collect())
+}
+
+fn patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pattern>, D::Error> {
+    let patterns: Vec<String> = Vec::deserialize(deserializer)?;
+    Ok(patterns
+        .iter()
+        .map(|pattern| Pattern::new(pattern))
+        .collect())
+}
+
+fn opt_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let level = i64::deserialize(deserializer)?;
+    if (0..=i64::from(MAX_OPT_LEVEL)).contains(&level) {
+        Ok(level as u8)
+    } else {
+        Err(de::Error::custom(format!(
+            "the optimization level must be between 0 and {}",
+            MAX_OPT_LEVEL
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> Diagnostic {
+        Manifest::parse(source, Path::new("root")).unwrap_err()
+    }
+
+    fn span(start: (usize, usize), end: (usize, usize)) -> Span {
+        Span::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn reads_every_setting() {
+        let source = r#"
+[project]
+name = "Pong"
+entry = "Main"
+sources = ["src", "game"]
+libraries = ["../os"]
+output = "out"
+exclude = ["**/scratch"]
+target = "hack"
+opt-level = 1
+extensions = ["else-if", "for-loops"]
+
+[lints]
+unused-variable = "deny"
+"#;
+        let manifest = Manifest::parse(source, Path::new("root")).unwrap();
+        let root = Path::new("root");
+        assert_eq!(manifest.root, root);
+        assert_eq!(manifest.name.as_deref(), Some("Pong"));
+        assert_eq!(manifest.entry.as_deref(), Some("Main"));
+        assert_eq!(manifest.sources, vec![root.join("src"), root.join("game")]);
+        assert_eq!(manifest.libraries, vec![root.join("../os")]);
+        assert_eq!(manifest.output, root.join("out"));
+        assert_eq!(manifest.exclude, vec![Pattern::new("**/scratch")]);
+        assert_eq!(manifest.target, Target::Hack);
+        assert_eq!(manifest.opt_level, 1);
+        let extensions: Extensions = vec![Extension::ElseIf, Extension::ForLoops]
+            .into_iter()
+            .collect();
+        assert_eq!(manifest.extensions, extensions);
+        assert_eq!(manifest.lints.level(Lint::UnusedVariable), LintLevel::Deny);
+    }
+
+    #[test]
+    fn empty_manifest_has_the_defaults() {
+        let manifest = Manifest::parse("# nothing yet\n", Path::new("root")).unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                root: PathBuf::from("root"),
+                sources: vec![PathBuf::from("root/src")],
+                output: PathBuf::from("root/build"),
+                ..Manifest::default()
+            }
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let diagnostic = error("[project]\nname = Pong\n");
+        assert_eq!(diagnostic.code, "E0400");
+        assert_eq!(diagnostic.span.unwrap().start, Position::new(2, 8));
+
+        let diagnostic = error("[project]\n[project]\n");
+        assert_eq!(diagnostic.code, "E0400");
+        assert!(!diagnostic.message.contains('\n'));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let diagnostic = error("[project]\ntarge = \"vm\"\n");
+        assert_eq!(diagnostic.code, "E0401");
+        assert_eq!(diagnostic.message, "unknown key `targe`");
+        assert_eq!(diagnostic.span, Some(span((2, 1), (2, 6))));
+        assert_eq!(diagnostic.help[0].replacement.as_deref(), Some("target"));
+
+        let diagnostic = error("name = \"Pong\"\n");
+        assert_eq!(diagnostic.message, "unknown key `name`");
+        assert_eq!(
+            diagnostic.help[0].message,
+            "project settings go in the `[project]` table"
+        );
+
+        let diagnostic = error("[project]\n[lint]\n");
+        assert_eq!(diagnostic.code, "E0401");
+        assert_eq!(diagnostic.message, "unknown table `[lint]`");
+        assert_eq!(diagnostic.span, Some(span((2, 2), (2, 6))));
+        assert_eq!(diagnostic.help[0].replacement.as_deref(), Some("lints"));
+    }
+
+    #[test]
+    fn invalid_values() {
+        let diagnostic = error("[project]\ntarget = \"vn\"\n");
+        assert_eq!(diagnostic.code, "E0402");
+        assert_eq!(
+            diagnostic.message,
+            "unknown target `vn`, expected one of `vm`, `asm`, `hack` (did you mean `vm`?)"
+        );
+        assert_eq!(diagnostic.span, Some(span((2, 10), (2, 14))));
+
+        let diagnostic = error("[project]\nopt-level = 2\n");
+        assert_eq!(diagnostic.code, "E0402");
+        assert_eq!(
+            diagnostic.message,
+            "the optimization level must be between 0 and 1"
+        );
+
+        assert_eq!(error("[project]\nsources = \"src\"\n").code, "E0402");
+        assert_eq!(error("[lints]\nunused = \"deny\"\n").code, "E0402");
+        assert_eq!(error("[lints]\nunused-variable = \"off\"\n").code, "E0402");
+    }
+}
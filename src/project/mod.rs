@@ -0,0 +1,11 @@
+mod artifact;
+mod cache;
+mod glob;
+mod manifest;
+mod sources;
+
+pub use artifact::Artifact;
+pub use cache::{dependencies, hash, signature, Cache, CacheEntry, CACHE_NAME};
+pub use glob::Pattern;
+pub use manifest::{Manifest, Target, MANIFEST_NAME};
+pub use sources::{find_files, find_sources, Layout};
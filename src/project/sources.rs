@@ -0,0 +1,99 @@
+use super::glob::Pattern;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// Where compiled files are written relative to the output directory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// Recreate the directory structure of the input
+    Mirror,
+    /// Write every file directly into the output directory
+    Flat,
+}
+
+impl Layout {
+    /// Returns the directory that the outputs for `file_path` should be written to
+    pub fn output_dir(self, input_root: &Path, output_root: &Path, file_path: &Path) -> PathBuf {
+        match self {
+            Layout::Mirror => {
+                let relative = file_path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(input_root).ok())
+                    .unwrap_or_else(|| Path::new(""));
+                output_root.join(relative)
+            }
+            Layout::Flat => output_root.to_path_buf(),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Layout, Self::Err> {
+        match s {
+            "mirror" => Ok(Layout::Mirror),
+            "flat" => Ok(Layout::Flat),
+            _ => Err(format!("Invalid output layout: {}", s)),
+        }
+    }
+}
+
+/// Finds every `.jack` file under `root`, skipping paths that match one of the `exclude` patterns.
+///
+/// If `root` is a file it is returned as is. Files are sorted so builds are reproducible.
+pub fn find_sources(root: &Path, exclude: &[Pattern]) -> io::Result<Vec<PathBuf>> {
+    if root.is_dir() {
+        find_files(root, "jack", exclude)
+    } else {
+        Ok(vec![root.to_path_buf()])
+    }
+}
+
+/// Finds every file with the given extension in the `root` directory and its subdirectories
+pub fn find_files(root: &Path, extension: &str, exclude: &[Pattern]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    visit(root, root, extension, exclude, &mut files)?;
+    Ok(files)
+}
+
+fn visit(
+    root: &Path,
+    dir: &Path,
+    extension: &str,
+    exclude: &[Pattern],
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut entries = dir.read_dir()?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let relative = relative_path(root, &path);
+        if exclude.iter().any(|pattern| pattern.matches(&relative)) {
+            continue;
+        }
+        // Symlinked directories are not followed, so a link cycle can't hang the build
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            visit(root, &path, extension, exclude, files)?;
+        } else if path.extension().and_then(|s| s.to_str()) == Some(extension) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Formats a path relative to `root` with `/` separators, which is what patterns are matched against
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
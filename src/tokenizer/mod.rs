@@ -1,5 +1,6 @@
+#[allow(clippy::module_inception)]
 mod tokenizer;
 mod types;
 
+pub use tokenizer::{tokenize, tokenize_with_extensions};
 pub use types::*;
-pub use tokenizer::tokenize;
\ No newline at end of file
@@ -1,16 +1,58 @@
-use super::types::{Symbol, Token, KEYWORDS, SYMBOLS};
+use super::types::{
+    char_code, Keyword, Symbol, Token, CHARACTER_SET_NOTE, COMPOUND_SYMBOLS, KEYWORDS, SYMBOLS,
+};
+use crate::diagnostics::{Diagnostic, Position, Span};
+use crate::{Extension, Extensions};
 use std::convert::TryFrom;
 use std::iter::Iterator;
+use std::num::IntErrorKind;
 use std::str::FromStr;
 
 const MAX_INT: u16 = 32767;
 
-pub fn tokenize<'a>(lines: impl Iterator<Item = String>) -> impl Iterator<Item = Token> {
+pub fn tokenize(
+    lines: impl Iterator<Item = String>,
+) -> impl Iterator<Item = Result<(Token, Span), Diagnostic>> {
+    tokenize_with_extensions(lines, &Extensions::new())
+}
+
+/// Like `tokenize`, but also recognizes the tokens of the given language extensions
+pub fn tokenize_with_extensions(
+    lines: impl Iterator<Item = String>,
+    extensions: &Extensions,
+) -> impl Iterator<Item = Result<(Token, Span), Diagnostic>> {
+    let keywords: Vec<Keyword> = KEYWORDS
+        .iter()
+        .copied()
+        .filter(|keyword| {
+            keyword
+                .extension()
+                .is_none_or(|extension| extensions.is_enabled(extension))
+        })
+        .collect();
+    let compound_symbols: Vec<Symbol> = COMPOUND_SYMBOLS
+        .iter()
+        .copied()
+        .filter(|symbol| {
+            symbol
+                .extension()
+                .is_none_or(|extension| extensions.is_enabled(extension))
+        })
+        .collect();
+    let literals = extensions.is_enabled(Extension::Literals);
     let mut is_comment_block = false;
-    lines.flat_map(move |line| {
+    lines.enumerate().flat_map(move |(line_index, line)| {
         let mut tokens = Vec::new();
         let mut start = 0;
 
+        // Converts byte offsets in the line into a span (columns are counted in characters)
+        let span = |start: usize, end: usize| {
+            Span::new(
+                Position::new(line_index + 1, line[..start].chars().count() + 1),
+                Position::new(line_index + 1, line[..end].chars().count() + 1),
+            )
+        };
+
         // Go through each character in the line
         while start < line.len() {
             let substr = &line[start..];
@@ -24,7 +66,7 @@ pub fn tokenize<'a>(lines: impl Iterator<Item = String>) -> impl Iterator<Item =
                     start += 2;
                 } else {
                     // Still in comment block, ignore this character
-                    start += 1;
+                    start += next_char.len_utf8();
                 }
             } else if substr.starts_with("/*") {
                 // Comment block
@@ -33,54 +75,207 @@ pub fn tokenize<'a>(lines: impl Iterator<Item = String>) -> impl Iterator<Item =
             } else if substr.starts_with("//") {
                 // Single-line comment
                 break;
+            } else if literals && (next_char == '"' || next_char == '\'') {
+                // String or character constant with escape sequences
+                match read_quoted(substr, next_char) {
+                    Ok((text, end)) => {
+                        let token = if next_char == '"' {
+                            Ok(Token::StringConstant(text))
+                        } else {
+                            char_constant(&text)
+                        };
+                        let token_span = span(start, start + end);
+                        tokens.push(
+                            token
+                                .map(|token| (token, token_span))
+                                .map_err(|diagnostic| diagnostic.with_span(token_span)),
+                        );
+                        start += end;
+                    }
+                    Err((diagnostic, error_start, error_end)) => {
+                        tokens.push(Err(
+                            diagnostic.with_span(span(start + error_start, start + error_end))
+                        ));
+                        break;
+                    }
+                }
             } else if next_char == '"' {
                 // String constant
-                let end = 1 + &substr[1..].find('"').expect("Unclosed string literal");
-                tokens.push(Token::StringConstant(substr[1..end].to_string()));
-                start += end + 1;
-            } else if next_char.is_numeric() {
+                match substr[1..].find('"') {
+                    Some(end) => {
+                        let end = end + 1;
+                        tokens.push(Ok((
+                            Token::StringConstant(substr[1..end].to_string()),
+                            span(start, start + end + 1),
+                        )));
+                        start += end + 1;
+                    }
+                    None => {
+                        tokens.push(Err(Diagnostic::error(
+                            "E0002",
+                            "unterminated string literal",
+                        )
+                        .with_span(span(start, line.len()))
+                        .with_label("missing closing quote")
+                        .with_note("string constants cannot span multiple lines")));
+                        break;
+                    }
+                }
+            } else if literals && (substr.starts_with("0x") || substr.starts_with("0b")) {
+                // Hexadecimal or binary integer constant
+                let (radix, base) = if substr.starts_with("0x") {
+                    (16, "hexadecimal")
+                } else {
+                    (2, "binary")
+                };
+                let end = substr
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(substr.len());
+                let literal = &substr[..end];
+                tokens.push(match u32::from_str_radix(&literal[2..], radix) {
+                    Ok(int) if int <= u32::from(MAX_INT) => {
+                        Ok((Token::IntegerConstant(int as u16), span(start, start + end)))
+                    }
+                    Err(error) if *error.kind() != IntErrorKind::PosOverflow => {
+                        Err(Diagnostic::error(
+                            "E0006",
+                            format!("invalid {} constant `{}`", base, literal),
+                        )
+                        .with_span(span(start, start + end)))
+                    }
+                    _ => Err(too_large(literal).with_span(span(start, start + end))),
+                });
+                start += end;
+            } else if next_char.is_ascii_digit() {
                 // Integer constant
-                let end = 1 + &substr[1..].find(|c: char| !c.is_numeric()).unwrap();
-                let int = u16::from_str(&substr[..end]).expect("Cannot parse integer constant");
-                if int > MAX_INT {
-                    panic!("Integer exceeds max value");
+                let end = 1 + substr[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(substr.len() - 1);
+                match u16::from_str(&substr[..end]) {
+                    Ok(int) if int <= MAX_INT => {
+                        tokens.push(Ok((Token::IntegerConstant(int), span(start, start + end))))
+                    }
+                    _ => tokens.push(Err(
+                        too_large(&substr[..end]).with_span(span(start, start + end))
+                    )),
                 }
-                tokens.push(Token::IntegerConstant(int));
                 start += end;
+            } else if let Some(symbol) = compound_symbols
+                .iter()
+                .find(|symbol| substr.starts_with(symbol.as_ref()))
+            {
+                let end = start + symbol.as_ref().len();
+                tokens.push(Ok((Token::Symbol(*symbol), span(start, end))));
+                start = end;
             } else if SYMBOLS.contains(&next_char) {
                 // Symbol
-                tokens.push(Token::Symbol(Symbol::try_from(next_char).unwrap()));
+                tokens.push(Ok((
+                    Token::Symbol(Symbol::try_from(next_char).unwrap()),
+                    span(start, start + 1),
+                )));
                 start += 1;
-            } else if !next_char.is_whitespace() {
+            } else if next_char.is_alphabetic() || next_char == '_' {
                 let mut is_keyword = false;
-                for variant in KEYWORDS {
+                for variant in keywords.iter() {
                     // Keyword
                     if substr.starts_with(variant.as_ref()) {
-                        // Also check that the next character after isn't alphabetic
+                        // Also check that the next character after isn't part of an identifier
                         // (so we don't accidentally treat "double" as the "do" keyword)
                         let next_char = substr.chars().nth(variant.as_ref().len());
-                        if let Some(next_char) = next_char {
-                            if !next_char.is_alphabetic() {
-                                tokens.push(Token::Keyword(*variant));
-                                start += variant.as_ref().len();
-                                is_keyword = true;
-                                break;
-                            }
+                        if !next_char.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                            let end = start + variant.as_ref().len();
+                            tokens.push(Ok((Token::Keyword(*variant), span(start, end))));
+                            start = end;
+                            is_keyword = true;
+                            break;
                         }
                     }
                 }
                 // Identifier
                 if !is_keyword {
-                    let end = 1 + &substr[1..]
+                    let end = 1 + substr[1..]
                         .find(|c: char| !c.is_alphanumeric() && c != '_')
                         .unwrap_or(substr.len() - 1);
-                    tokens.push(Token::Identifier(substr[..end].to_string()));
+                    tokens.push(Ok((
+                        Token::Identifier(substr[..end].to_string()),
+                        span(start, start + end),
+                    )));
                     start += end;
                 }
+            } else if !next_char.is_whitespace() {
+                tokens.push(Err(Diagnostic::error(
+                    "E0001",
+                    format!("unexpected character `{}`", next_char),
+                )
+                .with_span(span(start, start + next_char.len_utf8()))));
+                start += next_char.len_utf8();
             } else {
-                start += 1;
+                start += next_char.len_utf8();
             }
         }
         tokens.into_iter()
     })
 }
+
+fn too_large(literal: &str) -> Diagnostic {
+    Diagnostic::error(
+        "E0003",
+        format!("integer constant `{}` is too large", literal),
+    )
+    .with_note(format!("the maximum integer constant is {}", MAX_INT))
+}
+
+/// Reads a string or character constant that starts with `quote`, replacing its escape
+/// sequences. Returns the text and the length of the constant including the quotes,
+/// or an error and the byte range of `substr` it applies to.
+fn read_quoted(substr: &str, quote: char) -> Result<(String, usize), (Diagnostic, usize, usize)> {
+    let kind = if quote == '"' { "string" } else { "character" };
+    let mut text = String::new();
+    let mut chars = substr.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        if c == quote {
+            return Ok((text, index + 1));
+        }
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some((_, 'n')) => text.push('\n'),
+            Some((_, escaped @ ('\\' | '"' | '\''))) => text.push(escaped),
+            Some((escaped_index, escaped)) => {
+                let diagnostic =
+                    Diagnostic::error("E0004", format!("unknown escape sequence `\\{}`", escaped))
+                        .with_note(r#"the escape sequences are `\n`, `\\`, `\"` and `\'`"#);
+                return Err((diagnostic, index, escaped_index + escaped.len_utf8()));
+            }
+            None => break,
+        }
+    }
+    let diagnostic = Diagnostic::error("E0002", format!("unterminated {} literal", kind))
+        .with_label("missing closing quote")
+        .with_note(format!("{} constants cannot span multiple lines", kind));
+    Err((diagnostic, 0, substr.len()))
+}
+
+/// Character constants are integer constants holding the character's code
+fn char_constant(text: &str) -> Result<Token, Diagnostic> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => char_code(c).map(Token::IntegerConstant).ok_or_else(|| {
+            Diagnostic::error(
+                "E0005",
+                format!(
+                    "character `{}` is not in the Jack character set",
+                    c.escape_debug()
+                ),
+            )
+            .with_note(CHARACTER_SET_NOTE)
+        }),
+        _ => Err(Diagnostic::error(
+            "E0005",
+            "character constants must contain exactly one character",
+        )
+        .with_help("use double quotes for a string constant")),
+    }
+}
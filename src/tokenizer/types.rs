@@ -1,9 +1,47 @@
-use crate::ToXml;
+use crate::{Extension, ToXml, XmlWriter};
 use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
 
-pub const SYMBOLS: &'static [char] = &[
+pub const SYMBOLS: &[char] = &[
     '{', '}', '(', ')', '[', ']', '.', ',', ';', '+', '-', '*', '/', '&', '|', '<', '>', '=', '~',
 ];
+
+/// The Jack character set is ASCII, except that newline is 128
+pub const NEWLINE: u16 = 128;
+
+/// What to tell someone who wrote a character that isn't in the Jack character set
+pub const CHARACTER_SET_NOTE: &str =
+    "the Jack character set is the printable ASCII characters and newline";
+
+/// The code of a character in the Jack character set. The other codes the Hack platform uses,
+/// which are for keys like backspace and the arrows, have no character that can be written.
+pub fn char_code(c: char) -> Option<u16> {
+    match c {
+        '\n' => Some(NEWLINE),
+        ' '..='~' => Some(c as u16),
+        _ => None,
+    }
+}
+
+/// Symbols that are more than one character long, which are matched before the single characters,
+/// and the other symbols that aren't in the spec
+pub const COMPOUND_SYMBOLS: &[Symbol] = &[
+    Symbol::LessThanEquals,
+    Symbol::GreaterThanEquals,
+    Symbol::NotEquals,
+    Symbol::DoubleAmpersand,
+    Symbol::DoubleVerticalBar,
+    Symbol::PlusEquals,
+    Symbol::MinusEquals,
+    Symbol::AsterixEquals,
+    Symbol::SlashEquals,
+    Symbol::AmpersandEquals,
+    Symbol::VerticalBarEquals,
+    Symbol::DoubleLessThan,
+    Symbol::DoubleGreaterThan,
+    Symbol::Percent,
+];
 pub const KEYWORDS: &[Keyword] = &[
     Keyword::Class,
     Keyword::Constructor,
@@ -26,6 +64,13 @@ pub const KEYWORDS: &[Keyword] = &[
     Keyword::Else,
     Keyword::While,
     Keyword::Return,
+    Keyword::For,
+    Keyword::Break,
+    Keyword::Continue,
+    Keyword::Const,
+    Keyword::Enum,
+    Keyword::Extends,
+    Keyword::Super,
 ];
 
 #[derive(Debug, PartialEq)]
@@ -37,33 +82,37 @@ pub enum Token {
     Identifier(String),
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Keyword(keyword) => write!(f, "{}", keyword.as_ref()),
+            Token::Symbol(symbol) => write!(f, "{}", symbol.as_ref()),
+            Token::IntegerConstant(integer) => write!(f, "{}", integer),
+            Token::StringConstant(string) => write!(f, "\"{}\"", string),
+            Token::Identifier(identifier) => write!(f, "{}", identifier),
+        }
+    }
+}
+
 impl ToXml for Token {
-    fn to_xml(&self) -> String {
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
         match self {
-            Token::Keyword(keyword) => keyword.to_xml(),
-            Token::Symbol(symbol) => symbol.to_xml(),
-            Token::IntegerConstant(integer) => {
-                format!("<integerConstant> {} </integerConstant>", integer)
-            }
-            Token::StringConstant(string) => {
-                format!("<stringConstant> {} </stringConstant>", string)
-            }
-            Token::Identifier(identifier) => format!("<identifier> {} </identifier>", identifier),
+            Token::Keyword(keyword) => keyword.write_xml(xml),
+            Token::Symbol(symbol) => symbol.write_xml(xml),
+            Token::IntegerConstant(integer) => xml.leaf("integerConstant", &integer.to_string()),
+            Token::StringConstant(string) => xml.leaf("stringConstant", string),
+            Token::Identifier(identifier) => xml.leaf("identifier", identifier),
         }
     }
 }
 
 impl ToXml for Vec<Token> {
-    fn to_xml(&self) -> String {
-        format!(
-            "<tokens>
-{}
-</tokens>",
-            self.iter()
-                .map(|t| t.to_xml())
-                .collect::<Vec<String>>()
-                .join("\n")
-        )
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.open("tokens")?;
+        for token in self.iter() {
+            token.write_xml(xml)?;
+        }
+        xml.close("tokens")
     }
 }
 
@@ -90,6 +139,26 @@ pub enum Keyword {
     Else,
     While,
     Return,
+    For,
+    Break,
+    Continue,
+    Const,
+    Enum,
+    Extends,
+    Super,
+}
+
+impl Keyword {
+    /// The extension that makes this a keyword, for the keywords that aren't in the spec.
+    /// Without it, they are identifiers.
+    pub fn extension(self) -> Option<Extension> {
+        match self {
+            Keyword::For | Keyword::Break | Keyword::Continue => Some(Extension::ForLoops),
+            Keyword::Const | Keyword::Enum => Some(Extension::Constants),
+            Keyword::Extends | Keyword::Super => Some(Extension::Inheritance),
+            _ => None,
+        }
+    }
 }
 
 impl AsRef<str> for Keyword {
@@ -116,38 +185,121 @@ impl AsRef<str> for Keyword {
             Keyword::Else => "else",
             Keyword::While => "while",
             Keyword::Return => "return",
+            Keyword::For => "for",
+            Keyword::Break => "break",
+            Keyword::Continue => "continue",
+            Keyword::Const => "const",
+            Keyword::Enum => "enum",
+            Keyword::Extends => "extends",
+            Keyword::Super => "super",
         }
     }
 }
 
 impl ToXml for Keyword {
-    fn to_xml(&self) -> String {
-        format!("<keyword> {} </keyword>", self.as_ref())
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.keyword(self.as_ref())
     }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
-#[repr(u8)]
 pub enum Symbol {
-    CurlyOpen = b'{',
-    CurlyClose = b'}',
-    ParenOpen = b'(',
-    ParenClose = b')',
-    BracketOpen = b'[',
-    BracketClose = b']',
-    Period = b'.',
-    Comma = b',',
-    Semicolon = b';',
-    Plus = b'+',
-    Minus = b'-',
-    Asterix = b'*',
-    Slash = b'/',
-    Ampersand = b'&',
-    VerticalBar = b'|',
-    LessThan = b'<',
-    GreaterThan = b'>',
-    Equals = b'=',
-    Tilde = b'~',
+    CurlyOpen,
+    CurlyClose,
+    ParenOpen,
+    ParenClose,
+    BracketOpen,
+    BracketClose,
+    Period,
+    Comma,
+    Semicolon,
+    Plus,
+    Minus,
+    Asterix,
+    Slash,
+    Ampersand,
+    VerticalBar,
+    LessThan,
+    GreaterThan,
+    Equals,
+    Tilde,
+    LessThanEquals,
+    GreaterThanEquals,
+    NotEquals,
+    DoubleAmpersand,
+    DoubleVerticalBar,
+    PlusEquals,
+    MinusEquals,
+    AsterixEquals,
+    SlashEquals,
+    AmpersandEquals,
+    VerticalBarEquals,
+    DoubleLessThan,
+    DoubleGreaterThan,
+    Percent,
+}
+
+impl Symbol {
+    /// The extension that adds this symbol, for the symbols that aren't in the spec
+    pub fn extension(self) -> Option<Extension> {
+        match self {
+            Symbol::LessThanEquals
+            | Symbol::GreaterThanEquals
+            | Symbol::NotEquals
+            | Symbol::DoubleAmpersand
+            | Symbol::DoubleVerticalBar => Some(Extension::Operators),
+            Symbol::PlusEquals
+            | Symbol::MinusEquals
+            | Symbol::AsterixEquals
+            | Symbol::SlashEquals
+            | Symbol::AmpersandEquals
+            | Symbol::VerticalBarEquals => Some(Extension::CompoundAssignment),
+            Symbol::DoubleLessThan | Symbol::DoubleGreaterThan | Symbol::Percent => {
+                Some(Extension::ArithmeticOperators)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &'static str {
+        match self {
+            Symbol::CurlyOpen => "{",
+            Symbol::CurlyClose => "}",
+            Symbol::ParenOpen => "(",
+            Symbol::ParenClose => ")",
+            Symbol::BracketOpen => "[",
+            Symbol::BracketClose => "]",
+            Symbol::Period => ".",
+            Symbol::Comma => ",",
+            Symbol::Semicolon => ";",
+            Symbol::Plus => "+",
+            Symbol::Minus => "-",
+            Symbol::Asterix => "*",
+            Symbol::Slash => "/",
+            Symbol::Ampersand => "&",
+            Symbol::VerticalBar => "|",
+            Symbol::LessThan => "<",
+            Symbol::GreaterThan => ">",
+            Symbol::Equals => "=",
+            Symbol::Tilde => "~",
+            Symbol::LessThanEquals => "<=",
+            Symbol::GreaterThanEquals => ">=",
+            Symbol::NotEquals => "!=",
+            Symbol::DoubleAmpersand => "&&",
+            Symbol::DoubleVerticalBar => "||",
+            Symbol::PlusEquals => "+=",
+            Symbol::MinusEquals => "-=",
+            Symbol::AsterixEquals => "*=",
+            Symbol::SlashEquals => "/=",
+            Symbol::AmpersandEquals => "&=",
+            Symbol::VerticalBarEquals => "|=",
+            Symbol::DoubleLessThan => "<<",
+            Symbol::DoubleGreaterThan => ">>",
+            Symbol::Percent => "%",
+        }
+    }
 }
 
 impl TryFrom<char> for Symbol {
@@ -181,13 +333,27 @@ impl TryFrom<char> for Symbol {
 }
 
 impl ToXml for Symbol {
-    fn to_xml(&self) -> String {
-        let repr = match self {
-            Symbol::LessThan => String::from("&lt;"),
-            Symbol::GreaterThan => String::from("&gt;"),
-            Symbol::Ampersand => String::from("&amp;"),
-            _ => char::from(*self as u8).to_string(),
-        };
-        format!("<symbol> {} </symbol>", repr)
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.symbol(self.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_codes() {
+        assert_eq!(char_code(' '), Some(32));
+        assert_eq!(char_code('A'), Some(65));
+        assert_eq!(char_code('~'), Some(126));
+        assert_eq!(char_code('\n'), Some(NEWLINE));
+    }
+
+    #[test]
+    fn characters_outside_the_character_set_have_no_code() {
+        for c in ['\t', '\r', '\0', '\u{7f}', '\u{80}', 'é', '€', '😀'] {
+            assert_eq!(char_code(c), None, "{:?}", c);
+        }
     }
 }
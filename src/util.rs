@@ -1,3 +1,111 @@
+use std::io::{self, Write};
+
+/// Types that can be written in the XML format of the nand2tetris compare files
 pub trait ToXml {
-    fn to_xml(&self) -> String;
+    fn write_xml<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()>;
+
+    /// Writes the XML to a string, which is mostly useful for small nodes
+    fn to_xml(&self) -> String {
+        let mut xml = XmlWriter::new(Vec::new());
+        self.write_xml(&mut xml)
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(xml.into_inner()).expect("the XML is written from strings")
+    }
+}
+
+const INDENT: &[u8] = b"  ";
+/// The compare files were written on Windows
+const LINE_ENDING: &[u8] = b"\r\n";
+
+/// Writes XML elements one line at a time, in exactly the layout of the nand2tetris compare files
+pub struct XmlWriter<W: Write> {
+    writer: W,
+    depth: usize,
+    indent: bool,
+}
+
+impl<W: Write> XmlWriter<W> {
+    /// Nested elements are indented by two spaces, like the parse tree compare files
+    pub fn new(writer: W) -> XmlWriter<W> {
+        XmlWriter {
+            writer,
+            depth: 0,
+            indent: true,
+        }
+    }
+
+    /// Nothing is indented, like the token compare files
+    pub fn unindented(writer: W) -> XmlWriter<W> {
+        XmlWriter {
+            writer,
+            depth: 0,
+            indent: false,
+        }
+    }
+
+    pub fn open(&mut self, tag: &str) -> io::Result<()> {
+        self.write_indent()?;
+        write!(self.writer, "<{}>", tag)?;
+        self.writer.write_all(LINE_ENDING)?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn close(&mut self, tag: &str) -> io::Result<()> {
+        self.depth -= 1;
+        self.write_indent()?;
+        write!(self.writer, "</{}>", tag)?;
+        self.writer.write_all(LINE_ENDING)
+    }
+
+    /// Writes an element that only contains text, like `<symbol> &lt; </symbol>`
+    pub fn leaf(&mut self, tag: &str, text: &str) -> io::Result<()> {
+        self.write_indent()?;
+        write!(self.writer, "<{}> ", tag)?;
+        self.write_escaped(text)?;
+        write!(self.writer, " </{}>", tag)?;
+        self.writer.write_all(LINE_ENDING)
+    }
+
+    pub fn keyword(&mut self, keyword: &str) -> io::Result<()> {
+        self.leaf("keyword", keyword)
+    }
+
+    pub fn symbol(&mut self, symbol: &str) -> io::Result<()> {
+        self.leaf("symbol", symbol)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_indent(&mut self) -> io::Result<()> {
+        if self.indent {
+            for _ in 0..self.depth {
+                self.writer.write_all(INDENT)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_escaped(&mut self, text: &str) -> io::Result<()> {
+        let mut start = 0;
+        for (index, c) in text.char_indices() {
+            let escaped = match c {
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '&' => "&amp;",
+                '"' => "&quot;",
+                _ => continue,
+            };
+            self.writer.write_all(&text.as_bytes()[start..index])?;
+            self.writer.write_all(escaped.as_bytes())?;
+            start = index + c.len_utf8();
+        }
+        self.writer.write_all(&text.as_bytes()[start..])
+    }
 }
@@ -0,0 +1,203 @@
+//! Runs the compiler's binary on files written to temporary directories
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// An empty directory named after the test that uses it
+fn empty_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn jackc(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_jack_compiler"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "jackc {} failed:\n{}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Runs `jackc -` with `source` on stdin
+fn jackc_stdin(source: &str, args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jack_compiler"))
+        .arg("-")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error))
+}
+
+#[test]
+fn signature_changes_reach_the_end_of_an_inheritance_chain() {
+    let dir = empty_dir("inheritance_chain");
+    fs::write(dir.join("G.jack"), "class G { field int a; }").unwrap();
+    fs::write(dir.join("P.jack"), "class P extends G { field int b; }").unwrap();
+    fs::write(
+        dir.join("C.jack"),
+        "class C extends P {
+            field int c;
+            constructor C new() { let c = 3; return this; }
+        }",
+    )
+    .unwrap();
+    let args = [dir.to_str().unwrap(), "--extensions", "inheritance"];
+    jackc(&args);
+    let vm = read(&dir.join("C.vm"));
+    assert!(
+        vm.contains("push constant 3\ncall Memory.alloc 1"),
+        "{}",
+        vm
+    );
+    assert!(vm.contains("pop this 2"), "{}", vm);
+
+    // Only G changed, but P's fields moved, and so did C's
+    fs::write(dir.join("G.jack"), "class G { field int a, z; }").unwrap();
+    jackc(&args);
+    let vm = read(&dir.join("C.vm"));
+    assert!(
+        vm.contains("push constant 4\ncall Memory.alloc 1"),
+        "{}",
+        vm
+    );
+    assert!(vm.contains("pop this 3"), "{}", vm);
+}
+
+#[test]
+fn constant_changes_reach_the_end_of_a_chain() {
+    let dir = empty_dir("constant_chain");
+    fs::write(dir.join("A.jack"), "class A { const int X = 1; }").unwrap();
+    fs::write(dir.join("B.jack"), "class B { const int Y = A.X + 1; }").unwrap();
+    fs::write(
+        dir.join("C.jack"),
+        "class C { function int f() { return B.Y; } }",
+    )
+    .unwrap();
+    let args = [dir.to_str().unwrap(), "--extensions", "constants"];
+    jackc(&args);
+    assert!(read(&dir.join("C.vm")).contains("push constant 2"));
+
+    fs::write(dir.join("A.jack"), "class A { const int X = 10; }").unwrap();
+    jackc(&args);
+    let vm = read(&dir.join("C.vm"));
+    assert!(vm.contains("push constant 11"), "{}", vm);
+}
+
+#[test]
+fn compiles_stdin_to_stdout() {
+    let output = jackc_stdin(
+        "class Main {\n    function int two() {\n        return 1 + 1;\n    }\n}\n",
+        &[],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "function Main.two 0\npush constant 1\npush constant 1\nadd\nreturn\n"
+    );
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn reports_errors_in_stdin() {
+    let output = jackc_stdin("class Main {\n    function int two() {\n", &[]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("<stdin>:2:25"), "{}", stderr);
+}
+
+/// Compiles `tests/xml/<name>/Main.jack` and checks that the token and parse tree files are
+/// exactly like the course's compare files, down to their Windows line endings
+fn xml_golden(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/xml")
+        .join(name);
+    let output_dir = empty_dir(&format!("xml_{}", name));
+    jackc(&[
+        dir.join("Main.jack").to_str().unwrap(),
+        "--emit",
+        "tokens-xml,ast-xml",
+        "--no-cache",
+        "-o",
+        output_dir.to_str().unwrap(),
+    ]);
+    for file_name in ["MainT.xml", "Main.xml"] {
+        let expected = fs::read(dir.join(file_name)).unwrap();
+        assert!(expected.ends_with(b"\r\n"));
+        assert!(
+            fs::read(output_dir.join(file_name)).unwrap() == expected,
+            "{} of {} is different",
+            file_name,
+            name
+        );
+    }
+}
+
+#[test]
+fn writes_xml_like_the_course_for_array_test() {
+    xml_golden("ArrayTest");
+}
+
+#[test]
+fn writes_xml_like_the_course_for_square() {
+    xml_golden("Square");
+}
+
+#[test]
+fn invalid_tokens_leave_no_token_file() {
+    let dir = empty_dir("invalid_tokens");
+    fs::write(dir.join("Main.jack"), "class Main { field int x; # }").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_jack_compiler"))
+        .args([dir.to_str().unwrap(), "--emit", "tokens-xml", "--no-cache"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!dir.join("MainT.xml").exists());
+}
+
+#[test]
+fn artifacts_and_extensions_do_not_take_the_input_path() {
+    let dir = empty_dir("extensions_before_input");
+    fs::write(
+        dir.join("Main.jack"),
+        "class Main { function int f(int x) { if (x) { return 1; } else if (x) { return 2; } return 3; } }",
+    )
+    .unwrap();
+    jackc(&[
+        "--extensions",
+        "else-if,for-loops",
+        "--extensions",
+        "operators",
+        "--emit",
+        "vm,ast-xml",
+        "--emit",
+        "symbols",
+        dir.to_str().unwrap(),
+        "--no-cache",
+    ]);
+    assert!(dir.join("Main.vm").is_file());
+    assert!(dir.join("Main.xml").is_file());
+    assert!(dir.join("Main.symbols.json").is_file());
+}
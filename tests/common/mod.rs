@@ -0,0 +1,69 @@
+//! An emulated Hack CPU for running compiled programs in tests
+
+/// Stops programs that never halt
+const MAX_STEPS: usize = 50_000_000;
+
+/// Executes `program` on a Hack computer whose RAM starts out as `setup` and returns the RAM.
+///
+/// The program runs until it goes past its last instruction, or until it reaches a jump that
+/// doesn't store anything to the instruction before it, like `(HALT) @HALT 0;JMP`, which
+/// would loop forever.
+pub fn run(program: &[String], setup: &[(usize, i16)]) -> Vec<i16> {
+    let rom: Vec<u16> = program
+        .iter()
+        .map(|word| u16::from_str_radix(word, 2).expect("invalid machine code"))
+        .collect();
+    let mut ram = vec![0i16; 32768];
+    for (address, value) in setup {
+        ram[*address] = *value;
+    }
+    let (mut a, mut d, mut pc) = (0i16, 0i16, 0usize);
+
+    for _ in 0..MAX_STEPS {
+        let instruction = match rom.get(pc) {
+            Some(instruction) => *instruction,
+            None => break,
+        };
+        if instruction & 0x8000 == 0 {
+            a = instruction as i16;
+            pc += 1;
+            continue;
+        }
+        let bit = |n: u16| instruction & (1 << n) != 0;
+        let mut x = d;
+        let mut y = if bit(12) { ram[a as u16 as usize] } else { a };
+        if bit(11) {
+            x = 0;
+        }
+        if bit(10) {
+            x = !x;
+        }
+        if bit(9) {
+            y = 0;
+        }
+        if bit(8) {
+            y = !y;
+        }
+        let mut out = if bit(7) { x.wrapping_add(y) } else { x & y };
+        if bit(6) {
+            out = !out;
+        }
+        if bit(3) {
+            ram[a as u16 as usize] = out;
+        }
+        let jump = (bit(2) && out < 0) || (bit(1) && out == 0) || (bit(0) && out > 0);
+        let target = a as u16 as usize;
+        if bit(5) {
+            a = out;
+        }
+        if bit(4) {
+            d = out;
+        }
+        // Without a destination nothing changes, so the jump would be taken forever
+        if jump && target + 1 == pc && !bit(5) && !bit(4) && !bit(3) {
+            break;
+        }
+        pc = if jump { target } else { pc + 1 };
+    }
+    ram
+}
@@ -0,0 +1,188 @@
+//! Runs programs through the VM translator and the assembler and compares the results with the
+//! nand2tetris course's test files.
+//!
+//! `tests/asm` has assembly programs with the machine code the course's assembler produces for
+//! them. `tests/vm` has VM programs with the `.cmp` files the course compares the RAM with after
+//! running them, which are checked here by running the machine code on an emulated Hack CPU.
+
+mod common;
+
+use common::run;
+use jack_compiler::hack::{assemble, Translator};
+use std::fs;
+use std::path::Path;
+
+/// The addresses and values in a `.cmp` file. The course's files cut long headers like
+/// `RAM[3006]` short to fit their columns, so the closing bracket is optional.
+fn expected_ram(cmp: &str) -> Vec<(usize, i16)> {
+    let cells = |line: &str| -> Vec<String> {
+        line.split('|')
+            .map(str::trim)
+            .filter(|cell| !cell.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let lines: Vec<&str> = cmp.lines().filter(|line| !line.trim().is_empty()).collect();
+    lines
+        .chunks(2)
+        .flat_map(|pair| {
+            let addresses = cells(pair[0]).into_iter().map(|header| {
+                header
+                    .trim_start_matches("RAM[")
+                    .trim_end_matches(']')
+                    .parse::<usize>()
+                    .expect("invalid .cmp header")
+            });
+            let values = cells(pair[1])
+                .into_iter()
+                .map(|value| value.parse::<i16>().expect("invalid .cmp value"));
+            addresses.zip(values).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error))
+}
+
+fn assemble_golden(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/asm");
+    let source = read(&dir.join(format!("{}.asm", name)));
+    let expected = read(&dir.join(format!("{}.hack", name)));
+    let machine_code = assemble(&source).expect("the program should assemble");
+    assert_eq!(machine_code, expected.lines().collect::<Vec<_>>());
+}
+
+/// Translates the `.vm` files (or the directory of them) named `name` without the bootstrap code,
+/// runs them with the RAM set up like the course's test script does and checks the RAM against
+/// `name.cmp`. Programs with a `Sys.init` are started with the bootstrap code instead.
+fn translate_golden(name: &str, setup: &[(usize, i16)]) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vm");
+    let (files, cmp) = if dir.join(name).is_dir() {
+        let mut files: Vec<_> = fs::read_dir(dir.join(name))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "vm"))
+            .collect();
+        files.sort();
+        (files, dir.join(name).join(format!("{}.cmp", name)))
+    } else {
+        (
+            vec![dir.join(format!("{}.vm", name))],
+            dir.join(format!("{}.cmp", name)),
+        )
+    };
+
+    let mut translator = Translator::new();
+    for file in files.iter() {
+        let file_name = file.file_stem().unwrap().to_str().unwrap();
+        translator
+            .translate_file(file_name, &read(file))
+            .expect("the program should translate");
+    }
+    let assembly = if setup.is_empty() {
+        translator
+            .finish()
+            .expect("the program should have Sys.init")
+    } else {
+        translator.finish_without_bootstrap()
+    };
+    let machine_code = assemble(&assembly.join("\n")).expect("the translation should assemble");
+
+    let ram = run(&machine_code, setup);
+    for (address, value) in expected_ram(&read(&cmp)) {
+        assert_eq!(ram[address], value, "RAM[{}] of {}", address, name);
+    }
+}
+
+#[test]
+fn assembles_without_symbols() {
+    assemble_golden("Add");
+}
+
+#[test]
+fn assembles_labels() {
+    assemble_golden("Max");
+}
+
+#[test]
+fn assembles_variables_and_predefined_symbols() {
+    assemble_golden("Rect");
+}
+
+#[test]
+fn translates_push_constant_and_add() {
+    translate_golden("SimpleAdd", &[(0, 256)]);
+}
+
+#[test]
+fn translates_arithmetic_and_comparisons() {
+    translate_golden("StackTest", &[(0, 256)]);
+}
+
+#[test]
+fn translates_push_and_pop_for_memory_segments() {
+    translate_golden(
+        "BasicTest",
+        &[(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)],
+    );
+}
+
+#[test]
+fn translates_pointer_segment() {
+    translate_golden("PointerTest", &[(0, 256)]);
+}
+
+#[test]
+fn translates_static_segment() {
+    translate_golden("StaticTest", &[(0, 256)]);
+}
+
+#[test]
+fn translates_labels_and_if_goto() {
+    translate_golden("BasicLoop", &[(0, 256), (1, 300), (2, 400), (400, 3)]);
+}
+
+#[test]
+fn translates_goto() {
+    translate_golden(
+        "FibonacciSeries",
+        &[(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)],
+    );
+}
+
+#[test]
+fn translates_function_and_return() {
+    translate_golden(
+        "SimpleFunction",
+        &[
+            (0, 317),
+            (1, 317),
+            (2, 310),
+            (3, 3000),
+            (4, 4000),
+            (310, 1234),
+            (311, 37),
+            (312, 1000),
+            (313, 305),
+            (314, 300),
+            (315, 3010),
+            (316, 4010),
+        ],
+    );
+}
+
+#[test]
+fn translates_recursive_calls_with_bootstrap() {
+    translate_golden("FibonacciElement", &[]);
+}
+
+#[test]
+fn names_statics_after_their_file() {
+    translate_golden("StaticsTest", &[]);
+}
+
+#[test]
+fn scopes_labels_to_their_function() {
+    translate_golden("Labels", &[]);
+}
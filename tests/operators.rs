@@ -0,0 +1,211 @@
+//! Checks that the code generator computes operators the same way as `Constants` does when it
+//! evaluates constant expressions, by running the compiled code on an emulated Hack CPU.
+
+mod common;
+
+use common::run;
+use jack_compiler::compiler::{compile_in_program, Program};
+use jack_compiler::hack::{assemble, Translator};
+use jack_compiler::parser::{parse_with_extensions, Class};
+use jack_compiler::tokenizer::tokenize_with_extensions;
+use jack_compiler::{Extension, Extensions};
+use std::str::FromStr;
+
+/// Where the compiled code stores its results
+const RESULTS: usize = 8000;
+
+/// Runs `Main.run` and then halts
+const SYS: &str = "function Sys.init 0
+call Main.run 0
+pop temp 0
+label HALT
+goto HALT
+";
+
+/// Stands in for the operating system's `Math`, which `*`, `/` and `%` call. Like the real one,
+/// `divide` rounds towards zero, and neither works with -32768.
+const MATH: &str = "
+class Math {
+    function int multiply(int x, int y) {
+        var int sum, bit;
+        let sum = 0;
+        let bit = 1;
+        while (~(bit = 0)) {
+            if (~((y & bit) = 0)) {
+                let sum = sum + x;
+            }
+            let x = x + x;
+            let bit = bit + bit;
+        }
+        return sum;
+    }
+
+    function int divide(int x, int y) {
+        if (x < 0) {
+            return -Math.divide(-x, y);
+        }
+        if (y < 0) {
+            return -Math.divide(x, -y);
+        }
+        return Math.divideNatural(x, y);
+    }
+
+    function int divideNatural(int x, int y) {
+        var int q;
+        // y is negative once doubling it overflows
+        if ((y > x) | (y < 0)) {
+            return 0;
+        }
+        let q = Math.divideNatural(x, y + y);
+        if ((x - (2 * q * y)) < y) {
+            return q + q;
+        }
+        return q + q + 1;
+    }
+}
+";
+
+const OPERANDS: &[&str] = &["0", "1", "-1", "3", "5", "-6", "32767", "-32767 - 1"];
+
+/// A pair of operands, and the value of the operator applied to them
+struct Case {
+    expression: String,
+    constant: u16,
+    /// With both operands in variables
+    runtime: u16,
+    /// With the right operand a constant, which shifts are compiled differently for
+    runtime_constant: u16,
+}
+
+fn parse(source: &str) -> Class {
+    let extensions: Extensions = Extension::NAMES
+        .iter()
+        .map(|name| Extension::from_str(name).unwrap())
+        .collect();
+    let tokens = tokenize_with_extensions(source.lines().map(String::from), &extensions);
+    parse_with_extensions(tokens, &extensions).unwrap()
+}
+
+/// Compiles a `Main` class that declares `op` of every pair of operands as a constant and also
+/// computes it at runtime, and runs it
+fn evaluate(op: &str, lefts: &[&str], rights: &[&str]) -> Vec<Case> {
+    let pairs: Vec<(&str, &str)> = lefts
+        .iter()
+        .flat_map(|left| rights.iter().map(move |right| (*left, *right)))
+        .collect();
+    let mut source = String::from("class Main {\n");
+    for (index, (left, right)) in pairs.iter().enumerate() {
+        source.push_str(&format!(
+            "    const int C{} = ({}) {} ({});\n    const int R{} = {};\n",
+            index, left, op, right, index, right
+        ));
+    }
+    source
+        .push_str("    function void run() {\n        var Array results;\n        var int a, b;\n");
+    source.push_str(&format!("        let results = {};\n", RESULTS));
+    for (index, (left, right)) in pairs.iter().enumerate() {
+        source.push_str(&format!(
+            "        let a = {};\n        let b = {};\n        let results[{}] = a {} b;\n",
+            left,
+            right,
+            2 * index,
+            op
+        ));
+        source.push_str(&format!(
+            "        let results[{}] = a {} R{};\n",
+            2 * index + 1,
+            op,
+            index
+        ));
+    }
+    source.push_str("        return;\n    }\n}\n");
+
+    let classes = [parse(&source), parse(MATH)];
+    let extensions = Extension::NAMES
+        .iter()
+        .map(|name| Extension::from_str(name).unwrap())
+        .collect();
+    let program = Program::new(classes.iter(), &extensions);
+    let mut translator = Translator::new();
+    for class in classes.iter() {
+        let compiled = compile_in_program(class, &program).unwrap();
+        translator
+            .translate_file(&class.class_name.name, &compiled.vm.join("\n"))
+            .unwrap();
+    }
+    translator.translate_file("Sys", SYS).unwrap();
+    let machine_code = assemble(&translator.finish().unwrap().join("\n")).unwrap();
+    let ram = run(&machine_code, &[]);
+
+    pairs
+        .iter()
+        .enumerate()
+        .map(|(index, (left, right))| Case {
+            expression: format!("({}) {} ({})", left, op, right),
+            constant: program
+                .constants
+                .get("Main", &format!("C{}", index))
+                .expect("the constant should have a value"),
+            runtime: ram[RESULTS + 2 * index] as u16,
+            runtime_constant: ram[RESULTS + 2 * index + 1] as u16,
+        })
+        .collect()
+}
+
+/// Checks that `op` gives the same value at runtime as in constants, and returns the cases
+fn assert_same(op: &str, lefts: &[&str], rights: &[&str]) -> Vec<Case> {
+    let cases = evaluate(op, lefts, rights);
+    for case in cases.iter() {
+        for runtime in [case.runtime, case.runtime_constant] {
+            assert_eq!(
+                runtime, case.constant,
+                "`{}` is {} at runtime but {} as a constant",
+                case.expression, runtime as i16, case.constant as i16
+            );
+        }
+    }
+    cases
+}
+
+#[test]
+fn logical_operators_give_true_or_false() {
+    for op in ["&&", "||"] {
+        for case in assert_same(op, OPERANDS, OPERANDS) {
+            assert!(
+                case.constant == 0 || case.constant == 0xFFFF,
+                "`{}` is {}",
+                case.expression,
+                case.constant as i16
+            );
+        }
+    }
+}
+
+#[test]
+fn remainder_has_the_sign_of_the_left_side() {
+    let lefts = ["0", "1", "-1", "7", "-7", "32767", "-32767"];
+    let rights = ["1", "-1", "2", "-2", "3", "-3", "32767"];
+    let cases = assert_same("%", &lefts, &rights);
+    let remainder = |expression: &str| {
+        let case = cases
+            .iter()
+            .find(|case| case.expression == expression)
+            .unwrap();
+        case.constant as i16
+    };
+    assert_eq!(remainder("(7) % (3)"), 1);
+    assert_eq!(remainder("(-7) % (3)"), -1);
+    assert_eq!(remainder("(7) % (-3)"), 1);
+}
+
+#[test]
+fn shifts_fill_with_zeros() {
+    // One amount at a time, since every shift compiles to a lot of code
+    for amount in ["-1", "0", "1", "3", "15", "16", "17"] {
+        for op in ["<<", ">>"] {
+            assert_same(op, OPERANDS, &[amount]);
+        }
+    }
+    let cases = evaluate(">>", &["-32767 - 1"], &["15"]);
+    assert_eq!(cases[0].constant, 1);
+}